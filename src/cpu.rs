@@ -1,9 +1,15 @@
+use std::{fmt, mem};
+
 use crate::{
     ext,
     modules::{
-        assembler,
+        assembler::disassembler::Syntax,
+        breaks::{Class, Direction, Event, PortBreak},
         memory::Memory,
+        observer::Observer,
+        opcodes::{self, OPCODES},
         registers::{Flag, Registers},
+        watch::{Access, Hit, Watchpoint},
     },
 };
 
@@ -11,32 +17,58 @@ use crate::{
 pub struct Cpu {
     pub(crate) memory: Memory,
     pub(crate) registers: Registers,
+    //Syntax of the disassembled commands in the debugger
+    pub(crate) syntax: Syntax,
+    //Hooks of the host, the trace among them
+    observers: Vec<Box<dyn Observer>>,
+    //Clock cycles since the start of the program
+    pub(crate) cycles: u64,
+    //Undocumented opcodes stop the program instead of running as their documented twins
+    pub(crate) strict: bool,
+    //Set by HLT, nothing runs until an interrupt
+    pub(crate) halted: bool,
+    //Interrupt enable flip-flop, set by EI and cleared by DI
+    pub(crate) interrupts: bool,
+    //Last value written to each output port, IN reads it back
+    pub(crate) ports: Vec<u8>,
+    //Memory accesses are only checked while there are watchpoints
+    pub(crate) watchpoints: Vec<Watchpoint>,
+    //Watchpoints triggered since the last run or debugger stop, taken by whoever stops
+    pub(crate) hits: Vec<Hit>,
+    //Breaks on port accesses and instruction classes, checked only when there are some
+    pub(crate) port_breaks: Vec<PortBreak>,
+    pub(crate) class_breaks: Vec<Class>,
+    //Port accesses and instructions that triggered a break, taken like `hits`
+    pub(crate) events: Vec<Event>,
+    //Address of the instruction being executed
+    instruction: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CpuError {
+    //Only in strict mode, the opcode would do something else on a Z80 or an 8085
+    Undocumented { address: u16, opcode: u8 },
+    //Run stops after the instruction that triggered the watchpoints
+    Watchpoint(Vec<Hit>),
+    //An observer could not write its output
+    Output(String),
 }
 
-/* Macro for command with registers and immediate data adressing
- * If command have a two types: register and immediate data, it can be implement one time:
- * For example MOV and MVI:
- * MOV: 01TTTFFF
- * MVI: 00TTT110, where
- * TTT - register where data will be moves
- * FFF - register from which data will be moves
- * Means, if its immediate type of command, it xor second bit of command, and set "FFF" to 110
- */
-macro_rules! check_cmd {
-    ($f:stmt, $cmd:expr, $opcode:ident, $needed_opcode:literal, $is_a:literal, $self:ident, $to:ident, $from:ident) => (
-        if $cmd ^ $needed_opcode == 0 {
-            let $from = *$self.registers.bin_as_register(Cpu::get_second_argument($opcode));
-            let $to: &mut u8 = if $is_a {&mut $self.registers.a} else {$self.registers.bin_as_register(Cpu::get_first_argument($opcode))};
-            $f
-            continue;
-        }
-		if $cmd ^ ($needed_opcode ^ 0b01000) == 0 && ($opcode & 0b111) == 0b110 {
-            let $from = $self.get_w();
-            let $to: &mut u8 = if $is_a {&mut $self.registers.a} else {$self.registers.bin_as_register(Cpu::get_first_argument($opcode))};
-            $f
-            continue;
-        }
-    )
+impl fmt::Display for CpuError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CpuError::Undocumented { address, opcode } => write!(
+                fmt,
+                "undocumented opcode {:02X}H at {:04X}H, an alias of {}",
+                opcode, address, OPCODES[*opcode as usize].mnemonic
+            ),
+            CpuError::Watchpoint(hits) => {
+                let hits: Vec<String> = hits.iter().map(Hit::to_string).collect();
+                write!(fmt, "watchpoint, {}", hits.join(", "))
+            }
+            CpuError::Output(error) => write!(fmt, "output: {}", error),
+        }
+    }
 }
 
 impl Cpu {
@@ -44,152 +76,439 @@ impl Cpu {
         Self {
             memory: Memory::new(),
             registers: Registers::new(),
+            syntax: Syntax::Intel,
+            observers: Vec::new(),
+            cycles: 0,
+            strict: false,
+            halted: false,
+            interrupts: false,
+            ports: vec![0; 0x100],
+            watchpoints: Vec::new(),
+            hits: Vec::new(),
+            port_breaks: Vec::new(),
+            class_breaks: Vec::new(),
+            events: Vec::new(),
+            instruction: 0,
+        }
+    }
+
+    pub fn observe(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    //Run from PC until it reaches `end` or a HLT
+    pub fn run(&mut self, end: usize) -> Result<(), CpuError> {
+        let mut result = Ok(());
+        while self.registers.pc as usize != end && !self.halted {
+            result = self.step();
+            if result.is_ok() && !self.hits.is_empty() {
+                result = Err(CpuError::Watchpoint(mem::take(&mut self.hits)));
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+        for observer in &mut self.observers {
+            observer
+                .finish()
+                .map_err(|error| CpuError::Output(error.to_string()))?;
+        }
+        result
+    }
+
+    //Call `hook` on every observer with the CPU as it is now
+    fn notify(&mut self, hook: fn(&mut dyn Observer, &Cpu)) {
+        let mut observers = mem::take(&mut self.observers);
+        for observer in &mut observers {
+            hook(observer.as_mut(), self);
+        }
+        self.observers = observers;
+    }
+
+    /* Interrupt with an RST from the device, ignored while interrupts are disabled. It also
+     * ends a HLT. Returns whether the CPU accepted it.
+     */
+    pub fn interrupt(&mut self, vector: u8) -> bool {
+        if !self.interrupts {
+            return false;
+        }
+        self.interrupts = false;
+        self.halted = false;
+        self.instruction = self.registers.pc;
+        self.stack_push(self.registers.pc);
+        self.registers.pc = (vector as u16 & 7) << 3;
+        self.cycles += OPCODES[0xC7].cycles.0 as u64;
+        for observer in &mut self.observers {
+            observer.interrupt(self.registers.pc, vector);
         }
+        true
     }
 
-    pub fn execute(&mut self, program: Vec<u8>) -> () {
-        let program_memory = &mut self.memory.program[0..program.len() as u16];
-        program_memory.clone_from_slice(&program[..]);
-
-        println!("Loaded program: {:x?}", program_memory);
-
-        while self.registers.pc as usize != program.len() {
-            //        for _ in 0..u32::MAX {
-            let opcode = self.get_w();
-            let opcode_h = opcode >> 3; //For opcodes where higher bits its a command
-            let opcode_l = opcode & 0b0111; //For opcodes where command in lower bits.
-			#[cfg(debug_assertions)] {
-				println!("Processor data: {:?}", self);
-				println!(
-					"Current command: {}({:2X})",
-					assembler::disassembler(opcode),
-					opcode
-				);
-			}
-
-			//ADC and ACI
-            check_cmd!(self.alu_add(dbg!(value) + self.registers.get_flag(Flag::Carry) as u8), opcode_h, opcode, 0b10001, true, self, _to, value);
-            //SUB and SUI
-            check_cmd!(self.alu_sub(value), opcode_h, opcode, 0b10010, true, self, _to, value);
-            //CMP and CPI
-            check_cmd!({
-                    let temp = self.registers.a;
-                    self.alu_sub(value);
-                    self.registers.a = temp;
-                },
-				opcode_h,opcode, 0b10110, true, self, _to, value);
-            //ANA and ANI
-            check_cmd!(self.alu_and(value), opcode_h, opcode, 0b10100, true, self, _to, value);
-            //ADD and ADI
-            check_cmd!(self.alu_add(value), opcode_h, opcode, 0b10000, true, self, _to, value);
-            //MOV and MVI
-            check_cmd!(*to = value, opcode_h & 0b00, opcode, 0b01000, false, self, to, value);
-            //???
-            match opcode {
-                //LDA
-                0x3a => {
-                    let var_adress = self.get_dw();
-                    self.registers.a = self.memory.program[var_adress]
+    //Execute the instruction at PC, nothing happens while the CPU is halted
+    pub fn step(&mut self) -> Result<(), CpuError> {
+        if self.halted {
+            return Ok(());
+        }
+        if !self.observers.is_empty() {
+            self.notify(|observer, cpu| observer.before(cpu));
+        }
+        self.instruction = self.registers.pc;
+        let fetched = self.get_w();
+        if !self.class_breaks.is_empty() {
+            self.check_class(fetched);
+        }
+        let opcode = match fetched {
+            opcode if OPCODES[opcode as usize].documented => opcode,
+            opcode if self.strict => {
+                let address = self.registers.pc.wrapping_sub(1);
+                self.registers.pc = address;
+                return Err(CpuError::Undocumented { address, opcode });
+            }
+            opcode => opcodes::twin(opcode),
+        };
+        let first = Cpu::get_first_argument(opcode);
+        let second = Cpu::get_second_argument(opcode);
+        let pair = first >> 1;
+        //Only conditional calls and returns take longer when their condition holds
+        let mut taken = false;
+        match opcode {
+            0x00 => (), //NOP
+            //HLT takes the place of MOV M, M
+            0x76 => {
+                self.halted = true;
+                for observer in &mut self.observers {
+                    observer.halt(self.instruction);
                 }
-                //All kind of jumps
-                0xC3 => self.alu_jmp(true), //JMP
-                0xCA => self.alu_jmp(self.registers.get_flag(Flag::Zero)), //JZ
-                0xC2 => self.alu_jmp(!self.registers.get_flag(Flag::Zero)), //JNZ
-                0xF2 => self.alu_jmp(self.registers.get_flag(Flag::Sign)), //JP
-                0xFA => self.alu_jmp(!self.registers.get_flag(Flag::Sign)), //JM
-                0xDA => self.alu_jmp(self.registers.get_flag(Flag::Carry)), //JC
-                0xD2 => self.alu_jmp(!self.registers.get_flag(Flag::Carry)), //JNC
-                0xEA => self.alu_jmp(self.registers.get_flag(Flag::Parity)), //JPE
-                0xE2 => self.alu_jmp(!self.registers.get_flag(Flag::Parity)), //JPO
-                //CALL
-                0xCD => {
-                    self.stack_push(self.registers.pc + 1);
-                    self.alu_jmp(true)
+            }
+            //MOV
+            0x40..=0x7F => {
+                let value = self.get_register(second);
+                self.set_register(first, value);
+            }
+            //ADD, ADC, SUB, SBB, ANA, XRA, ORA, CMP
+            0x80..=0xBF => {
+                let value = self.get_register(second);
+                self.alu(first, value);
+            }
+            //ADI, ACI, SUI, SBI, ANI, XRI, ORI, CPI
+            _ if opcode & 0xC7 == 0xC6 => {
+                let value = self.get_w();
+                self.alu(first, value);
+            }
+            //MVI
+            _ if opcode & 0xC7 == 0x06 => {
+                let value = self.get_w();
+                self.set_register(first, value);
+            }
+            //INR
+            _ if opcode & 0xC7 == 0x04 => {
+                let value = self.get_register(first).wrapping_add(1);
+                self.set_register(first, value);
+                self.set_szp(value);
+                self.registers.set_flag(Flag::ACarry, value & 0xF == 0);
+            }
+            //DCR
+            _ if opcode & 0xC7 == 0x05 => {
+                let value = self.get_register(first).wrapping_sub(1);
+                self.set_register(first, value);
+                self.set_szp(value);
+                self.registers.set_flag(Flag::ACarry, value & 0xF != 0xF);
+            }
+            //LXI
+            _ if opcode & 0xCF == 0x01 => {
+                let value = self.get_dw();
+                self.set_pair(pair, value);
+            }
+            //DAD
+            _ if opcode & 0xCF == 0x09 => {
+                let sum = self.get_pair(2) as u32 + self.get_pair(pair) as u32;
+                self.registers.set_flag(Flag::Carry, sum > 0xFFFF);
+                self.set_pair(2, sum as u16);
+            }
+            //INX
+            _ if opcode & 0xCF == 0x03 => {
+                let value = self.get_pair(pair).wrapping_add(1);
+                self.set_pair(pair, value);
+            }
+            //DCX
+            _ if opcode & 0xCF == 0x0B => {
+                let value = self.get_pair(pair).wrapping_sub(1);
+                self.set_pair(pair, value);
+            }
+            //STAX
+            0x02 | 0x12 => {
+                let address = self.get_pair(pair);
+                self.write(address, self.registers.a);
+            }
+            //LDAX
+            0x0A | 0x1A => {
+                let address = self.get_pair(pair);
+                self.registers.a = self.read(address);
+            }
+            //SHLD
+            0x22 => {
+                let address = self.get_dw();
+                self.write(address, self.registers.l);
+                self.write(address.wrapping_add(1), self.registers.h);
+            }
+            //LHLD
+            0x2A => {
+                let address = self.get_dw();
+                self.registers.l = self.read(address);
+                self.registers.h = self.read(address.wrapping_add(1));
+            }
+            //STA
+            0x32 => {
+                let address = self.get_dw();
+                self.write(address, self.registers.a);
+            }
+            //LDA
+            0x3A => {
+                let address = self.get_dw();
+                self.registers.a = self.read(address);
+            }
+            //RLC
+            0x07 => {
+                let a = self.registers.a;
+                self.registers.set_flag(Flag::Carry, a & 0x80 != 0);
+                self.registers.a = a.rotate_left(1);
+            }
+            //RRC
+            0x0F => {
+                let a = self.registers.a;
+                self.registers.set_flag(Flag::Carry, a & 1 != 0);
+                self.registers.a = a.rotate_right(1);
+            }
+            //RAL
+            0x17 => {
+                let a = self.registers.a;
+                let carry = self.registers.get_flag(Flag::Carry) as u8;
+                self.registers.set_flag(Flag::Carry, a & 0x80 != 0);
+                self.registers.a = a << 1 | carry;
+            }
+            //RAR
+            0x1F => {
+                let a = self.registers.a;
+                let carry = self.registers.get_flag(Flag::Carry) as u8;
+                self.registers.set_flag(Flag::Carry, a & 1 != 0);
+                self.registers.a = a >> 1 | carry << 7;
+            }
+            0x27 => self.alu_daa(),                             //DAA
+            0x2F => self.registers.a = !self.registers.a,       //CMA
+            0x37 => self.registers.set_flag(Flag::Carry, true), //STC
+            0x3F => self
+                .registers
+                .set_flag(Flag::Carry, !self.registers.get_flag(Flag::Carry)), //CMC
+            //All kind of jumps
+            0xC3 => self.alu_jmp(true), //JMP
+            _ if opcode & 0xC7 == 0xC2 => self.alu_jmp(self.condition(first)), //Jcc
+            //CALL
+            0xCD => self.alu_call(true),
+            _ if opcode & 0xC7 == 0xC4 => {
+                taken = self.condition(first);
+                self.alu_call(taken)
+            }
+            0xC9 => self.registers.pc = self.stack_pop(), //RET
+            _ if opcode & 0xC7 == 0xC0 => {
+                taken = self.condition(first);
+                if taken {
+                    self.registers.pc = self.stack_pop();
                 }
-                0xC9 => self.registers.pc = self.stack_pop(), //RET
-                0x76 => return,                               //HLT
-                //NOTE: PUSH and POP have a special 11 bit in height half of opcode
-                //PUSH
-                _ if ((opcode_h >> 3) ^ 0b11 == 0) && (opcode_l ^ 0b101 == 0) => {
-                    let value = self.registers.get_dw_reg(Cpu::get_first_argument(opcode));
-                    self.stack_push(value)
+            }
+            //RST
+            _ if opcode & 0xC7 == 0xC7 => {
+                self.stack_push(self.registers.pc);
+                self.registers.pc = (first as u16) << 3;
+            }
+            //NOTE: PUSH and POP have a special 11 bit in height half of opcode
+            //PUSH
+            _ if opcode & 0xCF == 0xC5 => {
+                let value = self.get_stack_pair(pair);
+                self.stack_push(value);
+            }
+            //POP
+            _ if opcode & 0xCF == 0xC1 => {
+                let value = self.stack_pop();
+                self.set_stack_pair(pair, value);
+            }
+            //XTHL
+            0xE3 => {
+                let value = self.stack_pop();
+                let hl = self.get_pair(2);
+                self.stack_push(hl);
+                self.set_pair(2, value);
+            }
+            //XCHG
+            0xEB => {
+                let (de, hl) = (self.get_pair(1), self.get_pair(2));
+                self.set_pair(1, hl);
+                self.set_pair(2, de);
+            }
+            0xE9 => self.registers.pc = self.get_pair(2), //PCHL
+            0xF9 => self.registers.sp = self.get_pair(2), //SPHL
+            //OUT
+            0xD3 => {
+                let port = self.get_w();
+                self.ports[port as usize] = self.registers.a;
+                for observer in &mut self.observers {
+                    observer.output(self.instruction, port, self.registers.a);
                 }
-                //POP
-                _ if ((opcode_h >> 3) ^ 0b11 == 0) && (opcode_l ^ 0b001 == 0) => {
-                    let value = self.stack_pop();
-                    self.registers
-                        .set_dw_reg(Cpu::get_first_argument(opcode), value)
+                if !self.port_breaks.is_empty() {
+                    self.check_port(Direction::Out, port, self.registers.a);
                 }
-                0x2F => self.registers.a = !self.registers.a, //CMA
-                0x3F => self
-                    .registers
-                    .set_flag(Flag::Carry, !self.registers.get_flag(Flag::Carry)), //CMC
-                0x0 => println!("NOP goted."),                //NOP
-                //INR
-                _ if opcode_l ^ 0b100 == 0 => {
-                    let to = self
-                        .registers
-                        .bin_as_register(Cpu::get_first_argument(opcode));
-                    *to = (*to).wrapping_add(1);
+            }
+            //IN
+            0xDB => {
+                let port = self.get_w();
+                self.registers.a = self.ports[port as usize];
+                for observer in &mut self.observers {
+                    observer.input(self.instruction, port, self.registers.a);
                 }
-                //DCR
-                _ if opcode_l ^ 0b101 == 0 => {
-                    let to = self
-                        .registers
-                        .bin_as_register(Cpu::get_first_argument(opcode));
-                    *to = (*to).wrapping_sub(1);
+                if !self.port_breaks.is_empty() {
+                    self.check_port(Direction::In, port, self.registers.a);
                 }
-                _ => unreachable!("{:x}", opcode),
             }
+            0xF3 => self.interrupts = false, //DI
+            0xFB => self.interrupts = true,  //EI
+            _ => unreachable!("{:x}", opcode),
+        }
+        let (when_taken, not_taken) = OPCODES[opcode as usize].cycles;
+        self.cycles += if taken { when_taken } else { not_taken } as u64;
+        if !self.observers.is_empty() {
+            self.notify(|observer, cpu| observer.after(cpu));
+        }
+        Ok(())
+    }
+
+    fn check_class(&mut self, opcode: u8) {
+        let pc = self.instruction;
+        let classes = self.class_breaks.iter();
+        if let Some(class) = classes.copied().find(|class| class.contains(opcode)) {
+            self.events.push(Event::Instruction { pc, class, opcode });
+        }
+    }
+
+    fn check_port(&mut self, direction: Direction, port: u8, value: u8) {
+        let triggered = self
+            .port_breaks
+            .iter()
+            .any(|port_break| port_break.triggers(direction, port, value));
+        if triggered {
+            self.events.push(Event::Port {
+                pc: self.instruction,
+                direction,
+                port,
+                value,
+            });
         }
-		println!("Result: {:?}", self);
+    }
+
+    //Condition of Jcc, Ccc and Rcc by bits 3-5 of the opcode: NZ, Z, NC, C, PO, PE, P, M
+    fn condition(&self, bits: u8) -> bool {
+        let flag = match bits >> 1 {
+            0 => Flag::Zero,
+            1 => Flag::Carry,
+            2 => Flag::Parity,
+            _ => Flag::Sign,
+        };
+        self.registers.get_flag(flag) == (bits & 1 != 0)
     }
 }
 
 //Stack operations
 impl Cpu {
-    fn stack_push(&mut self, value: u16) -> () {
-        self.registers.sp += 1;
-        self.memory.stack[self.registers.sp] = value;
+    fn stack_push(&mut self, value: u16) {
+        self.registers.sp = self.registers.sp.wrapping_sub(2);
+        self.write(self.registers.sp, value as u8);
+        self.write(self.registers.sp.wrapping_add(1), (value >> 8) as u8);
     }
 
     fn stack_pop(&mut self) -> u16 {
-        let ret = self.memory.stack[self.registers.sp];
-        self.registers.sp -= 1;
-        ret
+        let low = self.read(self.registers.sp);
+        let high = self.read(self.registers.sp.wrapping_add(1));
+        self.registers.sp = self.registers.sp.wrapping_add(2);
+        u16::from_le_bytes([low, high])
     }
 }
 
 //ALU Operations
 impl Cpu {
-    fn alu_add(&mut self, value: u8) {
-        self.registers.clr();
-        self.registers
-            .set_flag(Flag::ACarry, (self.registers.a & 0xf) + (value & 0xf) > 0xf);
+    //Arithmetic and logic with A, the operation is bits 3-5 of the opcode
+    fn alu(&mut self, operation: u8, value: u8) {
+        let carry = self.registers.get_flag(Flag::Carry) as u8;
+        match operation {
+            0 => self.registers.a = self.alu_add(value, 0),
+            1 => self.registers.a = self.alu_add(value, carry),
+            2 => self.registers.a = self.alu_sub(value, 0),
+            3 => self.registers.a = self.alu_sub(value, carry),
+            4 => self.alu_and(value),
+            5 => self.alu_logic(self.registers.a ^ value),
+            6 => self.alu_logic(self.registers.a | value),
+            //CMP only keeps the flags
+            _ => {
+                self.alu_sub(value, 0);
+            }
+        }
+    }
 
-        self.registers.a = self.registers.a.wrapping_add(value);
+    fn set_szp(&mut self, value: u8) {
+        self.registers.set_flag(Flag::Sign, value & 0x80 != 0);
+        self.registers.set_flag(Flag::Zero, value == 0);
+        self.registers
+            .set_flag(Flag::Parity, value.count_ones() & 1 == 0);
+    }
 
-        self.registers.set_flag(Flag::Sign, false); //FIXME: change it:D
-        self.registers.set_flag(Flag::Zero, self.registers.a == 0);
+    fn alu_add(&mut self, value: u8, carry: u8) -> u8 {
+        let a = self.registers.a;
+        let sum = a as u16 + value as u16 + carry as u16;
         self.registers
-            .set_flag(Flag::Parity, self.registers.a.count_ones() & 1 == 0);
+            .set_flag(Flag::ACarry, (a & 0xf) + (value & 0xf) + carry > 0xf);
+        self.registers.set_flag(Flag::Carry, sum > 0xFF);
+        self.set_szp(sum as u8);
+        sum as u8
+    }
+
+    //The 8080 subtracts by adding the complement, the auxiliary carry comes from that sum
+    fn alu_sub(&mut self, value: u8, borrow: u8) -> u8 {
+        let a = self.registers.a;
+        let difference = a.wrapping_sub(value).wrapping_sub(borrow);
+        self.registers.set_flag(
+            Flag::ACarry,
+            (a & 0xf) + (!value & 0xf) + (1 - borrow) > 0xf,
+        );
         self.registers
-            .set_flag(Flag::Carry, self.registers.a < value);
+            .set_flag(Flag::Carry, (a as u16) < value as u16 + borrow as u16);
+        self.set_szp(difference);
+        difference
     }
 
-    fn alu_sub(&mut self, value: u8) {
-        self.registers.clr();
-        self.registers.set_flag(Flag::ACarry, false); //(self.registers.a & 0xf) + (value & 0xf) > 0xf
+    fn alu_and(&mut self, value: u8) {
+        let a = self.registers.a;
+        self.alu_logic(a & value);
+        self.registers
+            .set_flag(Flag::ACarry, (a | value) & 0x08 != 0);
+    }
 
-        let temp = self.registers.a;
-        self.registers.a = self.registers.a.wrapping_sub(value);
+    fn alu_logic(&mut self, result: u8) {
+        self.registers.a = result;
+        self.set_szp(result);
+        self.registers.set_flag(Flag::ACarry, false);
+        self.registers.set_flag(Flag::Carry, false);
+    }
 
-        self.registers.set_flag(Flag::Sign, false); //FIXME: change it:D
-        self.registers.set_flag(Flag::Zero, self.registers.a == 0);
-        self.registers
-            .set_flag(Flag::Parity, self.registers.a.count_ones() & 1 == 0);
-        self.registers
-            .set_flag(Flag::Carry, self.registers.a > temp);
+    fn alu_daa(&mut self) {
+        let a = self.registers.a;
+        let mut correction = 0;
+        let mut carry = self.registers.get_flag(Flag::Carry);
+        if a & 0xF > 9 || self.registers.get_flag(Flag::ACarry) {
+            correction |= 0x06;
+        }
+        if a > 0x99 || carry {
+            correction |= 0x60;
+            carry = true;
+        }
+        self.registers.a = self.alu_add(correction, 0);
+        self.registers.set_flag(Flag::Carry, carry);
     }
 
     fn alu_jmp(&mut self, exp: bool) {
@@ -199,36 +518,68 @@ impl Cpu {
         }
     }
 
-    fn alu_and(&mut self, value: u8) -> () {
-        self.registers.clr();
-
-        self.registers.a &= value;
-
-        self.registers.set_flag(Flag::ACarry, true);
-        self.registers.set_flag(Flag::Sign, false); //FIXME: change it:D
-        self.registers.set_flag(Flag::Zero, self.registers.a == 0);
-        self.registers
-            .set_flag(Flag::Parity, self.registers.a.count_ones() & 1 == 0);
-        self.registers.set_flag(Flag::Carry, false);
+    fn alu_call(&mut self, exp: bool) {
+        let to_adress = self.get_dw();
+        if exp {
+            self.stack_push(self.registers.pc);
+            self.registers.pc = to_adress;
+        }
     }
 }
 
 //Functions for read/write memory
 impl Cpu {
+    //Fetching is no memory access for the watchpoints
     fn get_w(&mut self) -> u8 {
         let data = self.memory.program[self.registers.pc];
-        self.registers.pc += 1;
+        self.registers.pc = self.registers.pc.wrapping_add(1);
         data
     }
 
     fn get_dw(&mut self) -> u16 {
-        let data = ext::split_slice(&self.memory.program[self.registers.pc..self.registers.pc + 2]);
-        self.registers.pc += 2;
-        data
+        let bytes = [self.get_w(), self.get_w()];
+        ext::split_slice(&bytes)
     }
 
-    fn _get_slice(&self, start: u16, amount: u16) -> &[u8] {
-        &self.memory.program[start..start + amount + 1]
+    fn read(&mut self, address: u16) -> u8 {
+        let value = self.memory.program[address];
+        if !self.watchpoints.is_empty() {
+            self.watch(address, Access::Read, value, value);
+        }
+        for observer in &mut self.observers {
+            observer.read(self.instruction, address, value);
+        }
+        value
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        if !self.watchpoints.is_empty() {
+            let old = self.memory.program[address];
+            self.watch(address, Access::Write, old, value);
+        }
+        if !self.observers.is_empty() {
+            let old = self.memory.program[address];
+            for observer in &mut self.observers {
+                observer.write(self.instruction, address, old, value);
+            }
+        }
+        self.memory.program[address] = value;
+    }
+
+    fn watch(&mut self, address: u16, access: Access, old: u8, new: u8) {
+        let triggered = self
+            .watchpoints
+            .iter()
+            .any(|watchpoint| watchpoint.triggers(address, access, old, new));
+        if triggered {
+            self.hits.push(Hit {
+                pc: self.instruction,
+                address,
+                access,
+                old,
+                new,
+            });
+        }
     }
 }
 
@@ -240,4 +591,56 @@ impl Cpu {
     fn get_second_argument(op: u8) -> u8 {
         op & 0b000111
     }
+
+    //B, C, D, E, H, L, M or A, M is the memory at HL
+    fn get_register(&mut self, b: u8) -> u8 {
+        match b {
+            0b110 => {
+                let address = self.get_pair(2);
+                self.read(address)
+            }
+            _ => *self.registers.bin_as_register(b),
+        }
+    }
+
+    fn set_register(&mut self, b: u8, value: u8) {
+        match b {
+            0b110 => {
+                let address = self.get_pair(2);
+                self.write(address, value)
+            }
+            _ => *self.registers.bin_as_register(b) = value,
+        }
+    }
+
+    //BC, DE, HL or SP
+    fn get_pair(&mut self, pair: u8) -> u16 {
+        match pair {
+            3 => self.registers.sp,
+            _ => self.registers.get_dw_reg(pair << 1),
+        }
+    }
+
+    fn set_pair(&mut self, pair: u8, value: u16) {
+        match pair {
+            3 => self.registers.sp = value,
+            _ => self.registers.set_dw_reg(pair << 1, value),
+        }
+    }
+
+    //BC, DE, HL or PSW, the bits of F that are no flags read as they do on the 8080
+    fn get_stack_pair(&mut self, pair: u8) -> u16 {
+        let value = self.registers.get_dw_reg(pair << 1);
+        match pair {
+            3 => value & 0xFFD7 | 0x0002,
+            _ => value,
+        }
+    }
+
+    fn set_stack_pair(&mut self, pair: u8, value: u16) {
+        self.registers.set_dw_reg(pair << 1, value);
+        if pair == 3 {
+            self.registers.f = self.registers.f & 0xD7 | 0x02;
+        }
+    }
 }
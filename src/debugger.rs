@@ -0,0 +1,755 @@
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt::Write as _,
+    fs,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    cpu::Cpu,
+    modules::{
+        assembler::{disassembler, expr::parse_number},
+        breaks::{Class, Direction, PortBreak},
+        condition::{Condition, Machine},
+        opcodes::{self, Category, OPCODES},
+        registers::Flag,
+        snapshot,
+        watch::{Trigger, Watchpoint},
+    },
+};
+
+const HELP: &str = "\
+s, step [count]          execute instructions
+n, next                  step over a CALL or RST
+o, out                   run until the current subroutine returns
+c, continue              run until a breakpoint or HLT
+b, break [address]... [if condition]
+                         set breakpoints, list them without an address
+d, delete address|all    clear breakpoints
+port [in|out] port[-end] [value]
+                         stop on IN or OUT, list the port breaks without a port
+catch [class]...         stop on rst, hlt, ei, di, pchl or undocumented opcodes
+delete port number|all, delete catch class|all
+                         clear port and instruction breaks
+w, watch [start[-end]] [read|write|change]
+                         stop on memory accesses, list the watchpoints without a range
+u, unwatch number...|all clear watchpoints
+r, regs                  show registers and flags
+int vector               interrupt with RST vector, if interrupts are enabled
+save file, load file     write the machine to a snapshot or go on from one
+set name value           set A..L, BC, DE, HL, SP, PC or the flags S, Z, AC, P, CY
+x, dump [address] [len]  show memory
+e, edit address values   write bytes and 'strings' to memory
+f, find values           search memory for bytes and 'strings'
+l, list [address] [n]    disassemble, around PC without an address
+history                  list the commands, !! or !n repeats one
+q, quit
+Addresses are numbers like 100H or symbols, Enter repeats the last command.
+Conditions look at registers, flags, [HL], w[SP], cycles and hits, like A == 1BH && Z.
+";
+const DUMP_BYTES: u16 = 64;
+const BYTES_PER_ROW: u16 = 16;
+const LIST_LINES: usize = 10;
+//Instructions shown before PC when listing around it
+const LIST_BEFORE: usize = 3;
+const MATCHES_SHOWN: usize = 16;
+
+struct Breakpoint {
+    //Text as it was given and the parsed condition, it stops every time without one
+    condition: Option<(String, Condition)>,
+    hits: u64,
+}
+
+pub struct Debugger {
+    pub(crate) cpu: Cpu,
+    symbols: BTreeMap<String, u16>,
+    breakpoints: BTreeMap<u16, Breakpoint>,
+    history: Vec<String>,
+    //Where dump and list go on when they are repeated without an address
+    next_dump: u16,
+    next_list: Option<u16>,
+}
+
+//Symbols of a symbol table as `asm --symbols` writes it, one `NAME EQU value` per line
+pub fn parse_symbols(text: &str) -> BTreeMap<String, u16> {
+    text.lines()
+        .filter_map(
+            |line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [name, "EQU", value] => {
+                    let value = parse_number(value).ok()?;
+                    Some((name.to_string(), u16::try_from(value).ok()?))
+                }
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+//Bytes and quoted strings separated by spaces or commas
+fn values(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut rest = text.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    while let Some(first) = rest.chars().next() {
+        if first == '\'' || first == '"' {
+            let end = rest[1..]
+                .find(first)
+                .ok_or_else(|| format!("unterminated string {}", rest))?;
+            bytes.extend_from_slice(&rest.as_bytes()[1..end + 1]);
+            rest = &rest[end + 2..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || c == ',')
+                .unwrap_or(rest.len());
+            let value = parse_number(&rest[..end])
+                .ok()
+                .and_then(|value| u8::try_from(value).ok())
+                .ok_or_else(|| format!("invalid byte `{}`", &rest[..end]))?;
+            bytes.push(value);
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == ',');
+    }
+    Ok(bytes)
+}
+
+fn is_call(opcode: u8) -> bool {
+    let opcode = &OPCODES[opcode as usize];
+    opcode.category == Category::Branch && opcode.mnemonic.starts_with('C')
+        || opcode.mnemonic == "RST"
+}
+
+fn is_return(opcode: u8) -> bool {
+    let opcode = &OPCODES[opcode as usize];
+    opcode.category == Category::Branch
+        && opcode.mnemonic.starts_with('R')
+        && opcode.mnemonic != "RST"
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu, symbols: BTreeMap<String, u16>) -> Self {
+        Self {
+            cpu,
+            symbols,
+            breakpoints: BTreeMap::new(),
+            history: Vec::new(),
+            next_dump: 0,
+            next_list: None,
+        }
+    }
+
+    //Read commands from the terminal until quit or the end of input
+    pub fn repl(&mut self) {
+        print!("{}", self.location());
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("(dbg) ");
+            io::stdout().flush().unwrap();
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => break,
+            };
+            match self.input(&line) {
+                Some(output) => print!("{}", output),
+                None => break,
+            }
+        }
+    }
+
+    /* One line of input: Enter repeats the last command, !! and !n one from the history.
+     * None when the debugger should quit.
+     */
+    pub fn input(&mut self, line: &str) -> Option<String> {
+        let line = line.trim();
+        let recalled = match line {
+            "" | "!!" => self.history.last().cloned(),
+            _ if line.starts_with('!') => line[1..]
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| self.history.get(n.wrapping_sub(1)).cloned()),
+            _ => {
+                self.history.push(line.to_string());
+                Some(line.to_string())
+            }
+        };
+        match recalled {
+            Some(command) => self.command(&command),
+            None if line.is_empty() => Some(String::new()),
+            None => Some(format!("error: no command {} in the history\n", line)),
+        }
+    }
+
+    fn command(&mut self, line: &str) -> Option<String> {
+        let (name, rest) = match line.find(char::is_whitespace) {
+            Some(space) => (&line[..space], line[space..].trim()),
+            None => (line, ""),
+        };
+        let args: Vec<&str> = rest.split_whitespace().collect();
+        let result = match name {
+            "s" | "step" => match args.first() {
+                Some(count) => match count.parse::<usize>() {
+                    Ok(count) => self.step(count),
+                    Err(_) => Err(format!("invalid count `{}`", count)),
+                },
+                None => self.step(1),
+            },
+            "n" | "next" => self.next(),
+            "o" | "out" => self.out(),
+            "c" | "continue" => self.resume(|_, _| false),
+            "b" | "break" => self.set_breakpoints(rest),
+            "d" | "delete" => self.delete_breakpoints(&args),
+            "w" | "watch" => self.set_watchpoint(&args),
+            "u" | "unwatch" => self.delete_watchpoints(&args),
+            "port" => self.set_port_break(&args),
+            "catch" => self.catch(&args),
+            "r" | "regs" => Ok(self.registers()),
+            "int" => self.interrupt(&args),
+            "save" => self.save(rest),
+            "load" => self.load(rest),
+            "set" => self.set(&args),
+            "x" | "dump" => self.dump(&args),
+            "e" | "edit" => self.edit(rest),
+            "f" | "find" => self.find(rest),
+            "l" | "list" => self.list(&args),
+            "history" => Ok(self
+                .history
+                .iter()
+                .enumerate()
+                .map(|(i, command)| format!("{:4}  {}\n", i + 1, command))
+                .collect()),
+            "h" | "help" => Ok(HELP.to_string()),
+            "q" | "quit" => return None,
+            _ => Err(format!("unknown command `{}`, try help", name)),
+        };
+        Some(result.unwrap_or_else(|error| format!("error: {}\n", error)))
+    }
+
+    //Number or symbol
+    fn value(&self, text: &str) -> Result<u16, String> {
+        if let Some(value) = self
+            .symbols
+            .get(text)
+            .or_else(|| self.symbols.get(&text.to_ascii_uppercase()))
+        {
+            return Ok(*value);
+        }
+        parse_number(text)
+            .ok()
+            .and_then(|value| u16::try_from(value).ok())
+            .ok_or_else(|| format!("`{}` is neither an address nor a symbol", text))
+    }
+
+    fn symbol_at(&self, address: u16) -> Option<&String> {
+        self.symbols
+            .iter()
+            .find(|(_, value)| **value == address)
+            .map(|(name, _)| name)
+    }
+
+    //Instruction at PC with its label
+    fn location(&self) -> String {
+        let pc = self.cpu.registers.pc;
+        let mut out = String::new();
+        if let Some(name) = self.symbol_at(pc) {
+            writeln!(out, "{}:", name).unwrap();
+        }
+        writeln!(out, "{}", self.lines(pc, 1)[0]).unwrap();
+        out
+    }
+
+    fn lines(&self, address: u16, count: usize) -> Vec<disassembler::Line> {
+        //Instructions are at most 3 bytes, the listing stops at the end of memory
+        let end = (address as usize).saturating_add(count.saturating_mul(3).saturating_add(2));
+        let bytes = self.cpu.memory.read(address, end.min(0xFFFF) as u16);
+        disassembler::disassemble(&bytes, address, self.cpu.syntax)
+            .take(count)
+            .collect()
+    }
+
+    /* Step until `done` holds for the CPU after an instruction with the opcode, a breakpoint
+     * is reached or the CPU halts. The first instruction runs even on a breakpoint.
+     */
+    fn resume(&mut self, mut done: impl FnMut(&Cpu, u8) -> bool) -> Result<String, String> {
+        if self.cpu.halted {
+            return Err("the CPU is halted".to_string());
+        }
+        let reason = loop {
+            let opcode = self.cpu.memory.program[self.cpu.registers.pc];
+            self.cpu.step().map_err(|error| error.to_string())?;
+            if !self.cpu.hits.is_empty() || !self.cpu.events.is_empty() {
+                let hits = self.cpu.hits.drain(..);
+                let events = self.cpu.events.drain(..);
+                break hits
+                    .map(|hit| format!("watchpoint, {}\n", hit))
+                    .chain(events.map(|event| format!("break, {}\n", event)))
+                    .collect();
+            }
+            if done(&self.cpu, opcode) {
+                break String::new();
+            }
+            if self.cpu.halted {
+                break "halted\n".to_string();
+            }
+            if let Some(breakpoint) = self.breakpoints.get_mut(&self.cpu.registers.pc) {
+                breakpoint.hits += 1;
+                let machine = Machine {
+                    registers: &self.cpu.registers,
+                    memory: &self.cpu.memory,
+                    cycles: self.cpu.cycles,
+                    hits: breakpoint.hits,
+                };
+                match &breakpoint.condition {
+                    Some((text, condition)) if condition.holds(&machine) => {
+                        break format!("breakpoint, {}\n", text)
+                    }
+                    Some(_) => (),
+                    None => break "breakpoint\n".to_string(),
+                }
+            }
+        };
+        self.next_list = None;
+        Ok(format!("{}{}", reason, self.location()))
+    }
+
+    fn step(&mut self, count: usize) -> Result<String, String> {
+        let mut steps = 0;
+        self.resume(|_, _| {
+            steps += 1;
+            steps >= count
+        })
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let pc = self.cpu.registers.pc;
+        let opcode = self.cpu.memory.program[pc];
+        if !is_call(opcode) {
+            return self.step(1);
+        }
+        let after = pc.wrapping_add(OPCODES[opcode as usize].length as u16);
+        self.resume(|cpu, _| cpu.registers.pc == after)
+    }
+
+    //Until a return leaves the stack above where it is now
+    fn out(&mut self) -> Result<String, String> {
+        //A return from FFFEH takes SP round to 0000H, so the distance is compared
+        let sp = self.cpu.registers.sp;
+        self.resume(|cpu, opcode| is_return(opcode) && cpu.registers.sp.wrapping_sub(sp) as i16 > 0)
+    }
+
+    //The condition is parsed here so a mistake shows before the program runs
+    fn set_breakpoints(&mut self, rest: &str) -> Result<String, String> {
+        let (addresses, condition) = match rest.find(" if ") {
+            Some(at) => {
+                let text = rest[at + 4..].trim();
+                let condition = Condition::parse(text, &self.symbols)?;
+                (&rest[..at], Some((text.to_string(), condition)))
+            }
+            None => (rest, None),
+        };
+        let addresses = addresses
+            .split_whitespace()
+            .map(|arg| self.value(arg))
+            .collect::<Result<Vec<u16>, String>>()?;
+        if addresses.is_empty() && condition.is_some() {
+            return Err("a condition needs an address".to_string());
+        }
+        for address in &addresses {
+            self.breakpoints.insert(
+                *address,
+                Breakpoint {
+                    condition: condition.clone(),
+                    hits: 0,
+                },
+            );
+        }
+        if !addresses.is_empty() {
+            return Ok(String::new());
+        }
+        let mut out = String::new();
+        for (address, breakpoint) in &self.breakpoints {
+            let name = self.symbol_at(*address).map_or("", String::as_str);
+            write!(
+                out,
+                "{:04X}  {:<16} hits {}",
+                address, name, breakpoint.hits
+            )
+            .unwrap();
+            if let Some((text, _)) = &breakpoint.condition {
+                write!(out, "  if {}", text).unwrap();
+            }
+            writeln!(out).unwrap();
+        }
+        Ok(out)
+    }
+
+    //`[in|out] port[-end] [value]`
+    fn set_port_break(&mut self, args: &[&str]) -> Result<String, String> {
+        if args.is_empty() {
+            let mut out = String::new();
+            for (i, port_break) in self.cpu.port_breaks.iter().enumerate() {
+                writeln!(out, "{:4}  {}", i + 1, port_break).unwrap();
+            }
+            return Ok(out);
+        }
+        let (direction, args) = match args[0].to_ascii_lowercase().as_str() {
+            "in" => (Some(Direction::In), &args[1..]),
+            "out" => (Some(Direction::Out), &args[1..]),
+            _ => (None, args),
+        };
+        let byte = |text: &str| {
+            self.value(text).and_then(|value| {
+                u8::try_from(value).map_err(|_| format!("{:04X}H is not a byte", value))
+            })
+        };
+        let (range, value) = match args {
+            [range] => (range, None),
+            [range, value] => (range, Some(byte(value)?)),
+            _ => return Err("port takes a port or a range and a value".to_string()),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (byte(start)?, byte(end)?),
+            None => (byte(range)?, byte(range)?),
+        };
+        if end < start {
+            return Err(format!("{:02X}H is before {:02X}H", end, start));
+        }
+        self.cpu.port_breaks.push(PortBreak {
+            start,
+            end,
+            direction,
+            value,
+        });
+        Ok(String::new())
+    }
+
+    fn catch(&mut self, args: &[&str]) -> Result<String, String> {
+        for arg in args {
+            let class = Class::parse(arg).ok_or_else(|| {
+                format!(
+                    "unknown class `{}`, use rst, hlt, ei, di, pchl or undocumented",
+                    arg
+                )
+            })?;
+            if !self.cpu.class_breaks.contains(&class) {
+                self.cpu.class_breaks.push(class);
+            }
+        }
+        let classes: Vec<&str> = self.cpu.class_breaks.iter().map(|c| c.name()).collect();
+        Ok(if args.is_empty() {
+            format!("{}\n", classes.join(" "))
+        } else {
+            String::new()
+        })
+    }
+
+    //`port number|all` or `catch class|all` clear those breaks, anything else breakpoints
+    fn delete_breakpoints(&mut self, args: &[&str]) -> Result<String, String> {
+        match args.first().copied() {
+            Some("port") => {
+                let mut numbers = Vec::new();
+                for arg in &args[1..] {
+                    match arg.parse::<usize>() {
+                        Ok(n) if (1..=self.cpu.port_breaks.len()).contains(&n) => {
+                            numbers.push(n - 1)
+                        }
+                        _ if *arg == "all" => numbers.extend(0..self.cpu.port_breaks.len()),
+                        _ => return Err(format!("no port break {}", arg)),
+                    }
+                }
+                numbers.sort_unstable();
+                numbers.dedup();
+                for i in numbers.into_iter().rev() {
+                    self.cpu.port_breaks.remove(i);
+                }
+                return Ok(String::new());
+            }
+            Some("catch") => {
+                for arg in &args[1..] {
+                    match Class::parse(arg) {
+                        Some(class) => self.cpu.class_breaks.retain(|c| *c != class),
+                        None if *arg == "all" => self.cpu.class_breaks.clear(),
+                        None => return Err(format!("unknown class `{}`", arg)),
+                    }
+                }
+                return Ok(String::new());
+            }
+            _ => (),
+        }
+        if args == ["all"] {
+            self.breakpoints.clear();
+        }
+        for arg in args.iter().filter(|arg| **arg != "all") {
+            let address = self.value(arg)?;
+            if self.breakpoints.remove(&address).is_none() {
+                return Err(format!("no breakpoint at {:04X}", address));
+            }
+        }
+        Ok(String::new())
+    }
+
+    //`start[-end] [read|write|change]`, a write watchpoint without a kind
+    fn set_watchpoint(&mut self, args: &[&str]) -> Result<String, String> {
+        let (range, trigger) = match args {
+            [] => {
+                let mut out = String::new();
+                for (i, watchpoint) in self.cpu.watchpoints.iter().enumerate() {
+                    writeln!(out, "{:4}  {}", i + 1, watchpoint).unwrap();
+                }
+                return Ok(out);
+            }
+            [range] => (range, Trigger::Write),
+            [range, kind] => (
+                range,
+                Trigger::parse(kind)
+                    .ok_or_else(|| format!("unknown kind `{}`, use read, write or change", kind))?,
+            ),
+            _ => return Err("watch takes a range and a kind".to_string()),
+        };
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (self.value(start)?, self.value(end)?),
+            None => (self.value(range)?, self.value(range)?),
+        };
+        if end < start {
+            return Err(format!("{:04X}H is before {:04X}H", end, start));
+        }
+        self.cpu.watchpoints.push(Watchpoint {
+            start,
+            end,
+            trigger,
+        });
+        Ok(String::new())
+    }
+
+    //By the number `watch` lists them with
+    fn delete_watchpoints(&mut self, args: &[&str]) -> Result<String, String> {
+        if args == ["all"] {
+            self.cpu.watchpoints.clear();
+            return Ok(String::new());
+        }
+        let mut numbers = Vec::new();
+        for arg in args {
+            match arg.parse::<usize>() {
+                Ok(n) if (1..=self.cpu.watchpoints.len()).contains(&n) => numbers.push(n - 1),
+                _ => return Err(format!("no watchpoint {}", arg)),
+            }
+        }
+        numbers.sort_unstable();
+        numbers.dedup();
+        for i in numbers.into_iter().rev() {
+            self.cpu.watchpoints.remove(i);
+        }
+        Ok(String::new())
+    }
+
+    fn interrupt(&mut self, args: &[&str]) -> Result<String, String> {
+        let vector = match args {
+            [vector] => match vector.parse::<u8>() {
+                Ok(vector) if vector < 8 => vector,
+                _ => return Err(format!("vector `{}` is not 0 to 7", vector)),
+            },
+            _ => return Err("int needs a vector".to_string()),
+        };
+        if !self.cpu.interrupt(vector) {
+            return Err("interrupts are disabled".to_string());
+        }
+        self.next_list = None;
+        Ok(self.location())
+    }
+
+    fn save(&self, file: &str) -> Result<String, String> {
+        if file.is_empty() {
+            return Err("save needs a file".to_string());
+        }
+        fs::write(file, snapshot::save(&self.cpu))
+            .map_err(|error| format!("{}: {}", file, error))?;
+        Ok(String::new())
+    }
+
+    fn load(&mut self, file: &str) -> Result<String, String> {
+        let bytes = fs::read(file).map_err(|error| format!("{}: {}", file, error))?;
+        snapshot::load(&mut self.cpu, &bytes).map_err(|error| format!("{}: {}", file, error))?;
+        self.next_list = None;
+        Ok(self.location())
+    }
+
+    fn registers(&self) -> String {
+        let r = &self.cpu.registers;
+        let pair = |high: u8, low: u8| u16::from_le_bytes([low, high]);
+        format!(
+            "A={:02X} F={:02X} [{}] BC={:04X} DE={:04X} HL={:04X} SP={:04X} PC={:04X} \
+             cycles={} INTE={}{}\n",
+            r.a,
+            r.f,
+            opcodes::flag_names(r.f),
+            pair(r.b, r.c),
+            pair(r.d, r.e),
+            pair(r.h, r.l),
+            r.sp,
+            r.pc,
+            self.cpu.cycles,
+            self.cpu.interrupts as u8,
+            if self.cpu.halted { " halted" } else { "" }
+        )
+    }
+
+    fn set(&mut self, args: &[&str]) -> Result<String, String> {
+        let (name, value) = match args {
+            [name, value] => (name.to_ascii_uppercase(), self.value(value)?),
+            _ => return Err("set needs a name and a value".to_string()),
+        };
+        let byte = || u8::try_from(value).map_err(|_| format!("{} takes a byte", name));
+        let flag = |flag| match value {
+            0 | 1 => Ok((flag, value == 1)),
+            _ => Err(format!("flag {} is 0 or 1", name)),
+        };
+        let r = &mut self.cpu.registers;
+        match name.as_str() {
+            "A" => r.a = byte()?,
+            "F" => r.f = byte()?,
+            "B" => r.b = byte()?,
+            "C" => r.c = byte()?,
+            "D" => r.d = byte()?,
+            "E" => r.e = byte()?,
+            "H" => r.h = byte()?,
+            "L" => r.l = byte()?,
+            "BC" => r.set_dw_reg(0b000, value),
+            "DE" => r.set_dw_reg(0b010, value),
+            "HL" => r.set_dw_reg(0b100, value),
+            "SP" => r.sp = value,
+            "PC" => {
+                r.pc = value;
+                self.cpu.halted = false;
+            }
+            "S" | "Z" | "AC" | "P" | "CY" => {
+                let (flag, set) = flag(match name.as_str() {
+                    "S" => Flag::Sign,
+                    "Z" => Flag::Zero,
+                    "AC" => Flag::ACarry,
+                    "P" => Flag::Parity,
+                    _ => Flag::Carry,
+                })?;
+                r.set_flag(flag, set);
+            }
+            _ => return Err(format!("unknown register or flag `{}`", name)),
+        }
+        Ok(self.registers())
+    }
+
+    fn dump(&mut self, args: &[&str]) -> Result<String, String> {
+        let start = match args.first() {
+            Some(address) => self.value(address)?,
+            None => self.next_dump,
+        };
+        let length = match args.get(1) {
+            Some(length) => self.value(length)?,
+            None => DUMP_BYTES,
+        };
+        let mut out = String::new();
+        let mut row = start;
+        let mut left = length as u32;
+        while left > 0 {
+            let count = left.min(BYTES_PER_ROW as u32) as u16;
+            let bytes: Vec<u8> = (0..count)
+                .map(|i| self.cpu.memory.program[row.wrapping_add(i)])
+                .collect();
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|b| match b {
+                    0x20..=0x7E => *b as char,
+                    _ => '.',
+                })
+                .collect();
+            writeln!(out, "{:04X}  {:<47}  {}", row, hex.join(" "), text).unwrap();
+            row = row.wrapping_add(count);
+            left -= count as u32;
+        }
+        self.next_dump = row;
+        Ok(out)
+    }
+
+    fn edit(&mut self, rest: &str) -> Result<String, String> {
+        let (address, data) = match rest.find(char::is_whitespace) {
+            Some(space) => (self.value(&rest[..space])?, values(&rest[space..])?),
+            None => return Err("edit needs an address and values".to_string()),
+        };
+        self.cpu.memory.load(address, &data);
+        Ok(String::new())
+    }
+
+    fn find(&mut self, rest: &str) -> Result<String, String> {
+        let pattern = values(rest)?;
+        if pattern.is_empty() {
+            return Err("find needs values".to_string());
+        }
+        let memory = self.cpu.memory.read(0, 0xFFFF);
+        let found: Vec<usize> = memory
+            .windows(pattern.len())
+            .enumerate()
+            .filter(|(_, window)| *window == &pattern[..])
+            .map(|(address, _)| address)
+            .collect();
+        let mut out = String::new();
+        for address in found.iter().take(MATCHES_SHOWN) {
+            writeln!(out, "{:04X}", address).unwrap();
+        }
+        match found.len() {
+            0 => writeln!(out, "not found").unwrap(),
+            n if n > MATCHES_SHOWN => writeln!(out, "... {} matches", n).unwrap(),
+            _ => (),
+        }
+        Ok(out)
+    }
+
+    /* Start of an instruction sequence a few instructions before PC that leads to it,
+     * disassembly cannot go backwards on its own.
+     */
+    fn before_pc(&self) -> u16 {
+        let pc = self.cpu.registers.pc;
+        for back in (1..=LIST_BEFORE as u16 * 3).rev() {
+            let start = pc.wrapping_sub(back);
+            let addresses: Vec<u16> = self
+                .lines(start, LIST_BEFORE * 3)
+                .iter()
+                .map(|line| line.address)
+                .take_while(|address| address.wrapping_sub(start) <= back)
+                .collect();
+            if let Some(at) = addresses.iter().position(|address| *address == pc) {
+                return addresses[at.saturating_sub(LIST_BEFORE)];
+            }
+        }
+        pc
+    }
+
+    fn list(&mut self, args: &[&str]) -> Result<String, String> {
+        let start = match args.first() {
+            Some(address) => self.value(address)?,
+            None => self.next_list.unwrap_or_else(|| self.before_pc()),
+        };
+        let count = match args.get(1) {
+            Some(count) => self.value(count)? as usize,
+            None => LIST_LINES,
+        };
+        let mut out = String::new();
+        let lines = self.lines(start, count);
+        for line in &lines {
+            if let Some(name) = self.symbol_at(line.address) {
+                writeln!(out, "{:>21}:", name).unwrap();
+            }
+            let marker = match (
+                line.address == self.cpu.registers.pc,
+                self.breakpoints.contains_key(&line.address),
+            ) {
+                (true, _) => "=>",
+                (_, true) => " *",
+                _ => "  ",
+            };
+            writeln!(out, "{} {}", marker, line).unwrap();
+        }
+        self.next_list = lines
+            .last()
+            .map(|line| line.address.wrapping_add(line.bytes.len() as u16));
+        Ok(out)
+    }
+}
@@ -1,20 +1,626 @@
-use std::fs::File;
-use std::io::prelude::*;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::env;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process;
 
 mod cpu;
+mod debugger;
 mod ext;
 mod modules;
+#[cfg(test)]
+mod tests;
 
 use cpu::Cpu;
-//use modules::memory::Memory;
+use debugger::Debugger;
+use modules::assembler::{
+    self, analysis,
+    dialect::Dialect,
+    disassembler::{self, Line, Syntax},
+    error::Severity,
+    object::Object,
+};
+use modules::image::{self, Format, Image};
+use modules::linker;
+use modules::opcodes::{self, OPCODES};
+use modules::snapshot;
+use modules::trace::{self, Trace};
+use modules::watch::{Trigger, Watchpoint};
 
 fn main() {
-    //    let memory = Memory::new(65536);
-    //    println!("{:?}", memory.get_memory(0, 256))
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("asm") => assemble(&args[1..]),
+        Some("link") => link(&args[1..]),
+        Some("dis") => disassemble(&args[1..]),
+        Some("opcodes") => opcodes(),
+        Some("run") => run(&args[1..]),
+        Some("debug") => debug(&args[1..]),
+        Some("trace-diff") => trace_diff(&args[1..]),
+        _ => run(&args),
+    }
+}
+
+/* [run] [<image>...|--resume <snapshot>] [--zilog] [--strict] [--registers] [--save <snapshot>]
+ *       [--dump <start> <end> <file>]
+ *       [--watch <start> <end> <read|write|change>]...
+ *       [--trace <file> [--trace-fields <fields>] [--trace-range <start> <end>]...]
+ * Loads the images, data.com by default, and runs them from the first start address or from
+ * the lowest address of the first image. An image is an Intel HEX file (.hex), an S-record
+ * file (.s19) or a raw binary, at 0 or at the address of `file@address`; images that overlap
+ * are an error.
+ * With --strict undocumented opcodes are an error instead of aliases of documented ones.
+ * --registers prints the registers after the run, nothing else is printed unless asked for.
+ * --resume goes on from a snapshot instead of loading images, until a HLT. --save writes the
+ * machine to a snapshot after the run.
+ * --dump writes memory from start to end after the run, in the format of the file extension.
+ * --watch stops the run after an instruction that accesses memory from start to end.
+ * --trace writes a line per instruction to the file, - for the terminal, with the fields
+ * pc,bytes,dis,regs,flags,cycles,writes or those of --trace-fields, in Zilog mnemonics with
+ * --zilog.
+ * With --trace-range only instructions from start to end are traced.
+ */
+fn run(args: &[String]) {
+    let mut processor = Cpu::new();
+    let mut specs = Vec::new();
+    let mut dump = None;
+    let mut registers = false;
+    let mut resume = None;
+    let mut save = None;
+    let mut trace = None;
+    let mut fields = trace::DEFAULT_FIELDS.to_string();
+    let mut ranges = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--zilog" => processor.syntax = Syntax::Zilog,
+            "--strict" => processor.strict = true,
+            "--registers" => registers = true,
+            "--resume" => resume = Some(snapshot_file(arg, args.next())),
+            "--save" => save = Some(snapshot_file(arg, args.next())),
+            "--dump" => {
+                let start = address(arg, args.next());
+                let end = address(arg, args.next());
+                let file = args.next().cloned();
+                let file = file.unwrap_or_else(|| fail("--dump needs a file".to_string()));
+                dump = Some((start, end.max(start), file));
+            }
+            "--trace" => {
+                let file = args.next().cloned();
+                trace = Some(file.unwrap_or_else(|| fail("--trace needs a file".to_string())));
+            }
+            "--trace-fields" => {
+                let names = args.next().cloned();
+                fields = names.unwrap_or_else(|| fail("--trace-fields needs fields".to_string()));
+            }
+            "--trace-range" => {
+                let start = address(arg, args.next());
+                let end = address(arg, args.next());
+                ranges.push((start, end.max(start)));
+            }
+            "--watch" => {
+                let start = address(arg, args.next());
+                let end = address(arg, args.next());
+                let kind = args.next().map_or("", String::as_str);
+                let trigger = Trigger::parse(kind).unwrap_or_else(|| {
+                    fail(format!(
+                        "unknown watch kind `{}`, use read, write or change",
+                        kind
+                    ))
+                });
+                processor.watchpoints.push(Watchpoint {
+                    start,
+                    end: end.max(start),
+                    trigger,
+                });
+            }
+            _ if arg.starts_with('-') => fail(format!("unexpected argument `{}`", arg)),
+            _ => specs.push(arg.clone()),
+        }
+    }
+    let end = match resume {
+        Some(file) if specs.is_empty() => {
+            restore(&file, &mut processor);
+            //No address is past the end, the run goes on until a HLT
+            0x10000
+        }
+        Some(_) => fail("--resume takes the place of images".to_string()),
+        None => load(specs, &mut processor),
+    };
+    let fields = trace::Field::parse_list(&fields).unwrap_or_else(|error| fail(error));
+    if let Some(file) = trace {
+        let out: Box<dyn Write> = match file.as_str() {
+            "-" => Box::new(io::stdout()),
+            _ => Box::new(
+                fs::File::create(&file)
+                    .unwrap_or_else(|error| fail(format!("{}: {}", file, error))),
+            ),
+        };
+        let out = Box::new(BufWriter::new(out));
+        let trace = Trace::new(out, fields, ranges, processor.syntax);
+        processor.observe(Box::new(trace));
+    }
+    processor
+        .run(end)
+        .unwrap_or_else(|error| fail(error.to_string()));
+    if registers {
+        println!("{:?}", processor.registers);
+    }
+    if let Some(file) = save {
+        fs::write(&file, snapshot::save(&processor))
+            .unwrap_or_else(|error| fail(format!("{}: {}", file, error)));
+    }
+
+    if let Some((start, end, file)) = dump {
+        let dumped = Image::new(start, processor.memory.read(start, end));
+        fs::write(&file, dumped.to_bytes(Format::of(&file)))
+            .unwrap_or_else(|error| fail(format!("{}: {}", file, error)));
+    }
+}
+
+/* Loads the images, data.com without any, and sets PC to the first start address or to the
+ * lowest address of the first image. Returns the end of the highest image.
+ */
+fn load(mut specs: Vec<String>, processor: &mut Cpu) -> usize {
+    if specs.is_empty() {
+        specs.push("data.com".to_string());
+    }
+    let images: Vec<(String, Image)> = specs
+        .into_iter()
+        .map(|spec| {
+            let loaded = image::read(&spec).unwrap_or_else(|error| fail(error));
+            (spec, loaded)
+        })
+        .collect();
+    let overlaps = image::overlaps(&images);
+    if !overlaps.is_empty() {
+        for overlap in &overlaps {
+            eprintln!("error: {}", overlap);
+        }
+        fail(format!(
+            "{} overlapping region(s), nothing run",
+            overlaps.len()
+        ));
+    }
+    for (_, loaded) in &images {
+        loaded.load(&mut processor.memory);
+    }
+    let start = images.iter().find_map(|(_, loaded)| loaded.start);
+    let first = images[0].1.bounds().map_or(0, |(first, _)| first);
+    let end = images
+        .iter()
+        .filter_map(|(_, loaded)| loaded.bounds())
+        .map(|(_, end)| end)
+        .max()
+        .unwrap_or(0);
+    processor.registers.pc = start.unwrap_or(first);
+    end
+}
+
+/* debug [<image>...|--resume <snapshot>] [--symbols <file>] [--zilog] [--strict]
+ * Loads the images like run and reads debugger commands from the terminal, help lists them.
+ * --symbols reads a symbol table written by asm --symbols for addresses and listings.
+ */
+fn debug(args: &[String]) {
     let mut processor = Cpu::new();
+    let mut specs = Vec::new();
+    let mut symbols = BTreeMap::new();
+    let mut resume = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--zilog" => processor.syntax = Syntax::Zilog,
+            "--strict" => processor.strict = true,
+            "--resume" => resume = Some(snapshot_file(arg, args.next())),
+            "--symbols" => {
+                let file = args.next();
+                let file = file.unwrap_or_else(|| fail("--symbols needs a file".to_string()));
+                let text = fs::read_to_string(file)
+                    .unwrap_or_else(|error| fail(format!("{}: {}", file, error)));
+                symbols = debugger::parse_symbols(&text);
+            }
+            _ if arg.starts_with('-') => fail(format!("unexpected argument `{}`", arg)),
+            _ => specs.push(arg.clone()),
+        }
+    }
+    match resume {
+        Some(file) if specs.is_empty() => restore(&file, &mut processor),
+        Some(_) => fail("--resume takes the place of images".to_string()),
+        None => {
+            load(specs, &mut processor);
+        }
+    }
+    Debugger::new(processor, symbols).repl();
+}
+
+fn snapshot_file(option: &str, file: Option<&String>) -> String {
+    let file = file.cloned();
+    file.unwrap_or_else(|| fail(format!("{} needs a snapshot file", option)))
+}
+
+fn restore(file: &str, processor: &mut Cpu) {
+    let bytes = fs::read(file).unwrap_or_else(|error| fail(format!("{}: {}", file, error)));
+    snapshot::load(processor, &bytes).unwrap_or_else(|error| fail(format!("{}: {}", file, error)));
+}
+
+/* trace-diff <first> <second> [--context <lines>]
+ * Compares two traces of run --trace line by line and shows the first instruction where they
+ * differ, after the lines before it, and which registers, flags or memory writes differ.
+ * Exits with 1 when they differ.
+ */
+fn trace_diff(args: &[String]) {
+    let mut files = Vec::new();
+    let mut context = 5;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--context" => {
+                let lines = args.next().and_then(|lines| lines.parse().ok());
+                context = lines.unwrap_or_else(|| fail("--context needs a number".to_string()));
+            }
+            _ if arg.starts_with('-') => fail(format!("unexpected argument `{}`", arg)),
+            _ => files.push(arg.clone()),
+        }
+    }
+    let traces: Vec<BufReader<fs::File>> = match &files[..] {
+        [_, _] => files
+            .iter()
+            .map(|file| {
+                let opened = fs::File::open(file);
+                BufReader::new(opened.unwrap_or_else(|error| fail(format!("{}: {}", file, error))))
+            })
+            .collect(),
+        _ => fail("trace-diff needs two traces".to_string()),
+    };
+    let mut traces = traces.into_iter();
+    let (first, second) = (traces.next().unwrap(), traces.next().unwrap());
+    match trace::diff(first, second, context) {
+        Ok(None) => println!("the traces are the same"),
+        Ok(Some(report)) => {
+            print!("{}", report);
+            process::exit(1)
+        }
+        Err(error) => fail(error.to_string()),
+    }
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1)
+}
+
+//NAME or NAME=VALUE, a bare name is defined as 1
+fn define(definition: &str) -> (String, i64) {
+    let mut parts = definition.splitn(2, '=');
+    let name = parts.next().unwrap_or("").to_string();
+    let value = match parts.next() {
+        Some(value) => assembler::expr::parse_number(value)
+            .unwrap_or_else(|error| fail(format!("-D {}: {}", definition, error))),
+        None => 1,
+    };
+    (name, value)
+}
+
+//asm <source> [-c | --hex] [-o <output>] [-D NAME[=VALUE]]... [--dialect intel|m80|asm|zilog]
+//    [--symbols <file>] [--listing <file>]
+//With -c the output is a relocatable object module instead of a flat binary, --hex writes Intel HEX
+fn assemble(args: &[String]) {
+    let mut source = None;
+    let mut output = None;
+    let mut symbols = None;
+    let mut listing = None;
+    let mut object = false;
+    let mut hex = false;
+    let mut options = assembler::Options::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next().cloned(),
+            "-c" => object = true,
+            "--hex" => hex = true,
+            "--dialect" => {
+                let name = args.next().map_or("", String::as_str);
+                options.dialect = Dialect::parse(name).unwrap_or_else(|| {
+                    fail(format!(
+                        "unknown dialect `{}`, use intel, m80, asm or zilog",
+                        name
+                    ))
+                });
+            }
+            "--symbols" => symbols = args.next().cloned(),
+            "-l" | "--listing" => listing = args.next().cloned(),
+            "-D" => match args.next() {
+                Some(definition) => options.defines.push(define(definition)),
+                None => fail("-D needs a symbol".to_string()),
+            },
+            _ if arg.starts_with("-D") => options.defines.push(define(&arg[2..])),
+            _ if source.is_none() => source = Some(arg.clone()),
+            _ => fail(format!("unexpected argument `{}`", arg)),
+        }
+    }
+    let source = source.unwrap_or_else(|| {
+        fail(
+            "usage: asm <source> [-c | --hex] [-o <output>] [-D NAME[=VALUE]]... \
+             [--dialect intel|m80|asm|zilog] [--symbols <file>] [--listing <file>]"
+                .to_string(),
+        )
+    });
+    let output = output.unwrap_or_else(|| {
+        Path::new(&source)
+            .with_extension(match (object, hex) {
+                (true, _) => "obj",
+                (_, true) => "hex",
+                _ => "com",
+            })
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let assembly =
+        assembler::assemble_file(Path::new(&source), &options).unwrap_or_else(|errors| {
+            for error in &errors {
+                eprintln!("{}\n", error);
+            }
+            let count = errors
+                .iter()
+                .filter(|error| error.severity == Severity::Error)
+                .count();
+            fail(format!("{} error(s), nothing written", count))
+        });
+    for warning in &assembly.warnings {
+        eprintln!("{}\n", warning);
+    }
+    if object {
+        let name = Path::new(&source).file_stem().map_or_else(
+            || source.clone(),
+            |stem| stem.to_string_lossy().into_owned(),
+        );
+        fs::write(&output, assembly.to_object(&name).to_text())
+            .unwrap_or_else(|error| fail(format!("{}: {}", output, error)));
+    } else if assembly.is_relocatable() {
+        fail(format!(
+            "{} has relocatable segments or external symbols, assemble it with -c and link it",
+            source
+        ));
+    } else if hex {
+        let image = Image {
+            segments: assembly.segments.clone(),
+            start: assembly.entry.map(|(_, address)| address),
+        };
+        fs::write(&output, image.to_bytes(Format::IntelHex))
+            .unwrap_or_else(|error| fail(format!("{}: {}", output, error)));
+    } else {
+        fs::write(&output, assembly.to_binary())
+            .unwrap_or_else(|error| fail(format!("{}: {}", output, error)));
+    }
+    if let Some(symbols) = symbols {
+        fs::write(&symbols, assembly.symbol_table())
+            .unwrap_or_else(|error| fail(format!("{}: {}", symbols, error)));
+    }
+    if let Some(listing) = listing {
+        fs::write(&listing, assembler::listing::render(&assembly))
+            .unwrap_or_else(|error| fail(format!("{}: {}", listing, error)));
+    }
+}
+
+fn address(option: &str, value: Option<&String>) -> u16 {
+    let value = value.unwrap_or_else(|| fail(format!("{} needs an address", option)));
+    assembler::expr::parse_number(value)
+        .ok()
+        .and_then(|address| u16::try_from(address).ok())
+        .unwrap_or_else(|| fail(format!("{}: invalid address `{}`", option, value)))
+}
+
+//link <object>... [-o <output>] [--map <file>] [--code <address>] [--data <address>]
+fn link(args: &[String]) {
+    let mut objects = Vec::new();
+    let mut output = None;
+    let mut map = None;
+    let mut options = linker::LinkOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next().cloned(),
+            "--map" => map = args.next().cloned(),
+            "--code" => options.code = address(arg, args.next()),
+            "--data" => options.data = Some(address(arg, args.next())),
+            _ if arg.starts_with('-') => fail(format!("unexpected argument `{}`", arg)),
+            _ => objects.push(arg.clone()),
+        }
+    }
+    if objects.is_empty() {
+        fail(
+            "usage: link <object>... [-o <output>] [--map <file>] [--code <address>] \
+             [--data <address>]"
+                .to_string(),
+        );
+    }
+    let output = output.unwrap_or_else(|| {
+        Path::new(&objects[0])
+            .with_extension("com")
+            .to_string_lossy()
+            .into_owned()
+    });
+
+    let objects: Vec<Object> = objects
+        .iter()
+        .map(|path| {
+            let text = fs::read_to_string(path)
+                .unwrap_or_else(|error| fail(format!("{}: {}", path, error)));
+            Object::parse(&text).unwrap_or_else(|error| fail(format!("{}: {}", path, error)))
+        })
+        .collect();
+    let image = linker::link(&objects, &options).unwrap_or_else(|errors| {
+        for error in &errors {
+            eprintln!("error: {}", error);
+        }
+        fail(format!("{} error(s), nothing written", errors.len()))
+    });
+    fs::write(&output, image.to_binary())
+        .unwrap_or_else(|error| fail(format!("{}: {}", output, error)));
+    if let Some(map) = map {
+        fs::write(&map, image.map()).unwrap_or_else(|error| fail(format!("{}: {}", map, error)));
+    }
+}
+
+/* dis <binary> [--origin <address>] [--start <address>] [--end <address>] [--zilog]
+ *     [--recursive] [--entry <address>]... [--vectors] [--source] [--xref <file>]
+ *     [--dot <file>] [-o <output>]
+ * The binary is loaded at the origin, 100H by default, and disassembled from start to end.
+ * With --recursive or --entry only code reached from the entry points is disassembled,
+ * --vectors adds the RST vectors as entry points for interrupt handlers.
+ * --source writes the whole image as source that asm turns back into the same binary.
+ * --xref writes the cross-reference of the traced code, --dot its call graph for Graphviz.
+ */
+fn disassemble(args: &[String]) {
+    let mut binary = None;
+    let mut output = None;
+    let mut origin = 0x100;
+    let mut start = None;
+    let mut end = None;
+    let mut syntax = Syntax::Intel;
+    let mut recursive = false;
+    let mut source = false;
+    let mut xref = None;
+    let mut dot = None;
+    let mut entries = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = args.next().cloned(),
+            "--origin" => origin = address(arg, args.next()),
+            "--start" => start = Some(address(arg, args.next())),
+            "--end" => end = Some(address(arg, args.next())),
+            "--zilog" => syntax = Syntax::Zilog,
+            "--recursive" => recursive = true,
+            "--entry" => {
+                recursive = true;
+                entries.push(address(arg, args.next()));
+            }
+            "--vectors" => {
+                recursive = true;
+                entries.extend_from_slice(&analysis::VECTORS);
+            }
+            "--xref" => {
+                recursive = true;
+                xref = args.next().cloned();
+            }
+            "--dot" => {
+                recursive = true;
+                dot = args.next().cloned();
+            }
+            "--source" => {
+                recursive = true;
+                source = true;
+            }
+            _ if arg.starts_with('-') => fail(format!("unexpected argument `{}`", arg)),
+            _ if binary.is_none() => binary = Some(arg.clone()),
+            _ => fail(format!("unexpected argument `{}`", arg)),
+        }
+    }
+    let binary = binary.unwrap_or_else(|| {
+        fail(
+            "usage: dis <binary> [--origin <address>] [--start <address>] [--end <address>] \
+             [--zilog] [--recursive] [--entry <address>]... [--vectors] [--source] \
+             [--xref <file>] [--dot <file>] [-o <output>]"
+                .to_string(),
+        )
+    });
+    if source && (start.is_some() || end.is_some()) {
+        fail("--source disassembles the whole binary, leave out --start and --end".to_string());
+    }
+    let data = fs::read(&binary).unwrap_or_else(|error| fail(format!("{}: {}", binary, error)));
+
+    //Offsets into the binary, instructions that start at the end address are included
+    let last = origin as usize + data.len();
+    let offset = |address: u16| {
+        let address = address as usize;
+        if address < origin as usize || address > last {
+            fail(format!(
+                "{:04X}H is outside of {} loaded at {:04X}H",
+                address, binary, origin
+            ));
+        }
+        address - origin as usize
+    };
+    let from = start.map_or(0, offset);
+    let to = end.map_or(data.len(), offset);
+    let in_range =
+        |line: &Line| (from..=to).contains(&(line.address.wrapping_sub(origin) as usize));
+    let text = if recursive {
+        let mut analysis = analysis::analyze(&data, origin, &entries, syntax);
+        for address in &analysis.unresolved {
+            eprintln!(
+                "warning: PCHL at {:04X}H jumps to an unknown address",
+                address
+            );
+        }
+        for (path, text) in [
+            (&xref, analysis.cross_reference()),
+            (&dot, analysis.call_graph()),
+        ] {
+            if let Some(path) = path {
+                fs::write(path, text).unwrap_or_else(|error| fail(format!("{}: {}", path, error)));
+            }
+        }
+        if source {
+            analysis.source()
+        } else {
+            analysis.lines.retain(in_range);
+            analysis.listing()
+        }
+    } else {
+        disassembler::disassemble(&data[from..], origin.wrapping_add(from as u16), syntax)
+            .take_while(in_range)
+            .map(|line| format!("{}\n", line))
+            .collect()
+    };
+    match output {
+        Some(output) => {
+            fs::write(&output, text).unwrap_or_else(|error| fail(format!("{}: {}", output, error)))
+        }
+        None => print!("{}", text),
+    }
+}
 
-    let mut file = File::open("data.com").unwrap();
-    let mut data: Vec<u8> = Vec::new();
-    file.read_to_end(&mut data).unwrap();
-    processor.execute(data);
+//opcodes, prints the opcode table: length, cycles, flags affected and category
+fn opcodes() {
+    for (value, opcode) in OPCODES.iter().enumerate() {
+        let operands: Vec<String> = opcode
+            .operands
+            .iter()
+            .map(|operand| match operand {
+                opcodes::Operand::Register(name) | opcodes::Operand::Pair(name) => name.to_string(),
+                opcodes::Operand::Data8 => "d8".to_string(),
+                opcodes::Operand::Data16 => "d16".to_string(),
+                opcodes::Operand::Address => "a16".to_string(),
+                opcodes::Operand::Port => "port".to_string(),
+                opcodes::Operand::Vector(number) => number.to_string(),
+            })
+            .collect();
+        let (taken, not_taken) = opcode.cycles;
+        let cycles = if taken == not_taken {
+            taken.to_string()
+        } else {
+            format!("{}/{}", taken, not_taken)
+        };
+        let line = format!(
+            "{:02X}  {:<4} {:<8} {}  {:>5}  {}  {:<10} {}",
+            value,
+            opcode.mnemonic,
+            operands.join(","),
+            opcode.length,
+            cycles,
+            opcodes::flag_names(opcode.flags),
+            opcode.category.name(),
+            if opcode.documented {
+                ""
+            } else {
+                "undocumented"
+            }
+        );
+        println!("{}", line.trim_end());
+    }
 }
@@ -0,0 +1,427 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt::Write,
+};
+
+use super::{
+    disassembler::{self, Line, Syntax},
+    instruction::{self, hex},
+};
+
+//Targets of RST, entry points of the interrupt handlers; 0 is also the reset address
+pub const VECTORS: [u16; 8] = [0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38];
+//Printable characters in a row that are taken for a string
+const MIN_STRING: usize = 4;
+const CHARS_PER_LINE: usize = 32;
+const BYTES_PER_LINE: usize = 8;
+
+//How an instruction refers to an address or I/O port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Access {
+    Call,
+    Jump,
+    Read,
+    Write,
+    //LXI, the address is loaded into a register pair
+    Address,
+    Input,
+    Output,
+}
+
+impl Access {
+    pub fn name(self) -> &'static str {
+        match self {
+            Access::Call => "call",
+            Access::Jump => "jump",
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Address => "address",
+            Access::Input => "input",
+            Access::Output => "output",
+        }
+    }
+
+    fn is_port(self) -> bool {
+        self == Access::Input || self == Access::Output
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Reference {
+    //Address of the instruction
+    pub from: u16,
+    //Address or port referred to
+    pub to: u16,
+    pub access: Access,
+}
+
+//Code and data of an image, as found by following the control flow from its entry points
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    pub origin: u16,
+    pub lines: Vec<Line>,
+    //Label of every entry point and branch target that starts a line
+    pub labels: BTreeMap<u16, String>,
+    //Addresses of PCHL instructions, where the trace could not follow
+    pub unresolved: Vec<u16>,
+    //References of the traced instructions, in address order
+    pub references: Vec<Reference>,
+    //Routines by entry point, with the routines they call or jump to
+    pub routines: BTreeMap<u16, BTreeSet<(u16, Access)>>,
+}
+
+/* Where execution can go after the instruction at the start of `bytes`:
+ * the target of a jump, call or restart and whether the next instruction follows.
+ */
+fn flow(bytes: &[u8]) -> (Option<u16>, bool) {
+    let opcode = bytes[0];
+    let word = || u16::from_le_bytes([bytes[1], bytes[2]]);
+    match opcode {
+        0xC3 => (Some(word()), false),                      //JMP
+        0xC9 | 0xE9 => (None, false),                       //RET, PCHL
+        0xCD => (Some(word()), true),                       //CALL
+        _ if opcode & 0xC7 == 0xC2 => (Some(word()), true), //Jcc
+        _ if opcode & 0xC7 == 0xC4 => (Some(word()), true), //Ccc
+        _ if opcode & 0xC7 == 0xC7 => (Some((opcode & 0x38) as u16), true), //RST
+        _ => (None, true),
+    }
+}
+
+//Address or port the instruction at the start of `bytes` refers to
+fn reference(from: u16, bytes: &[u8]) -> Option<Reference> {
+    let opcode = bytes[0];
+    let word = || u16::from_le_bytes([bytes[1], bytes[2]]);
+    let (to, access) = match opcode {
+        0xCD => (word(), Access::Call),
+        _ if opcode & 0xC7 == 0xC4 => (word(), Access::Call),
+        _ if opcode & 0xC7 == 0xC7 => ((opcode & 0x38) as u16, Access::Call),
+        0xC3 => (word(), Access::Jump),
+        _ if opcode & 0xC7 == 0xC2 => (word(), Access::Jump),
+        0x3A | 0x2A => (word(), Access::Read),  //LDA, LHLD
+        0x32 | 0x22 => (word(), Access::Write), //STA, SHLD
+        _ if opcode & 0xCF == 0x01 => (word(), Access::Address), //LXI
+        0xDB => (bytes[1] as u16, Access::Input),
+        0xD3 => (bytes[1] as u16, Access::Output),
+        _ => return None,
+    };
+    Some(Reference { from, to, access })
+}
+
+//Printable ASCII, without the quote that would end the string
+fn is_text(byte: u8) -> bool {
+    (0x20..0x7F).contains(&byte) && byte != b'\''
+}
+
+fn text_length(bytes: &[u8]) -> usize {
+    bytes.iter().take_while(|byte| is_text(**byte)).count()
+}
+
+//Label of the word at the start of `bytes`, for tables of addresses
+fn word_label<'a>(bytes: &[u8], labels: &'a BTreeMap<u16, String>) -> Option<&'a String> {
+    match bytes {
+        [low, high, ..] => labels.get(&u16::from_le_bytes([*low, *high])),
+        _ => None,
+    }
+}
+
+//DB and DW lines of a data region, with runs of printable characters as strings
+fn data_lines(address: u16, bytes: &[u8], labels: &BTreeMap<u16, String>, lines: &mut Vec<Line>) {
+    let mut i = 0;
+    while i < bytes.len() {
+        let address = address.wrapping_add(i as u16);
+        if let Some(label) = word_label(&bytes[i..], labels) {
+            lines.push(Line {
+                address,
+                bytes: bytes[i..i + 2].to_vec(),
+                text: format!("DW {}", label),
+            });
+            i += 2;
+            continue;
+        }
+        let text = text_length(&bytes[i..]);
+        if text >= MIN_STRING {
+            let length = text.min(CHARS_PER_LINE);
+            let string = String::from_utf8_lossy(&bytes[i..i + length]);
+            lines.push(Line {
+                address,
+                bytes: bytes[i..i + length].to_vec(),
+                text: format!("DB '{}'", string),
+            });
+            i += length;
+            continue;
+        }
+        let mut length = 1;
+        while i + length < bytes.len()
+            && length < BYTES_PER_LINE
+            && text_length(&bytes[i + length..]) < MIN_STRING
+            && word_label(&bytes[i + length..], labels).is_none()
+        {
+            length += 1;
+        }
+        lines.push(disassembler::data(address, &bytes[i..i + length]));
+        i += length;
+    }
+}
+
+/* Trace `bytes` loaded at `origin` from the reset address and from `entries`, or from the
+ * origin when none of them is inside the image. Bytes no instruction was reached at become data.
+ */
+pub fn analyze(bytes: &[u8], origin: u16, entries: &[u16], syntax: Syntax) -> Analysis {
+    let offset = |address: u16| {
+        Some(address.wrapping_sub(origin) as usize).filter(|offset| *offset < bytes.len())
+    };
+    //Reset first, a wrong entry point cannot take its bytes away
+    let mut pending: VecDeque<u16> = [0]
+        .iter()
+        .chain(entries)
+        .copied()
+        .filter(|address| offset(*address).is_some())
+        .collect();
+    if pending.is_empty() && !bytes.is_empty() {
+        pending.push_back(origin);
+    }
+    let mut targets: BTreeSet<u16> = pending.iter().copied().collect();
+    let entries: Vec<u16> = pending.iter().copied().collect();
+
+    //Length of the instruction at each offset reached
+    let mut starts = BTreeMap::new();
+    let mut code = vec![false; bytes.len()];
+    let mut unresolved = Vec::new();
+    while let Some(address) = pending.pop_front() {
+        let start = match offset(address) {
+            Some(start) => start,
+            None => continue,
+        };
+        let decoded = match instruction::decode(&bytes[start..]) {
+            Some(decoded) => decoded,
+            None => continue,
+        };
+        let range = start..start + decoded.length;
+        if code[range.clone()].iter().any(|code| *code) {
+            continue;
+        }
+        code[range].iter_mut().for_each(|code| *code = true);
+        starts.insert(start, decoded.length);
+        if bytes[start] == 0xE9 {
+            unresolved.push(address);
+        }
+        let (target, next) = flow(&bytes[start..]);
+        if let Some(target) = target {
+            targets.insert(target);
+            pending.push_back(target);
+        }
+        if next {
+            pending.push_back(address.wrapping_add(decoded.length as u16));
+        }
+    }
+    unresolved.sort_unstable();
+
+    let code_at = |address: u16| offset(address).filter(|start| starts.contains_key(start));
+    let references: Vec<Reference> = starts
+        .iter()
+        .filter_map(|(start, length)| {
+            reference(
+                origin.wrapping_add(*start as u16),
+                &bytes[*start..start + length],
+            )
+        })
+        .collect();
+
+    //Routines start at the entry points and call targets, their bodies end at other routines
+    let mut routines: BTreeMap<u16, BTreeSet<(u16, Access)>> = entries
+        .into_iter()
+        .chain(
+            references
+                .iter()
+                .filter(|reference| reference.access == Access::Call)
+                .map(|reference| reference.to),
+        )
+        .filter(|address| code_at(*address).is_some())
+        .map(|address| (address, BTreeSet::new()))
+        .collect();
+    let heads: BTreeSet<u16> = routines.keys().copied().collect();
+    for (head, calls) in routines.iter_mut() {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![*head];
+        while let Some(address) = pending.pop() {
+            let start = match code_at(address) {
+                Some(start) if seen.insert(address) => start,
+                _ => continue,
+            };
+            let slice = &bytes[start..start + starts[&start]];
+            let next = address.wrapping_add(slice.len() as u16);
+            if flow(slice).1 && !heads.contains(&next) {
+                pending.push(next);
+            }
+            match reference(address, slice) {
+                Some(Reference { to, access, .. })
+                    if access == Access::Call && heads.contains(&to) =>
+                {
+                    calls.insert((to, access));
+                }
+                Some(Reference { to, access, .. }) if access == Access::Jump => {
+                    if heads.contains(&to) && to != *head {
+                        calls.insert((to, access));
+                    } else {
+                        pending.push(to);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    //Targets in the middle of an instruction keep their address
+    let labels: BTreeMap<u16, String> = targets
+        .into_iter()
+        .filter(|target| match offset(*target) {
+            Some(offset) => starts.contains_key(&offset) || !code[offset],
+            None => false,
+        })
+        .map(|target| (target, format!("L{:04X}", target)))
+        .collect();
+
+    let mut lines = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() {
+        let address = origin.wrapping_add(start as u16);
+        if let Some(length) = starts.get(&start) {
+            let slice = &bytes[start..start + length];
+            let mut decoded = instruction::decode(slice).expect("traced instruction");
+            match flow(slice) {
+                (Some(target), _) if bytes[start] & 0xC7 != 0xC7 => {
+                    if let Some(label) = labels.get(&target) {
+                        decoded.operands[0] = label.clone();
+                    }
+                }
+                _ => (),
+            }
+            lines.push(Line {
+                address,
+                bytes: slice.to_vec(),
+                text: disassembler::text(&decoded, syntax).expect("documented instruction"),
+            });
+            start += length;
+            continue;
+        }
+        //Data runs up to the next instruction or label
+        let mut end = start + 1;
+        while end < bytes.len()
+            && !code[end]
+            && !labels.contains_key(&origin.wrapping_add(end as u16))
+        {
+            end += 1;
+        }
+        data_lines(address, &bytes[start..end], &labels, &mut lines);
+        start = end;
+    }
+    Analysis {
+        origin,
+        lines,
+        labels,
+        unresolved,
+        references,
+        routines,
+    }
+}
+
+impl Analysis {
+    //Lines as `dis` prints them, labels on lines of their own
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.address) {
+                writeln!(out, "{:19}{}:", "", label).unwrap();
+            }
+            writeln!(out, "{}", line).unwrap();
+        }
+        out
+    }
+
+    /* References sorted by the address referred to, with the instructions that refer to it,
+     * I/O ports follow the memory addresses.
+     */
+    pub fn cross_reference(&self) -> String {
+        let mut references = self.references.clone();
+        references.sort_by_key(|reference| {
+            (
+                reference.access.is_port(),
+                reference.to,
+                reference.access,
+                reference.from,
+            )
+        });
+        let mut out = String::new();
+        for group in references.chunk_by(|a, b| (a.to, a.access) == (b.to, b.access)) {
+            let Reference { to, access, .. } = group[0];
+            let (target, label) = if access.is_port() {
+                (format!("{:02X}H", to), "port")
+            } else {
+                (
+                    format!("{:04X}H", to),
+                    self.labels.get(&to).map_or("", String::as_str),
+                )
+            };
+            let sources: Vec<String> = group
+                .iter()
+                .map(|reference| format!("{:04X}H", reference.from))
+                .collect();
+            writeln!(
+                out,
+                "{:>5}  {:<6} {:<8} {}",
+                target,
+                label,
+                access.name(),
+                sources.join(" ")
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    //Call graph in Graphviz DOT, jumps from one routine into another are dashed
+    pub fn call_graph(&self) -> String {
+        let name = |address: u16| {
+            self.labels
+                .get(&address)
+                .cloned()
+                .unwrap_or_else(|| format!("L{:04X}", address))
+        };
+        let mut out = String::from("digraph calls {\n    node [shape=box];\n");
+        for routine in self.routines.keys() {
+            writeln!(out, "    {};", name(*routine)).unwrap();
+        }
+        for (routine, calls) in &self.routines {
+            for (to, access) in calls {
+                let style = if *access == Access::Jump {
+                    " [style=dashed]"
+                } else {
+                    ""
+                };
+                writeln!(out, "    {} -> {}{};", name(*routine), name(*to), style).unwrap();
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /* Source the assembler turns back into the same bytes, in the syntax of the analysis:
+     * Zilog sources are assembled with --dialect zilog.
+     */
+    pub fn source(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "\tORG\t{}", hex(self.origin, 4)).unwrap();
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.address) {
+                write!(out, "{}:", label).unwrap();
+            }
+            match line.text.split_once(' ') {
+                Some((operation, operands)) => writeln!(out, "\t{}\t{}", operation, operands),
+                None => writeln!(out, "\t{}", line.text),
+            }
+            .unwrap();
+        }
+        writeln!(out, "\tEND").unwrap();
+        out
+    }
+}
@@ -0,0 +1,147 @@
+use super::{expr::is_identifier_char, source::find_directive};
+
+/* Source dialects besides the Intel one.
+ * M80: TITLE, SUBTTL, PAGE, .8080 and friends are ignored, DEFB/DEFW/DEFS/DEFM stand for
+ * DB/DW/DS, EXTRN and EXT for EXTERN, ENTRY and GLOBAL for PUBLIC. `LABEL::` makes a label
+ * public and `NAME##` declares an external symbol where it is used.
+ * Digital Research ASM: TITLE and PAGE are ignored, `*` in the first column starts a
+ * comment and `$` between letters or digits is a separator that is dropped, so `0FFFF$H`
+ * and `BUF$LEN` read as `0FFFFH` and `BUFLEN`.
+ * Zilog: Z80 mnemonics for the 8080 subset, `LD A,(HL)` and `JP NZ,LOOP`, see `zilog`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Dialect {
+    #[default]
+    Intel,
+    M80,
+    DigitalResearch,
+    Zilog,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alias {
+    Directive(&'static str),
+    //Listing and processor directives without an effect on the output
+    Ignored,
+}
+
+const M80_ALIASES: [(&str, Alias); 19] = [
+    ("DEFB", Alias::Directive("DB")),
+    ("DEFM", Alias::Directive("DB")),
+    ("DEFW", Alias::Directive("DW")),
+    ("DEFS", Alias::Directive("DS")),
+    ("EXTRN", Alias::Directive("EXTERN")),
+    ("EXT", Alias::Directive("EXTERN")),
+    ("ENTRY", Alias::Directive("PUBLIC")),
+    ("GLOBAL", Alias::Directive("PUBLIC")),
+    ("TITLE", Alias::Ignored),
+    ("SUBTTL", Alias::Ignored),
+    ("PAGE", Alias::Ignored),
+    ("NAME", Alias::Ignored),
+    ("EJECT", Alias::Ignored),
+    (".8080", Alias::Ignored),
+    (".LIST", Alias::Ignored),
+    (".XLIST", Alias::Ignored),
+    (".LALL", Alias::Ignored),
+    (".SALL", Alias::Ignored),
+    (".XALL", Alias::Ignored),
+];
+
+const M80_TEXT: [&str; 3] = ["TITLE", "SUBTTL", "NAME"];
+const DR_TEXT: [&str; 1] = ["TITLE"];
+
+const DR_ALIASES: [(&str, Alias); 2] = [("TITLE", Alias::Ignored), ("PAGE", Alias::Ignored)];
+
+impl Dialect {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "intel" => Some(Dialect::Intel),
+            "m80" => Some(Dialect::M80),
+            "asm" | "dr" => Some(Dialect::DigitalResearch),
+            "zilog" | "z80" => Some(Dialect::Zilog),
+            _ => None,
+        }
+    }
+
+    pub fn alias(self, name: &str) -> Option<Alias> {
+        let aliases: &[(&str, Alias)] = match self {
+            Dialect::Intel | Dialect::Zilog => &[],
+            Dialect::M80 => &M80_ALIASES,
+            Dialect::DigitalResearch => &DR_ALIASES,
+        };
+        aliases
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map(|(_, alias)| *alias)
+    }
+
+    //Directives whose operand is free text, the whole line is skipped
+    pub fn ignores(self, text: &str) -> bool {
+        let directives: &[&'static str] = match self {
+            Dialect::Intel | Dialect::Zilog => &[],
+            Dialect::M80 => &M80_TEXT,
+            Dialect::DigitalResearch => &DR_TEXT,
+        };
+        find_directive(text, directives).is_some()
+    }
+
+    pub fn public_labels(self) -> bool {
+        self == Dialect::M80
+    }
+
+    /* Rewrite a line into the Intel syntax, with the external names it declares.
+     * Quoted strings and comments are left alone.
+     */
+    pub fn normalize(self, text: &str) -> (String, Vec<String>) {
+        let mut externals = Vec::new();
+        if self == Dialect::Intel || self == Dialect::Zilog {
+            return (text.to_string(), externals);
+        }
+        if self == Dialect::DigitalResearch && text.starts_with('*') {
+            return (format!(";{}", &text[1..]), externals);
+        }
+        let chars: Vec<char> = text.chars().collect();
+        let mut result = String::new();
+        let mut quote = None;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match quote {
+                Some(q) if q == c => quote = None,
+                Some(_) => (),
+                None if c == ';' => {
+                    result.extend(&chars[i..]);
+                    break;
+                }
+                None if c == '\'' || c == '"' => quote = Some(c),
+                None => {
+                    let separator = i > 0
+                        && chars[i - 1].is_ascii_alphanumeric()
+                        && chars.get(i + 1).is_some_and(|c| c.is_ascii_alphanumeric());
+                    if self == Dialect::DigitalResearch && c == '$' && separator {
+                        i += 1;
+                        continue;
+                    }
+                    if self == Dialect::M80 && c == '#' && chars.get(i + 1) == Some(&'#') {
+                        let name: String = result
+                            .chars()
+                            .rev()
+                            .take_while(|c| is_identifier_char(*c))
+                            .collect::<Vec<char>>()
+                            .into_iter()
+                            .rev()
+                            .collect();
+                        if !name.is_empty() {
+                            externals.push(name.to_ascii_uppercase());
+                        }
+                        i += 2;
+                        continue;
+                    }
+                }
+            }
+            result.push(c);
+            i += 1;
+        }
+        (result, externals)
+    }
+}
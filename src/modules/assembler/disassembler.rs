@@ -0,0 +1,85 @@
+use std::fmt;
+
+use super::{
+    instruction::{self, hex, Decoded},
+    zilog,
+};
+
+//Mnemonics instructions are written with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Syntax {
+    Intel,
+    Zilog,
+}
+
+/* Text and length of the instruction at the start of `bytes`, both syntaxes come from the
+ * opcode table. None for undocumented opcodes and cut off instructions.
+ */
+pub fn instruction(bytes: &[u8], syntax: Syntax) -> Option<(String, usize)> {
+    let decoded = instruction::decode(bytes)?;
+    Some((text(&decoded, syntax)?, decoded.length))
+}
+
+pub(super) fn text(decoded: &Decoded, syntax: Syntax) -> Option<String> {
+    let text = match syntax {
+        Syntax::Intel => format!("{} {}", decoded.mnemonic, decoded.operands.join(",")),
+        Syntax::Zilog => zilog::render(decoded)?,
+    };
+    Some(text.trim_end().to_string())
+}
+
+const BYTES_SHOWN: usize = 4;
+
+//One disassembled instruction, or a DB for a byte that does not start one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        //Data lines can be longer, their bytes are all in the text
+        let shown = &self.bytes[..self.bytes.len().min(BYTES_SHOWN)];
+        let bytes: Vec<String> = shown.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(
+            fmt,
+            "{:04X}  {:<11}  {}",
+            self.address,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+//DB line of the bytes in hexadecimal
+pub(super) fn data(address: u16, bytes: &[u8]) -> Line {
+    let values: Vec<String> = bytes.iter().map(|b| hex(*b as u16, 2)).collect();
+    Line {
+        address,
+        bytes: bytes.to_vec(),
+        text: format!("DB {}", values.join(",")),
+    }
+}
+
+/* Disassemble `bytes` loaded at `origin`, one line per instruction. Undocumented opcodes and
+ * an instruction cut off by the end of `bytes` become DB lines of one byte.
+ */
+pub fn disassemble(bytes: &[u8], origin: u16, syntax: Syntax) -> impl Iterator<Item = Line> + '_ {
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let rest = bytes.get(offset..).filter(|rest| !rest.is_empty())?;
+        let address = origin.wrapping_add(offset as u16);
+        let line = match instruction(rest, syntax) {
+            Some((text, length)) => Line {
+                address,
+                bytes: rest[..length].to_vec(),
+                text,
+            },
+            None => data(address, &rest[..1]),
+        };
+        offset += line.bytes.len();
+        Some(line)
+    })
+}
@@ -0,0 +1,334 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    Syntax(String),
+    InvalidNumber(String),
+    InvalidString,
+    UnknownMnemonic(String),
+    OperandCount {
+        mnemonic: String,
+        expected: usize,
+        found: usize,
+    },
+    InvalidRegister(String),
+    InvalidRegisterPair(String),
+    MissingLabel(String),
+    UndefinedSymbol(String),
+    UndefinedLocal(String, String),
+    DuplicateSymbol(String),
+    ForwardReference(String),
+    DivisionByZero,
+    NegativeShift(i64),
+    ByteRange(i64),
+    WordRange(i64),
+    RstRange(i64),
+    RstAddress(i64),
+    NotIntel8080(String),
+    NegativeSize(i64),
+    AddressOverflow,
+    UnterminatedBlock(String, &'static str),
+    UnexpectedDirective(String),
+    MacroArguments {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    ExpansionTooDeep(String),
+    FileRead(String, String),
+    IncludeCycle(String),
+    Relocation,
+    RelocatableByte,
+    UndefinedPublic(String),
+    //Warnings
+    Truncated(i64),
+    //Instruction after the one that ends the flow
+    Unreachable(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+//Characters of a line a diagnostic is about, `column` is 1-based
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub column: usize,
+    pub length: usize,
+}
+
+//Error kind with the part of the line it is about, when that is known
+#[derive(Debug, Clone, PartialEq)]
+pub struct Located {
+    pub kind: ErrorKind,
+    pub span: Option<Span>,
+}
+
+impl From<ErrorKind> for Located {
+    fn from(kind: ErrorKind) -> Self {
+        Self { kind, span: None }
+    }
+}
+
+impl ErrorKind {
+    pub fn at(self, span: Span) -> Located {
+        Located {
+            kind: self,
+            span: Some(span),
+        }
+    }
+
+    //Name the diagnostic is about, used to find the span when none was given
+    pub fn subject(&self) -> Option<&str> {
+        match self {
+            ErrorKind::UnknownMnemonic(name)
+            | ErrorKind::OperandCount { mnemonic: name, .. }
+            | ErrorKind::MissingLabel(name)
+            | ErrorKind::UndefinedSymbol(name)
+            | ErrorKind::UndefinedLocal(name, _)
+            | ErrorKind::ForwardReference(name)
+            | ErrorKind::UndefinedPublic(name)
+            | ErrorKind::UnexpectedDirective(name)
+            | ErrorKind::MacroArguments { name, .. }
+            | ErrorKind::ExpansionTooDeep(name) => Some(name),
+            //Local labels are reported under their qualified name
+            ErrorKind::DuplicateSymbol(name) => match name.find(['.', '@']) {
+                Some(start) if start > 0 => Some(&name[start..]),
+                _ => Some(name),
+            },
+            _ => None,
+        }
+    }
+}
+
+//Macro call a line was expanded from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expansion {
+    pub name: String,
+    pub file: String,
+    pub line: usize,
+}
+
+//Diagnostic for one line, warnings use the same type
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error {
+    pub file: String,
+    pub line: usize,
+    pub kind: ErrorKind,
+    pub severity: Severity,
+    //Source text of the line and the part of it to underline
+    pub text: String,
+    pub span: Option<Span>,
+    //Innermost call first
+    pub expansions: Vec<Expansion>,
+}
+
+impl Error {
+    pub fn new(file: &str, line: usize, kind: ErrorKind, expansions: &[Expansion]) -> Self {
+        Self {
+            file: file.to_string(),
+            line,
+            kind,
+            severity: Severity::Error,
+            text: String::new(),
+            span: None,
+            expansions: expansions.to_vec(),
+        }
+    }
+
+    /* Attach the source line. Without a span the error points at the name it is about,
+     * or at the whole statement.
+     */
+    pub fn with_source(mut self, text: &str, span: Option<Span>) -> Self {
+        let code = text.split(';').next().unwrap_or("");
+        let statement = || {
+            let start = code.len() - code.trim_start().len();
+            let length = code.trim().chars().count();
+            let column = code[..start].chars().count() + 1;
+            Some(Span { column, length }).filter(|span| span.length > 0)
+        };
+        let subject = || {
+            let name = self.kind.subject()?;
+            let start = code.to_ascii_uppercase().find(&name.to_ascii_uppercase())?;
+            Some(Span {
+                column: code[..start].chars().count() + 1,
+                length: name.chars().count(),
+            })
+        };
+        self.span = span.or_else(subject).or_else(statement);
+        self.text = text.to_string();
+        self
+    }
+
+    pub fn warning(mut self) -> Self {
+        self.severity = Severity::Warning;
+        self
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Syntax(message) => write!(fmt, "syntax error: {}", message),
+            ErrorKind::InvalidNumber(text) => write!(fmt, "invalid number `{}`", text),
+            ErrorKind::InvalidString => {
+                write!(
+                    fmt,
+                    "string in expression must be one or two characters long"
+                )
+            }
+            ErrorKind::UnknownMnemonic(name) => write!(fmt, "unknown mnemonic `{}`", name),
+            ErrorKind::OperandCount {
+                mnemonic,
+                expected,
+                found,
+            } => write!(
+                fmt,
+                "`{}` takes {} operand(s), found {}",
+                mnemonic, expected, found
+            ),
+            ErrorKind::InvalidRegister(text) => write!(fmt, "`{}` is not a register", text),
+            ErrorKind::InvalidRegisterPair(text) => {
+                write!(fmt, "`{}` is not a valid register pair here", text)
+            }
+            ErrorKind::MissingLabel(directive) => write!(fmt, "`{}` requires a name", directive),
+            ErrorKind::UndefinedSymbol(name) => write!(fmt, "undefined symbol `{}`", name),
+            ErrorKind::UndefinedLocal(name, scope) if scope.is_empty() => {
+                write!(
+                    fmt,
+                    "undefined local label `{}` before any global label",
+                    name
+                )
+            }
+            ErrorKind::UndefinedLocal(name, scope) => {
+                write!(fmt, "undefined local label `{}` in scope `{}`", name, scope)
+            }
+            ErrorKind::DuplicateSymbol(name) => write!(fmt, "symbol `{}` is already defined", name),
+            ErrorKind::ForwardReference(name) => write!(
+                fmt,
+                "symbol `{}` must be defined before it is used here",
+                name
+            ),
+            ErrorKind::DivisionByZero => write!(fmt, "division by zero"),
+            ErrorKind::NegativeShift(count) => write!(fmt, "negative shift count {}", count),
+            ErrorKind::ByteRange(value) => {
+                write!(fmt, "value {} ({:#X}) does not fit in a byte", value, value)
+            }
+            ErrorKind::WordRange(value) => {
+                write!(fmt, "value {} ({:#X}) does not fit in a word", value, value)
+            }
+            ErrorKind::RstRange(value) => {
+                write!(fmt, "restart vector {} is out of range 0..=7", value)
+            }
+            ErrorKind::RstAddress(value) => write!(
+                fmt,
+                "restart address {} is not one of 0, 8, 10H, ... 38H",
+                value
+            ),
+            ErrorKind::NotIntel8080(text) => {
+                write!(
+                    fmt,
+                    "`{}` is a Z80 instruction outside the 8080 subset",
+                    text
+                )
+            }
+            ErrorKind::NegativeSize(value) => write!(fmt, "negative storage size {}", value),
+            ErrorKind::AddressOverflow => write!(fmt, "location counter passed 0FFFFH"),
+            ErrorKind::UnterminatedBlock(directive, end) => {
+                write!(fmt, "`{}` block is missing its {}", directive, end)
+            }
+            ErrorKind::FileRead(path, message) => {
+                write!(fmt, "cannot read `{}`: {}", path, message)
+            }
+            ErrorKind::IncludeCycle(path) => {
+                write!(fmt, "include cycle: `{}` is already being included", path)
+            }
+            ErrorKind::Relocation => write!(
+                fmt,
+                "relocatable values can only be offset by absolute ones"
+            ),
+            ErrorKind::RelocatableByte => {
+                write!(fmt, "relocatable value does not fit in a byte")
+            }
+            ErrorKind::UndefinedPublic(name) => {
+                write!(fmt, "public symbol `{}` is not defined here", name)
+            }
+            ErrorKind::UnexpectedDirective(directive) => {
+                write!(fmt, "`{}` is not allowed here", directive)
+            }
+            ErrorKind::MacroArguments {
+                name,
+                expected,
+                found,
+            } => write!(
+                fmt,
+                "macro `{}` takes at most {} argument(s), found {}",
+                name, expected, found
+            ),
+            ErrorKind::ExpansionTooDeep(name) => {
+                write!(fmt, "expansion of `{}` is nested too deeply", name)
+            }
+            ErrorKind::Truncated(value) => {
+                write!(
+                    fmt,
+                    "value {} truncated to byte {:#04X}",
+                    value, *value as u8
+                )
+            }
+            ErrorKind::Unreachable(after) => write!(fmt, "unreachable code after `{}`", after),
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(fmt, "error"),
+            Severity::Warning => write!(fmt, "warning"),
+        }
+    }
+}
+
+/* file:line:column: error: message
+ *    12 |         MVI A,300
+ *       |               ^^^
+ */
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match (self.line, self.span) {
+            (0, _) => write!(fmt, "{}: ", self.file)?,
+            (line, None) => write!(fmt, "{}:{}: ", self.file, line)?,
+            (line, Some(span)) => write!(fmt, "{}:{}:{}: ", self.file, line, span.column)?,
+        }
+        write!(fmt, "{}: {}", self.severity, self.kind)?;
+        if self.line > 0 && !self.text.is_empty() {
+            write!(fmt, "\n{:>5} | {}", self.line, self.text)?;
+            if let Some(span) = self.span {
+                //Keep tabs so the carets line up with the text above
+                let indent: String = self
+                    .text
+                    .chars()
+                    .take(span.column - 1)
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                write!(
+                    fmt,
+                    "\n{:>5} | {}{}",
+                    "",
+                    indent,
+                    "^".repeat(span.length.max(1))
+                )?;
+            }
+        }
+        for expansion in &self.expansions {
+            write!(
+                fmt,
+                "\n  in expansion of `{}` at {}:{}",
+                expansion.name, expansion.file, expansion.line
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,502 @@
+use super::error::ErrorKind;
+
+/* Operand expressions
+ * Precedence follows the Intel 8080 assembler, from the tightest binding:
+ * ( )
+ * HIGH LOW, unary + - ~
+ * * / MOD SHL SHR
+ * + -
+ * EQ NE LT LE GT GE (also = <> != == < <= > >=)
+ * NOT
+ * AND (&)
+ * OR XOR (| ^)
+ * Comparisons give -1 (0FFFFH as a word, 0FFH as a byte) for true and 0 for false.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Symbol(String),
+    Location,
+    //`:+` is the next anonymous label, `:--` the one before the previous
+    Anonymous(i64),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    High,
+    Low,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Mod,
+    Shl,
+    Shr,
+    Add,
+    Sub,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Xor,
+}
+
+//Symbol values and location counter visible to an expression
+pub trait Scope {
+    fn symbol(&self, name: &str) -> Option<i64>;
+    fn location(&self) -> i64;
+    fn anonymous(&self, offset: i64) -> Option<i64>;
+
+    //What a symbol is relative to, None for absolute values
+    fn base(&self, _name: &str) -> Option<Base> {
+        None
+    }
+
+    fn location_base(&self) -> Option<Base> {
+        None
+    }
+}
+
+//What a relocatable value is an offset from, the linker adds its address
+#[derive(Debug, Clone, PartialEq)]
+pub enum Base {
+    Code,
+    Data,
+    External(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Str(Vec<u8>),
+    Dollar,
+    Anonymous(i64),
+    Op(&'static str),
+    LParen,
+    RParen,
+}
+
+const KEYWORDS: [&str; 15] = [
+    "NOT", "AND", "OR", "XOR", "MOD", "SHL", "SHR", "HIGH", "LOW", "EQ", "NE", "LT", "LE", "GT",
+    "GE",
+];
+
+pub fn is_identifier_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || c == '?' || c == '@' || c == '.'
+}
+
+pub fn is_identifier_char(c: char) -> bool {
+    is_identifier_start(c) || c.is_ascii_digit()
+}
+
+pub fn is_keyword(name: &str) -> bool {
+    KEYWORDS.contains(&name)
+}
+
+pub fn parse_number(text: &str) -> Result<i64, ErrorKind> {
+    let upper = text.to_ascii_uppercase();
+    let (digits, radix) = if let Some(digits) = upper.strip_prefix("0X") {
+        (digits, 16)
+    } else {
+        match upper.chars().last() {
+            Some('H') => (&upper[..upper.len() - 1], 16),
+            Some('B') => (&upper[..upper.len() - 1], 2),
+            Some('O') | Some('Q') => (&upper[..upper.len() - 1], 8),
+            Some('D') => (&upper[..upper.len() - 1], 10),
+            _ => (&upper[..], 10),
+        }
+    };
+    i64::from_str_radix(digits, radix)
+        .ok()
+        .filter(|value| *value <= 0xFFFF_FFFF)
+        .ok_or_else(|| ErrorKind::InvalidNumber(text.to_string()))
+}
+
+//Read a quoted string starting at `chars[0]`, doubled quotes stand for one quote character
+pub fn read_string(chars: &[char]) -> Option<(Vec<u8>, usize)> {
+    let quote = chars[0];
+    let mut bytes = Vec::new();
+    let mut i = 1;
+    while i < chars.len() {
+        if chars[i] == quote {
+            if chars.get(i + 1) == Some(&quote) {
+                bytes.push(quote as u8);
+                i += 2;
+                continue;
+            }
+            return Some((bytes, i + 1));
+        }
+        bytes.push(chars[i] as u8);
+        i += 1;
+    }
+    None
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, ErrorKind> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(parse_number(&number)?));
+            continue;
+        }
+        if is_identifier_start(c) {
+            let start = i;
+            while i < chars.len() && is_identifier_char(chars[i]) {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            tokens.push(Token::Ident(name.to_ascii_uppercase()));
+            continue;
+        }
+        if c == '\'' || c == '"' {
+            let (bytes, length) = read_string(&chars[i..])
+                .ok_or_else(|| ErrorKind::Syntax("unterminated string".to_string()))?;
+            tokens.push(Token::Str(bytes));
+            i += length;
+            continue;
+        }
+        if c == ':' {
+            let direction = chars.get(i + 1).copied();
+            let count = chars[i + 1..]
+                .iter()
+                .take_while(|c| Some(**c) == direction)
+                .count();
+            match direction {
+                Some('+') if count > 0 => tokens.push(Token::Anonymous(count as i64)),
+                Some('-') if count > 0 => tokens.push(Token::Anonymous(-(count as i64))),
+                _ => return Err(ErrorKind::Syntax("expected `:+` or `:-`".to_string())),
+            }
+            i += count + 1;
+            continue;
+        }
+        let next = chars.get(i + 1).copied();
+        let (token, length) = match (c, next) {
+            ('$', _) => (Token::Dollar, 1),
+            ('(', _) => (Token::LParen, 1),
+            (')', _) => (Token::RParen, 1),
+            ('<', Some('<')) => (Token::Op("SHL"), 2),
+            ('>', Some('>')) => (Token::Op("SHR"), 2),
+            ('<', Some('=')) => (Token::Op("LE"), 2),
+            ('>', Some('=')) => (Token::Op("GE"), 2),
+            ('<', Some('>')) | ('!', Some('=')) => (Token::Op("NE"), 2),
+            ('=', Some('=')) => (Token::Op("EQ"), 2),
+            ('=', _) => (Token::Op("EQ"), 1),
+            ('<', _) => (Token::Op("LT"), 1),
+            ('>', _) => (Token::Op("GT"), 1),
+            ('&', _) => (Token::Op("AND"), 1),
+            ('|', _) => (Token::Op("OR"), 1),
+            ('^', _) => (Token::Op("XOR"), 1),
+            ('~', _) => (Token::Op("~"), 1),
+            ('%', _) => (Token::Op("MOD"), 1),
+            ('+', _) => (Token::Op("+"), 1),
+            ('-', _) => (Token::Op("-"), 1),
+            ('*', _) => (Token::Op("*"), 1),
+            ('/', _) => (Token::Op("/"), 1),
+            _ => return Err(ErrorKind::Syntax(format!("unexpected character `{}`", c))),
+        };
+        tokens.push(token);
+        i += length;
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    //Operator at the cursor, keywords like AND are spelled as identifiers
+    fn peek_op(&self) -> Option<&str> {
+        match self.peek() {
+            Some(Token::Op(op)) => Some(op),
+            Some(Token::Ident(name)) if is_keyword(name) => Some(name.as_str()),
+            _ => None,
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinaryOp)],
+        next: fn(&mut Self) -> Result<Expr, ErrorKind>,
+    ) -> Result<Expr, ErrorKind> {
+        let mut left = next(self)?;
+        while let Some(op) = self
+            .peek_op()
+            .and_then(|name| ops.iter().find(|(op, _)| *op == name))
+            .map(|(_, op)| *op)
+        {
+            self.position += 1;
+            let right = next(self)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Expr, ErrorKind> {
+        self.binary(&[("OR", BinaryOp::Or), ("XOR", BinaryOp::Xor)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, ErrorKind> {
+        self.binary(&[("AND", BinaryOp::And)], Self::not)
+    }
+
+    fn not(&mut self) -> Result<Expr, ErrorKind> {
+        if self.peek_op() == Some("NOT") {
+            self.position += 1;
+            return Ok(Expr::Unary(UnaryOp::Not, Box::new(self.not()?)));
+        }
+        self.relation()
+    }
+
+    fn relation(&mut self) -> Result<Expr, ErrorKind> {
+        self.binary(
+            &[
+                ("EQ", BinaryOp::Eq),
+                ("NE", BinaryOp::Ne),
+                ("LT", BinaryOp::Lt),
+                ("LE", BinaryOp::Le),
+                ("GT", BinaryOp::Gt),
+                ("GE", BinaryOp::Ge),
+            ],
+            Self::additive,
+        )
+    }
+
+    fn additive(&mut self) -> Result<Expr, ErrorKind> {
+        self.binary(
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            Self::multiplicative,
+        )
+    }
+
+    fn multiplicative(&mut self) -> Result<Expr, ErrorKind> {
+        self.binary(
+            &[
+                ("*", BinaryOp::Mul),
+                ("/", BinaryOp::Div),
+                ("MOD", BinaryOp::Mod),
+                ("SHL", BinaryOp::Shl),
+                ("SHR", BinaryOp::Shr),
+            ],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr, ErrorKind> {
+        let op = match self.peek_op() {
+            Some("-") => Some(UnaryOp::Neg),
+            Some("~") => Some(UnaryOp::Not),
+            Some("HIGH") => Some(UnaryOp::High),
+            Some("LOW") => Some(UnaryOp::Low),
+            Some("+") => {
+                self.position += 1;
+                return self.unary();
+            }
+            _ => None,
+        };
+        match op {
+            Some(op) => {
+                self.position += 1;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            None => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, ErrorKind> {
+        let token = self
+            .peek()
+            .cloned()
+            .ok_or_else(|| ErrorKind::Syntax("expression expected".to_string()))?;
+        self.position += 1;
+        match token {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Dollar => Ok(Expr::Location),
+            Token::Anonymous(offset) => Ok(Expr::Anonymous(offset)),
+            Token::Str(bytes) => match bytes[..] {
+                [c] => Ok(Expr::Number(c as i64)),
+                [h, l] => Ok(Expr::Number((h as i64) << 8 | l as i64)),
+                _ => Err(ErrorKind::InvalidString),
+            },
+            Token::Ident(name) if !is_keyword(&name) => Ok(Expr::Symbol(name)),
+            Token::LParen => {
+                let expr = self.or()?;
+                match self.peek() {
+                    Some(Token::RParen) => {
+                        self.position += 1;
+                        Ok(expr)
+                    }
+                    _ => Err(ErrorKind::Syntax("missing `)`".to_string())),
+                }
+            }
+            Token::Ident(name) => Err(ErrorKind::Syntax(format!(
+                "operator `{}` where a value was expected",
+                name
+            ))),
+            Token::Op(op) => Err(ErrorKind::Syntax(format!(
+                "operator `{}` where a value was expected",
+                op
+            ))),
+            Token::RParen => Err(ErrorKind::Syntax("unexpected `)`".to_string())),
+        }
+    }
+}
+
+impl Expr {
+    pub fn parse(text: &str) -> Result<Expr, ErrorKind> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        if parser.position != parser.tokens.len() {
+            return Err(ErrorKind::Syntax(format!(
+                "unexpected trailing input in `{}`",
+                text.trim()
+            )));
+        }
+        Ok(expr)
+    }
+
+    //Names of the symbols the expression refers to, in order of appearance
+    pub fn symbols<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Expr::Symbol(name) => names.push(name),
+            Expr::Unary(_, expr) => expr.symbols(names),
+            Expr::Binary(_, left, right) => {
+                left.symbols(names);
+                right.symbols(names);
+            }
+            _ => (),
+        }
+    }
+
+    /* Base of the value. A relocatable value may only be added to or subtracted from
+     * absolute ones, the difference of two values in the same segment is absolute.
+     */
+    pub fn base(&self, scope: &dyn Scope) -> Result<Option<Base>, ErrorKind> {
+        let invalid = || Err(ErrorKind::Relocation);
+        match self {
+            Expr::Number(_) => Ok(None),
+            Expr::Symbol(name) => Ok(scope.base(name)),
+            Expr::Location | Expr::Anonymous(_) => Ok(scope.location_base()),
+            Expr::Unary(_, expr) => match expr.base(scope)? {
+                None => Ok(None),
+                Some(_) => invalid(),
+            },
+            Expr::Binary(op, left, right) => match (op, left.base(scope)?, right.base(scope)?) {
+                (_, None, None) => Ok(None),
+                (BinaryOp::Add, base, None) | (BinaryOp::Add, None, base) => Ok(base),
+                (BinaryOp::Sub, base, None) => Ok(base),
+                (BinaryOp::Sub, Some(left), Some(right))
+                    if left == right && !matches!(left, Base::External(_)) =>
+                {
+                    Ok(None)
+                }
+                _ => invalid(),
+            },
+        }
+    }
+
+    pub fn eval(&self, scope: &dyn Scope) -> Result<i64, ErrorKind> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Location => Ok(scope.location()),
+            Expr::Anonymous(offset) => scope.anonymous(*offset).ok_or_else(|| {
+                let sign = if *offset > 0 { "+" } else { "-" };
+                ErrorKind::UndefinedSymbol(format!(
+                    ":{}",
+                    sign.repeat(offset.unsigned_abs() as usize)
+                ))
+            }),
+            Expr::Symbol(name) => scope
+                .symbol(name)
+                .ok_or_else(|| ErrorKind::UndefinedSymbol(name.clone())),
+            Expr::Unary(op, expr) => {
+                let value = expr.eval(scope)?;
+                Ok(match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::Not => !value,
+                    UnaryOp::High => (value >> 8) & 0xFF,
+                    UnaryOp::Low => value & 0xFF,
+                })
+            }
+            Expr::Binary(op, left, right) => {
+                let left = left.eval(scope)?;
+                let right = right.eval(scope)?;
+                let truth = |value: bool| if value { -1 } else { 0 };
+                //Comparisons are of unsigned 16-bit values, so -1 equals and is not below 0FFFFH
+                let unsigned = |value: i64| value & 0xFFFF;
+                Ok(match op {
+                    BinaryOp::Mul => left.wrapping_mul(right),
+                    BinaryOp::Div | BinaryOp::Mod if right == 0 => {
+                        return Err(ErrorKind::DivisionByZero)
+                    }
+                    BinaryOp::Div => left.wrapping_div(right),
+                    BinaryOp::Mod => left.wrapping_rem(right),
+                    BinaryOp::Shl | BinaryOp::Shr if right < 0 => {
+                        return Err(ErrorKind::NegativeShift(right))
+                    }
+                    BinaryOp::Shl if right > 31 => 0,
+                    BinaryOp::Shl => (left << right) & 0xFFFF_FFFF,
+                    BinaryOp::Shr if right > 31 => 0,
+                    BinaryOp::Shr => (left & 0xFFFF) >> right,
+                    BinaryOp::Add => left.wrapping_add(right),
+                    BinaryOp::Sub => left.wrapping_sub(right),
+                    BinaryOp::Eq => truth(unsigned(left) == unsigned(right)),
+                    BinaryOp::Ne => truth(unsigned(left) != unsigned(right)),
+                    BinaryOp::Lt => truth(unsigned(left) < unsigned(right)),
+                    BinaryOp::Le => truth(unsigned(left) <= unsigned(right)),
+                    BinaryOp::Gt => truth(unsigned(left) > unsigned(right)),
+                    BinaryOp::Ge => truth(unsigned(left) >= unsigned(right)),
+                    BinaryOp::And => left & right,
+                    BinaryOp::Or => left | right,
+                    BinaryOp::Xor => left ^ right,
+                })
+            }
+        }
+    }
+}
+
+//Byte operands accept -128..=255 so both signed and unsigned values can be written
+pub fn to_byte(value: i64) -> Result<u8, ErrorKind> {
+    match value {
+        -0x80..=0xFF => Ok(value as u8),
+        _ => Err(ErrorKind::ByteRange(value)),
+    }
+}
+
+pub fn to_word(value: i64) -> Result<u16, ErrorKind> {
+    match value {
+        -0x8000..=0xFFFF => Ok(value as u16),
+        _ => Err(ErrorKind::WordRange(value)),
+    }
+}
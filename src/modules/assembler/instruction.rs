@@ -0,0 +1,237 @@
+use super::{
+    error::{ErrorKind, Located},
+    expr::{to_byte, to_word},
+    parser::Operand,
+};
+use crate::modules::opcodes::{
+    Operand::{Address, Data16, Data8, Pair, Port, Register, Vector},
+    OPCODES,
+};
+
+//Operand layout of an instruction, it defines both the size and the encoding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Form {
+    Implied,
+    Dst,     //00DDD000: INR, DCR
+    Src,     //00000SSS: ADD, SUB, ...
+    DstSrc,  //01DDDSSS: MOV
+    DstImm8, //00DDD000 data: MVI
+    Pair(Pairs),
+    PairImm16,
+    Imm8,
+    Imm16,
+    Rst,
+}
+
+//Which register pair names are accepted, for bits 4 and 5 of the opcode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pairs {
+    Indirect,
+    WithSp,
+    WithPsw,
+}
+
+impl Form {
+    pub fn size(self) -> u16 {
+        match self {
+            Form::DstImm8 | Form::Imm8 => 2,
+            Form::PairImm16 | Form::Imm16 => 3,
+            _ => 1,
+        }
+    }
+
+    //Index of the operand that is emitted as data after the opcode
+    pub fn data_operand(self) -> Option<usize> {
+        match self {
+            Form::Imm8 | Form::Imm16 => Some(0),
+            Form::DstImm8 | Form::PairImm16 => Some(1),
+            _ => None,
+        }
+    }
+
+    fn operand_count(self) -> usize {
+        match self {
+            Form::Implied => 0,
+            Form::DstSrc | Form::DstImm8 | Form::PairImm16 => 2,
+            _ => 1,
+        }
+    }
+}
+
+//Instruction decoded back from its bytes, with the operands in Intel syntax
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoded {
+    pub mnemonic: &'static str,
+    pub operands: Vec<String>,
+    pub length: usize,
+}
+
+//Intel hexadecimal literal, with a leading zero when it would start with a letter
+pub fn hex(value: u16, digits: usize) -> String {
+    let text = format!("{:0digits$X}H", value, digits = digits);
+    if text.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        format!("0{}", text)
+    } else {
+        text
+    }
+}
+
+//Instruction at the start of `bytes`, None for undocumented opcodes and cut off instructions
+pub fn decode(bytes: &[u8]) -> Option<Decoded> {
+    let opcode = &OPCODES[*bytes.first()? as usize];
+    if !opcode.documented {
+        return None;
+    }
+    let length = opcode.length as usize;
+    let data = bytes.get(1..length)?;
+    let byte = || hex(data[0] as u16, 2);
+    let word = || hex(u16::from_le_bytes([data[0], data[1]]), 4);
+    let operands = opcode
+        .operands
+        .iter()
+        .map(|operand| match operand {
+            Register(name) | Pair(name) => name.to_string(),
+            Data8 | Port => byte(),
+            Data16 | Address => word(),
+            Vector(number) => number.to_string(),
+        })
+        .collect();
+    Some(Decoded {
+        mnemonic: opcode.mnemonic,
+        operands,
+        length,
+    })
+}
+
+/* Base opcode and operand form of a mnemonic, from the opcode table. The base is the
+ * lowest opcode, the one with register B or pair B in it.
+ */
+pub fn lookup(mnemonic: &str) -> Option<(u8, Form)> {
+    let base = OPCODES
+        .iter()
+        .position(|opcode| opcode.documented && opcode.mnemonic == mnemonic)?;
+    let form = match OPCODES[base].operands {
+        [] => Form::Implied,
+        [Register(_)] if base < 0x40 => Form::Dst,
+        [Register(_)] => Form::Src,
+        [Register(_), Register(_)] => Form::DstSrc,
+        [Register(_), Data8] => Form::DstImm8,
+        [Pair(_)] => Form::Pair(pairs(base)),
+        [Pair(_), Data16] => Form::PairImm16,
+        [Data8] | [Port] => Form::Imm8,
+        [Data16] | [Address] => Form::Imm16,
+        [Vector(_)] => Form::Rst,
+        operands => unreachable!("operands {:?} of {}", operands, mnemonic),
+    };
+    Some((base as u8, form))
+}
+
+//The pair the opcode with bits 4 and 5 set names, if it is still the same instruction
+fn pairs(base: usize) -> Pairs {
+    let last = &OPCODES[base | 0x30];
+    match last.operands {
+        _ if last.mnemonic != OPCODES[base].mnemonic => Pairs::Indirect,
+        [Pair("SP")] => Pairs::WithSp,
+        _ => Pairs::WithPsw,
+    }
+}
+
+fn register(operand: &Operand) -> Result<u8, Located> {
+    match operand.text.to_ascii_uppercase().as_str() {
+        "B" => Ok(0b000),
+        "C" => Ok(0b001),
+        "D" => Ok(0b010),
+        "E" => Ok(0b011),
+        "H" => Ok(0b100),
+        "L" => Ok(0b101),
+        "M" => Ok(0b110),
+        "A" => Ok(0b111),
+        _ => Err(ErrorKind::InvalidRegister(operand.text.clone()).at(operand.span())),
+    }
+}
+
+fn pair(operand: &Operand, pairs: Pairs) -> Result<u8, Located> {
+    let pair = match (operand.text.to_ascii_uppercase().as_str(), pairs) {
+        ("B", _) => 0b00,
+        ("D", _) => 0b01,
+        ("H", Pairs::WithSp) | ("H", Pairs::WithPsw) => 0b10,
+        ("SP", Pairs::WithSp) | ("PSW", Pairs::WithPsw) => 0b11,
+        _ => {
+            let kind = ErrorKind::InvalidRegisterPair(operand.text.clone());
+            return Err(kind.at(operand.span()));
+        }
+    };
+    Ok(pair << 4)
+}
+
+fn rst(value: i64, operand: &Operand) -> Result<u8, Located> {
+    match value {
+        0..=7 => Ok(value as u8),
+        _ => Err(ErrorKind::RstRange(value).at(operand.span())),
+    }
+}
+
+//Byte operand, values that only fit in a word are cut to their low byte with a warning
+pub fn byte(value: i64, operand: &Operand, warnings: &mut Vec<Located>) -> Result<u8, Located> {
+    match to_byte(value) {
+        Ok(byte) => Ok(byte),
+        Err(_) if to_word(value).is_ok() => {
+            warnings.push(ErrorKind::Truncated(value).at(operand.span()));
+            Ok(value as u8)
+        }
+        Err(kind) => Err(kind.at(operand.span())),
+    }
+}
+
+/* Encode one instruction, `eval` gives the value of an operand expression.
+ * Truncated byte values are added to `warnings`.
+ */
+pub fn encode(
+    mnemonic: &str,
+    opcode: u8,
+    form: Form,
+    operands: &[Operand],
+    eval: &dyn Fn(&Operand) -> Result<i64, Located>,
+    warnings: &mut Vec<Located>,
+) -> Result<Vec<u8>, Located> {
+    let mut imm8 = |operand| byte(eval(operand)?, operand, warnings);
+    let word = |operand: &Operand| to_word(eval(operand)?).map_err(|kind| kind.at(operand.span()));
+    if operands.len() != form.operand_count() {
+        return Err(ErrorKind::OperandCount {
+            mnemonic: mnemonic.to_string(),
+            expected: form.operand_count(),
+            found: operands.len(),
+        }
+        .into());
+    }
+    let bytes = match form {
+        Form::Implied => vec![opcode],
+        Form::Dst => vec![opcode | register(&operands[0])? << 3],
+        Form::Src => vec![opcode | register(&operands[0])?],
+        Form::DstSrc => {
+            let (dst, src) = (register(&operands[0])?, register(&operands[1])?);
+            if dst == 0b110 && src == 0b110 {
+                let text = format!("{},{}", operands[0].text, operands[1].text);
+                return Err(ErrorKind::InvalidRegister(text).into());
+            }
+            vec![opcode | dst << 3 | src]
+        }
+        Form::DstImm8 => vec![opcode | register(&operands[0])? << 3, imm8(&operands[1])?],
+        Form::Pair(pairs) => vec![opcode | pair(&operands[0], pairs)?],
+        Form::PairImm16 => {
+            let value = word(&operands[1])?;
+            vec![
+                opcode | pair(&operands[0], Pairs::WithSp)?,
+                value as u8,
+                (value >> 8) as u8,
+            ]
+        }
+        Form::Imm8 => vec![opcode, imm8(&operands[0])?],
+        Form::Imm16 => {
+            let value = word(&operands[0])?;
+            vec![opcode, value as u8, (value >> 8) as u8]
+        }
+        Form::Rst => vec![opcode | rst(eval(&operands[0])?, &operands[0])? << 3],
+    };
+    Ok(bytes)
+}
@@ -0,0 +1,131 @@
+use std::{fmt::Write, rc::Rc};
+
+use super::{object::Section, Assembly};
+
+//Source file and line, as shown in listings and cross-references
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceRef {
+    pub file: Rc<str>,
+    pub line: usize,
+}
+
+//What one source line became
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListingLine {
+    pub source: SourceRef,
+    //Number of macro expansions the line is nested in
+    pub depth: usize,
+    pub text: String,
+    pub section: Section,
+    pub address: Option<u16>,
+    //Value of EQU and SET, new location of ORG
+    pub value: Option<u16>,
+    pub bytes: Vec<u8>,
+    pub cycles: Option<(u8, u8)>,
+    //Inside a false IF
+    pub skipped: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossReference {
+    pub value: u16,
+    pub defined: SourceRef,
+    pub references: Vec<SourceRef>,
+}
+
+const BYTES_PER_ROW: usize = 4;
+
+//Line number, with the file name for lines outside the main file
+fn site(source: &SourceRef, main: &str) -> String {
+    if &*source.file == main {
+        source.line.to_string()
+    } else {
+        format!("{}:{}", source.file, source.line)
+    }
+}
+
+fn bytes(bytes: &[u8]) -> String {
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    hex.join(" ")
+}
+
+/* LOC   OBJECT       CYCLES  LINE  SOURCE
+ * Expanded macro lines are marked with `+`, lines skipped by IF with `-`.
+ * Bytes that do not fit on the line continue on the next ones.
+ */
+pub fn render(assembly: &Assembly) -> String {
+    let mut out = String::new();
+    let main = assembly
+        .listing
+        .first()
+        .map(|line| line.source.file.clone())
+        .unwrap_or_else(|| Rc::from(""));
+    let mut file = main.clone();
+    writeln!(out, "LOC   OBJECT       CYCLES  LINE  SOURCE").unwrap();
+    for line in &assembly.listing {
+        if line.source.file != file && line.depth == 0 {
+            file = line.source.file.clone();
+            writeln!(out, "{:34}FILE {}", "", file).unwrap();
+        }
+        let location = match (line.value, line.address) {
+            (Some(value), _) => format!("={:04X}", value),
+            //Relocatable addresses are marked with ' for code and " for data
+            (None, Some(address)) => match line.section {
+                Section::Absolute => format!("{:04X} ", address),
+                Section::Code => format!("{:04X}'", address),
+                Section::Data => format!("{:04X}\"", address),
+            },
+            (None, None) => String::new(),
+        };
+        let cycles = match line.cycles {
+            Some((taken, not_taken)) if taken != not_taken => format!("{}/{}", taken, not_taken),
+            Some((taken, _)) => taken.to_string(),
+            None => String::new(),
+        };
+        let marker = if line.skipped {
+            "-"
+        } else if line.depth > 0 {
+            "+"
+        } else {
+            " "
+        };
+        let mut rows = line.bytes.chunks(BYTES_PER_ROW);
+        writeln!(
+            out,
+            "{:5} {:12} {:>6} {:>5}{} {}",
+            location,
+            bytes(rows.next().unwrap_or(&[])),
+            cycles,
+            line.source.line,
+            marker,
+            line.text
+        )
+        .unwrap();
+        for (row, chunk) in rows.enumerate() {
+            let address = line.address.unwrap_or(0) as usize + (row + 1) * BYTES_PER_ROW;
+            writeln!(out, "{:04X}  {}", address, bytes(chunk)).unwrap();
+        }
+    }
+
+    writeln!(out, "\nSYMBOLS").unwrap();
+    for (name, value) in &assembly.symbols {
+        writeln!(out, "{:<24} {:04X}", name, value).unwrap();
+    }
+
+    writeln!(out, "\nCROSS REFERENCE").unwrap();
+    for (name, reference) in &assembly.cross_reference {
+        write!(
+            out,
+            "{:<24} {:04X}  {}#",
+            name,
+            reference.value,
+            site(&reference.defined, &main)
+        )
+        .unwrap();
+        for used in &reference.references {
+            write!(out, " {}", site(used, &main)).unwrap();
+        }
+        writeln!(out).unwrap();
+    }
+    out
+}
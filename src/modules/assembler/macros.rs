@@ -0,0 +1,206 @@
+use std::rc::Rc;
+
+use super::{
+    error::{ErrorKind, Expansion},
+    expr::{is_identifier_char, is_identifier_start, read_string},
+    source::{find_directive, SourceLine},
+};
+
+#[derive(Debug, Clone)]
+pub struct Macro {
+    pub parameters: Vec<String>,
+    pub body: Vec<SourceLine>,
+}
+
+//Block being recorded until its matching ENDM
+#[derive(Debug)]
+pub enum Block {
+    Macro(String, Vec<String>),
+    Rept(i64),
+    Irp(String, Vec<String>),
+}
+
+#[derive(Debug)]
+pub struct Recording {
+    pub block: Block,
+    pub start: SourceLine,
+    pub depth: usize,
+    pub body: Vec<SourceLine>,
+}
+
+pub const MACRO_DIRECTIVES: [&str; 7] = ["MACRO", "ENDM", "REPT", "IRP", "IRPC", "LOCAL", "EXITM"];
+const OPENING: [&str; 4] = ["MACRO", "REPT", "IRP", "IRPC"];
+
+//Nesting depth where expansion gives up, it catches macros that call themselves forever
+pub const MAX_DEPTH: usize = 64;
+
+pub fn block_directive(text: &str) -> Option<&'static str> {
+    find_directive(text, &MACRO_DIRECTIVES)
+}
+
+pub fn opens_block(directive: &str) -> bool {
+    OPENING.contains(&directive)
+}
+
+//Split macro arguments on commas outside of quotes, parentheses and <...> groups
+pub fn split_arguments(text: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut depth = 0;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), _) => (),
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (None, '(') | (None, '<') => depth += 1,
+            (None, ')') | (None, '>') => depth -= 1,
+            (None, ',') if depth <= 0 => {
+                arguments.push(unbracket(&current));
+                current.clear();
+                continue;
+            }
+            _ => (),
+        }
+        current.push(c);
+    }
+    if !current.trim().is_empty() || !arguments.is_empty() {
+        arguments.push(unbracket(&current));
+    }
+    arguments
+}
+
+//`<a, b>` passes `a, b` as a single argument
+fn unbracket(argument: &str) -> String {
+    let argument = argument.trim();
+    match argument.strip_prefix('<').and_then(|a| a.strip_suffix('>')) {
+        Some(inner) => inner.to_string(),
+        None => argument.to_string(),
+    }
+}
+
+/* Replace parameter names with their values.
+ * Outside of strings every identifier equal to a parameter is replaced, inside of strings
+ * only the ones glued to `&`. The `&` next to a replaced parameter is dropped, so
+ * `L&N` becomes `L1` for N = 1.
+ */
+pub fn substitute(text: &str, bindings: &[(String, String)]) -> String {
+    if bindings.is_empty() {
+        return text.to_string();
+    }
+    let chars: Vec<char> = text.chars().collect();
+    let lookup = |name: &str| {
+        let name = name.to_ascii_uppercase();
+        bindings
+            .iter()
+            .find(|(parameter, _)| *parameter == name)
+            .map(|(_, value)| value.as_str())
+    };
+    let mut result = String::new();
+    let mut quote = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if quote.is_none() && c == ';' {
+            result.extend(&chars[i..]);
+            break;
+        }
+        if quote == Some(c) {
+            quote = None;
+        } else if quote.is_none() && (c == '\'' || c == '"') {
+            quote = Some(c);
+        }
+        if c.is_ascii_digit() {
+            while i < chars.len() && is_identifier_char(chars[i]) {
+                result.push(chars[i]);
+                i += 1;
+            }
+            continue;
+        }
+        if !is_identifier_start(c) {
+            result.push(c);
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < chars.len() && is_identifier_char(chars[i]) {
+            i += 1;
+        }
+        let name: String = chars[start..i].iter().collect();
+        let glued_before = start > 0 && chars[start - 1] == '&';
+        let glued_after = chars.get(i) == Some(&'&');
+        match lookup(&name) {
+            Some(value) if quote.is_none() || glued_before || glued_after => {
+                if glued_before {
+                    result.pop();
+                }
+                result.push_str(value);
+                if glued_after {
+                    i += 1;
+                }
+            }
+            _ => result.push_str(&name),
+        }
+    }
+    result
+}
+
+//Characters of an IRPC argument, quotes around it are optional
+pub fn irpc_characters(text: &str) -> Vec<String> {
+    let chars: Vec<char> = text.trim().chars().collect();
+    let characters = match chars.first() {
+        Some('\'') | Some('"') => match read_string(&chars) {
+            Some((bytes, _)) => bytes.into_iter().map(|b| b as char).collect(),
+            None => chars,
+        },
+        _ => chars,
+    };
+    characters.iter().map(|c| c.to_string()).collect()
+}
+
+/* Lines of one expansion.
+ * LOCAL names at the top level of the body get a fresh `??nnnn` name each time,
+ * LOCALs of nested definitions are left for the inner macro.
+ */
+pub fn expand(
+    body: &[SourceLine],
+    mut bindings: Vec<(String, String)>,
+    counter: &mut u32,
+    expansions: Rc<Vec<Expansion>>,
+) -> Result<Vec<SourceLine>, ErrorKind> {
+    let mut depth = 0;
+    let mut kept = Vec::new();
+    for line in body {
+        match block_directive(&line.text) {
+            Some("LOCAL") if depth == 0 => {
+                let code = line.text.split(';').next().unwrap_or("");
+                let upper = code.to_ascii_uppercase();
+                let start = upper.find("LOCAL").unwrap_or(0) + "LOCAL".len();
+                for name in code[start..].split(',').map(str::trim) {
+                    if name.is_empty() {
+                        continue;
+                    }
+                    if !name.chars().all(is_identifier_char) {
+                        return Err(ErrorKind::Syntax(format!("invalid local name `{}`", name)));
+                    }
+                    *counter += 1;
+                    bindings.push((name.to_ascii_uppercase(), format!("??{:04}", counter)));
+                }
+                continue;
+            }
+            Some(directive) if opens_block(directive) => depth += 1,
+            Some("ENDM") => depth -= 1,
+            _ => (),
+        }
+        kept.push(line);
+    }
+    Ok(kept
+        .into_iter()
+        .map(|line| SourceLine {
+            file: line.file.clone(),
+            line: line.line,
+            text: substitute(&line.text, &bindings),
+            expansions: expansions.clone(),
+        })
+        .collect())
+}
@@ -0,0 +1,1365 @@
+pub mod analysis;
+pub mod dialect;
+pub mod disassembler;
+pub mod error;
+pub mod expr;
+mod instruction;
+pub mod listing;
+mod macros;
+pub mod object;
+mod parser;
+mod source;
+#[cfg(test)]
+mod tests;
+mod zilog;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    convert::TryFrom,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use crate::modules::opcodes::OPCODES;
+
+use self::{
+    dialect::{Alias, Dialect},
+    error::{Error, ErrorKind, Expansion, Located, Severity, Span},
+    expr::{to_word, Base, Expr, Scope},
+    listing::{CrossReference, ListingLine, SourceRef},
+    macros::{Block, Macro, Recording},
+    object::{Object, Public, Relocation, Section},
+    parser::{Operand, Statement},
+    source::{Condition, Frame, SourceLine},
+};
+
+const DIRECTIVES: [&str; 14] = [
+    "ORG", "EQU", "SET", "DB", "DW", "DS", "END", "INCLUDE", "INCBIN", "ASEG", "CSEG", "DSEG",
+    "PUBLIC", "EXTERN",
+];
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    //Symbols defined before the first line, as with -D on the command line
+    pub defines: Vec<(String, i64)>,
+    pub dialect: Dialect,
+}
+
+//Contiguous run of assembled bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub section: Section,
+    //Offset from the start of the section for relocatable ones
+    pub address: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assembly {
+    pub segments: Vec<Segment>,
+    pub symbols: BTreeMap<String, u16>,
+    pub entry: Option<(Section, u16)>,
+    pub code_size: u16,
+    pub data_size: u16,
+    pub relocations: Vec<Relocation>,
+    pub publics: Vec<Public>,
+    pub externals: Vec<String>,
+    pub warnings: Vec<Error>,
+    pub listing: Vec<ListingLine>,
+    //Definition and uses of every symbol, by name
+    pub cross_reference: BTreeMap<String, CrossReference>,
+}
+
+impl Assembly {
+    //Relocatable modules have to be linked, they have no flat image of their own
+    pub fn is_relocatable(&self) -> bool {
+        self.code_size > 0
+            || self.data_size > 0
+            || !self.relocations.is_empty()
+            || !self.externals.is_empty()
+    }
+
+    pub fn to_object(&self, name: &str) -> Object {
+        Object {
+            name: name.to_string(),
+            code_size: self.code_size,
+            data_size: self.data_size,
+            segments: self.segments.clone(),
+            relocations: self.relocations.clone(),
+            publics: self.publics.clone(),
+            externals: self.externals.clone(),
+            entry: self.entry,
+        }
+    }
+
+    //Flat image from the origin to the last emitted byte, gaps are zero filled
+    pub fn to_binary(&self) -> Vec<u8> {
+        flatten(&self.segments)
+    }
+
+    //One `NAME EQU value` line per symbol, local labels under their qualified name
+    pub fn symbol_table(&self) -> String {
+        self.symbols
+            .iter()
+            .map(|(name, value)| format!("{:<16} EQU 0{:04X}H\n", name, value))
+            .collect()
+    }
+}
+
+//Image from the lowest to the highest address of the segments, gaps are zero filled
+pub fn flatten(segments: &[Segment]) -> Vec<u8> {
+    let origin = segments.iter().map(|s| s.address).min().unwrap_or(0) as usize;
+    let end = segments
+        .iter()
+        .map(|s| s.address as usize + s.data.len())
+        .max()
+        .unwrap_or(origin);
+    let mut binary = vec![0u8; end - origin];
+    for segment in segments {
+        let start = segment.address as usize - origin;
+        binary[start..start + segment.data.len()].clone_from_slice(&segment.data);
+    }
+    binary
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SymbolKind {
+    Label,
+    Equ,
+    Set,
+    Extern,
+}
+
+#[derive(Debug, Clone)]
+struct Symbol {
+    value: i64,
+    kind: SymbolKind,
+    base: Option<Base>,
+    defined: SourceRef,
+    references: Vec<SourceRef>,
+}
+
+//Where a statement was placed, everything its expressions are evaluated against
+#[derive(Debug, Clone)]
+struct Position {
+    section: Section,
+    address: u16,
+    //Last global label, local labels are qualified with it
+    scope: Rc<str>,
+    //Anonymous labels defined before the statement, its own included
+    anonymous: usize,
+}
+
+//Statement kept from the first pass with the position it was placed at
+struct Entry {
+    source: SourceLine,
+    position: Position,
+    statement: Statement,
+    //Index of the line in the listing
+    listed: usize,
+}
+
+struct Deferred {
+    name: String,
+    expr: Expr,
+    source: SourceLine,
+    operand: Operand,
+    position: Position,
+    listed: usize,
+}
+
+struct Assembler {
+    symbols: HashMap<String, Symbol>,
+    entries: Vec<Entry>,
+    //EQU with forward references, resolved between the passes
+    deferred: Vec<Deferred>,
+    location: u32,
+    section: Section,
+    //Location counters of the sections that are not current
+    counters: BTreeMap<Section, u32>,
+    segments: Vec<Segment>,
+    entry: Option<(Section, u16)>,
+    relocations: Vec<Relocation>,
+    //PUBLIC names with the line that declared them
+    publics: Vec<(String, SourceLine, usize)>,
+    externals: Vec<String>,
+    macros: HashMap<String, Macro>,
+    frames: Vec<Frame>,
+    recording: Option<Recording>,
+    //Numbering for LOCAL names, unique over the whole assembly
+    locals: u32,
+    conditions: Vec<Condition>,
+    binaries: HashMap<PathBuf, Rc<Vec<u8>>>,
+    scope: Rc<str>,
+    //Addresses of the anonymous labels in definition order
+    anonymous: Vec<u16>,
+    listing: Vec<ListingLine>,
+    //Line being assembled, where symbols defined now are recorded as defined
+    current: SourceRef,
+    dialect: Dialect,
+    //Errors and warnings with the index of their line in the listing
+    diagnostics: Vec<(usize, Error)>,
+    //Symbols whose definition failed, uses of them are not reported again
+    failed: HashSet<String>,
+}
+
+struct Context<'a> {
+    symbols: &'a HashMap<String, Symbol>,
+    anonymous: &'a [u16],
+    position: &'a Position,
+}
+
+impl Scope for Context<'_> {
+    fn symbol(&self, name: &str) -> Option<i64> {
+        let name = qualify(name, &self.position.scope);
+        self.symbols.get(&name).map(|symbol| symbol.value)
+    }
+
+    fn location(&self) -> i64 {
+        self.position.address as i64
+    }
+
+    fn base(&self, name: &str) -> Option<Base> {
+        let name = qualify(name, &self.position.scope);
+        self.symbols
+            .get(&name)
+            .and_then(|symbol| symbol.base.clone())
+    }
+
+    fn location_base(&self) -> Option<Base> {
+        self.position.section.base()
+    }
+
+    fn anonymous(&self, offset: i64) -> Option<i64> {
+        let index = match offset {
+            0 => return None,
+            _ if offset < 0 => self.position.anonymous as i64 + offset,
+            _ => self.position.anonymous as i64 + offset - 1,
+        };
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| self.anonymous.get(index))
+            .map(|address| *address as i64)
+    }
+}
+
+fn is_local(name: &str) -> bool {
+    name.starts_with('.') || name.starts_with('@')
+}
+
+//Local labels are stored under the name of their global label, `MAIN.LOOP` for `.LOOP`
+fn qualify(name: &str, scope: &str) -> String {
+    if is_local(name) {
+        format!("{}{}", scope, name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn is_directive(name: &str) -> bool {
+    DIRECTIVES.contains(&name)
+        || macros::MACRO_DIRECTIVES.contains(&name)
+        || source::CONDITIONAL_DIRECTIVES.contains(&name)
+}
+
+fn is_operation(name: &str) -> bool {
+    is_directive(name) || instruction::lookup(name).is_some()
+}
+
+fn error(source: &SourceLine, error: impl Into<Located>) -> Error {
+    let error = error.into();
+    Error::new(&source.file, source.line, error.kind, &source.expansions)
+        .with_source(&source.text, error.span)
+}
+
+//Error in an operand, pointing at the symbol it is about when there is one
+fn operand_error(kind: ErrorKind, operand: &Operand) -> Located {
+    let text = operand.text.to_ascii_uppercase();
+    let start = kind
+        .subject()
+        .and_then(|name| Some((text.find(&name.to_ascii_uppercase())?, name)));
+    let span = match start {
+        Some((start, name)) => Span {
+            column: operand.column + operand.text[..start].chars().count(),
+            length: name.chars().count(),
+        },
+        None => operand.span(),
+    };
+    kind.at(span)
+}
+
+fn expect_operands(statement: &Statement, count: usize) -> Result<(), ErrorKind> {
+    if statement.operands.len() != count {
+        return Err(ErrorKind::OperandCount {
+            mnemonic: statement.operation.clone().unwrap_or_default(),
+            expected: count,
+            found: statement.operands.len(),
+        });
+    }
+    Ok(())
+}
+
+//Size of a DB operand: strings are emitted byte by byte, anything else is one byte
+fn db_size(operand: &Operand) -> u16 {
+    match operand.string() {
+        Some(bytes) if bytes.len() != 1 => bytes.len() as u16,
+        _ => 1,
+    }
+}
+
+/* Assemble a file, reporting every independent error.
+ * A failed run returns its errors and warnings in source order, a successful one keeps
+ * its warnings in the Assembly.
+ */
+pub fn assemble_file(path: &Path, options: &Options) -> Result<Assembly, Vec<Error>> {
+    let name = path.display().to_string();
+    let file_error = |error: std::io::Error| {
+        let kind = ErrorKind::FileRead(name.clone(), error.to_string());
+        vec![Error::new(&name, 0, kind, &[])]
+    };
+    let text = fs::read_to_string(path).map_err(file_error)?;
+    let canonical = fs::canonicalize(path).map_err(file_error)?;
+    assemble_frame(Frame::file(&name, &text, Some(canonical)), options)
+}
+
+fn assemble_frame(frame: Frame, options: &Options) -> Result<Assembly, Vec<Error>> {
+    let mut assembler = Assembler {
+        symbols: HashMap::new(),
+        entries: Vec::new(),
+        deferred: Vec::new(),
+        location: 0,
+        section: Section::Absolute,
+        counters: BTreeMap::new(),
+        segments: Vec::new(),
+        entry: None,
+        relocations: Vec::new(),
+        publics: Vec::new(),
+        externals: Vec::new(),
+        macros: HashMap::new(),
+        frames: Vec::new(),
+        recording: None,
+        locals: 0,
+        conditions: Vec::new(),
+        binaries: HashMap::new(),
+        scope: Rc::from(""),
+        anonymous: Vec::new(),
+        listing: Vec::new(),
+        current: SourceRef {
+            file: Rc::from("<command line>"),
+            line: 0,
+        },
+        dialect: options.dialect,
+        diagnostics: Vec::new(),
+        failed: HashSet::new(),
+    };
+    for (name, value) in &options.defines {
+        let kind = ErrorKind::DuplicateSymbol(name.clone());
+        if assembler
+            .define(&name.to_ascii_uppercase(), *value, SymbolKind::Equ, None)
+            .is_err()
+        {
+            let error = Error::new("<command line>", 0, kind, &[]);
+            assembler.diagnostics.push((0, error));
+        }
+    }
+    assembler.first_pass(frame);
+    assembler.resolve_deferred();
+    assembler.second_pass();
+    assembler.check_publics();
+    assembler.switch(Section::Absolute);
+
+    assembler.diagnostics.sort_by_key(|(index, _)| *index);
+    let diagnostics: Vec<Error> = assembler
+        .diagnostics
+        .drain(..)
+        .map(|(_, error)| error)
+        .collect();
+    if diagnostics
+        .iter()
+        .any(|error| error.severity == Severity::Error)
+    {
+        return Err(diagnostics);
+    }
+    let publics = assembler
+        .publics
+        .iter()
+        .map(|(name, _, _)| {
+            let symbol = &assembler.symbols[name];
+            let section = match symbol.base {
+                Some(Base::Code) => Section::Code,
+                Some(Base::Data) => Section::Data,
+                _ => Section::Absolute,
+            };
+            Public {
+                name: name.clone(),
+                section,
+                value: symbol.value as u16,
+            }
+        })
+        .collect();
+    let size = |section| {
+        assembler
+            .counters
+            .get(&section)
+            .map_or(0, |size| *size as u16)
+    };
+    Ok(Assembly {
+        code_size: size(Section::Code),
+        data_size: size(Section::Data),
+        relocations: assembler.relocations,
+        publics,
+        externals: assembler.externals,
+        segments: assembler.segments,
+        symbols: assembler
+            .symbols
+            .iter()
+            .map(|(name, symbol)| (name.clone(), symbol.value as u16))
+            .collect(),
+        entry: assembler.entry,
+        warnings: diagnostics,
+        listing: assembler.listing,
+        cross_reference: assembler
+            .symbols
+            .into_iter()
+            .map(|(name, symbol)| {
+                let reference = CrossReference {
+                    value: symbol.value as u16,
+                    defined: symbol.defined,
+                    references: symbol.references,
+                };
+                (name, reference)
+            })
+            .collect(),
+    })
+}
+
+impl Assembler {
+    fn position(&self) -> Position {
+        Position {
+            section: self.section,
+            address: self.location as u16,
+            scope: self.scope.clone(),
+            anonymous: self.anonymous.len(),
+        }
+    }
+
+    fn context<'a>(&'a self, position: &'a Position) -> Context<'a> {
+        Context {
+            symbols: &self.symbols,
+            anonymous: &self.anonymous,
+            position,
+        }
+    }
+
+    fn eval(&self, operand: &Operand, position: &Position) -> Result<i64, Located> {
+        Expr::parse(&operand.text)
+            .and_then(|expr| expr.eval(&self.context(position)))
+            .map_err(|error| match error {
+                ErrorKind::UndefinedSymbol(name) if is_local(&name) => {
+                    ErrorKind::UndefinedLocal(name, position.scope.to_string())
+                }
+                error => error,
+            })
+            .map_err(|error| operand_error(error, operand))
+    }
+
+    //Values that decide the layout must be known in the first pass
+    fn eval_now(&self, operand: &Operand) -> Result<i64, Located> {
+        self.eval(operand, &self.position()).map_err(|error| {
+            let kind = match error.kind {
+                ErrorKind::UndefinedLocal(name, scope) => {
+                    ErrorKind::ForwardReference(qualify(&name, &scope))
+                }
+                ErrorKind::UndefinedSymbol(name) => ErrorKind::ForwardReference(name),
+                kind => kind,
+            };
+            Located { kind, ..error }
+        })
+    }
+
+    //Keep a diagnostic, unless it is about a symbol whose definition already failed
+    fn report(&mut self, index: usize, error: Error) {
+        let name = match &error.kind {
+            ErrorKind::UndefinedSymbol(name) | ErrorKind::ForwardReference(name) => {
+                Some(name.clone())
+            }
+            ErrorKind::UndefinedLocal(name, scope) => Some(qualify(name, scope)),
+            _ => None,
+        };
+        if !name.is_some_and(|name| self.failed.contains(&name)) {
+            self.diagnostics.push((index, error));
+        }
+    }
+
+    fn define(
+        &mut self,
+        name: &str,
+        value: i64,
+        kind: SymbolKind,
+        base: Option<Base>,
+    ) -> Result<(), ErrorKind> {
+        match self.symbols.get(name) {
+            Some(symbol) if !(symbol.kind == SymbolKind::Set && kind == SymbolKind::Set) => {
+                Err(ErrorKind::DuplicateSymbol(name.to_string()))
+            }
+            Some(_) => {
+                let symbol = self.symbols.get_mut(name).expect("symbol was found");
+                symbol.value = value;
+                symbol.base = base;
+                symbol.defined = self.current.clone();
+                Ok(())
+            }
+            None => {
+                let symbol = Symbol {
+                    value,
+                    kind,
+                    base,
+                    defined: self.current.clone(),
+                    references: Vec::new(),
+                };
+                self.symbols.insert(name.to_string(), symbol);
+                Ok(())
+            }
+        }
+    }
+
+    //Errors are reported and the line is skipped, so one run finds all of them
+    fn first_pass(&mut self, frame: Frame) {
+        self.frames.push(frame);
+        while let Some(source) = self.next_line() {
+            self.current = SourceRef {
+                file: source.file.clone(),
+                line: source.line,
+            };
+            self.listing.push(ListingLine {
+                source: self.current.clone(),
+                depth: source.expansions.len(),
+                text: source.text.clone(),
+                section: Section::Absolute,
+                address: None,
+                value: None,
+                bytes: Vec::new(),
+                cycles: None,
+                skipped: false,
+            });
+            let (text, externals) = self.dialect.normalize(&source.text);
+            for name in externals {
+                if !self.symbols.contains_key(&name) {
+                    let base = Some(Base::External(name.clone()));
+                    let _ = self.define(&name, 0, SymbolKind::Extern, base);
+                    self.externals.push(name);
+                }
+            }
+            let source = SourceLine { text, ..source };
+            match self.process(&source) {
+                Ok(true) => break,
+                Ok(false) => (),
+                Err(located) => self.report(self.listing.len() - 1, error(&source, located)),
+            }
+        }
+        let end = self.listing.len();
+        if let Some(recording) = self.recording.take() {
+            let directive = match recording.block {
+                Block::Macro(..) => "MACRO",
+                Block::Rept(_) => "REPT",
+                Block::Irp(..) => "IRP",
+            };
+            let kind = ErrorKind::UnterminatedBlock(directive.to_string(), "ENDM");
+            self.report(end, error(&recording.start, kind));
+        }
+        while let Some(condition) = self.conditions.pop() {
+            let kind = ErrorKind::UnterminatedBlock("IF".to_string(), "ENDIF");
+            self.report(end, error(&condition.start, kind));
+        }
+    }
+
+    //IF blocks end in the file or expansion they were opened in
+    fn next_line(&mut self) -> Option<SourceLine> {
+        while let Some(frame) = self.frames.last_mut() {
+            if let Some(line) = frame.lines.get(frame.next) {
+                frame.next += 1;
+                return Some(line.clone());
+            }
+            while let Some(condition) = self.conditions.last() {
+                if condition.frame != self.frames.len() {
+                    break;
+                }
+                let kind = ErrorKind::UnterminatedBlock("IF".to_string(), "ENDIF");
+                let error = error(&condition.start, kind);
+                self.conditions.pop();
+                self.report(self.listing.len(), error);
+            }
+            self.frames.pop();
+        }
+        None
+    }
+
+    fn skipping(&self) -> bool {
+        self.conditions
+            .last()
+            .is_some_and(|condition| !condition.active)
+    }
+
+    //IF, ELSE and ENDIF, returns false for any other line
+    fn condition(&mut self, source: &SourceLine) -> Result<bool, Located> {
+        let directive = match source::find_directive(&source.text, &source::CONDITIONAL_DIRECTIVES)
+        {
+            Some(directive) => directive,
+            None => return Ok(false),
+        };
+        let frame = self.frames.len();
+        let current = match self.conditions.last_mut() {
+            Some(condition) if condition.frame == frame => Some(condition),
+            _ => None,
+        };
+        match (directive, current) {
+            ("ELSE", Some(condition)) if !condition.has_else => {
+                condition.active = condition.reachable && !condition.active;
+                condition.has_else = true;
+            }
+            ("ENDIF", Some(_)) => {
+                self.conditions.pop();
+            }
+            ("ELSE", _) | ("ENDIF", _) => {
+                return Err(ErrorKind::UnexpectedDirective(directive.to_string()).into())
+            }
+            _ => {
+                //Opened before the test, an IF that fails still pairs with its ENDIF
+                let reachable = !self.skipping();
+                self.conditions.push(Condition {
+                    active: false,
+                    reachable,
+                    has_else: false,
+                    frame,
+                    start: source.clone(),
+                });
+                let active = reachable && self.test(directive, source)?;
+                if let Some(condition) = self.conditions.last_mut() {
+                    condition.active = active;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    //Value of IF, IFDEF or IFNDEF
+    fn test(&self, directive: &str, source: &SourceLine) -> Result<bool, Located> {
+        let statement = parser::parse_line(&source.text, is_operation)?;
+        expect_operands(&statement, 1)?;
+        let operand = &statement.operands[0];
+        match directive {
+            "IF" => Ok(self.eval_now(operand)? != 0),
+            _ => {
+                let name = qualify(&operand.text.to_ascii_uppercase(), &self.scope);
+                Ok(self.symbols.contains_key(&name) == (directive == "IFDEF"))
+            }
+        }
+    }
+
+    //Handle one line of the first pass, returns true at END
+    fn process(&mut self, source: &SourceLine) -> Result<bool, Located> {
+        if self.record(source)? || self.condition(source)? {
+            return Ok(false);
+        }
+        if self.skipping() {
+            if let Some(line) = self.listing.last_mut() {
+                line.skipped = true;
+            }
+            return Ok(false);
+        }
+        //Listing directives take free text that need not parse
+        if self.dialect.ignores(&source.text) {
+            return Ok(false);
+        }
+        let macros = &self.macros;
+        let dialect = self.dialect;
+        let mut statement = parser::parse_line(&source.text, |name| {
+            is_operation(name)
+                || macros.contains_key(name)
+                || dialect.alias(name).is_some()
+                || dialect == Dialect::Zilog && zilog::is_mnemonic(name)
+        })?;
+        match statement
+            .operation
+            .as_deref()
+            .and_then(|name| dialect.alias(name))
+        {
+            Some(Alias::Directive(directive)) => statement.operation = Some(directive.to_string()),
+            Some(Alias::Ignored) => {
+                statement.operation = None;
+                statement.operands.clear();
+                statement.operand_text.clear();
+            }
+            None => (),
+        }
+        //Z80 SET with a bit number has no label, the directive needs one
+        let instruction = match statement.operation.as_deref() {
+            Some("SET") => statement.label.is_none(),
+            Some(name) => !is_directive(name) && !self.macros.contains_key(name),
+            None => false,
+        };
+        if dialect == Dialect::Zilog && instruction {
+            zilog::translate(&mut statement, &|operand| self.eval_now(operand))?;
+        }
+        let operation = statement.operation.clone().unwrap_or_default();
+        match operation.as_str() {
+            //Blocks with errors are still recorded, their body is not assembled on its own
+            "MACRO" => {
+                let parameters = statement
+                    .operands
+                    .iter()
+                    .map(|operand| operand.text.to_ascii_uppercase())
+                    .collect();
+                let name = match &statement.label {
+                    Some(name) if !is_operation(name) => name.clone(),
+                    _ => String::new(),
+                };
+                self.start_recording(Block::Macro(name, parameters), source);
+                match &statement.label {
+                    Some(name) if is_operation(name) => {
+                        return Err(ErrorKind::DuplicateSymbol(name.clone()).into())
+                    }
+                    None => return Err(ErrorKind::MissingLabel(operation).into()),
+                    _ => (),
+                }
+            }
+            "REPT" => {
+                self.start_recording(Block::Rept(0), source);
+                self.define_label(source, &statement)?;
+                expect_operands(&statement, 1)?;
+                let count = self.eval_now(&statement.operands[0])?;
+                if let Some(recording) = &mut self.recording {
+                    recording.block = Block::Rept(count);
+                }
+            }
+            "IRP" | "IRPC" => {
+                self.start_recording(Block::Irp(String::new(), Vec::new()), source);
+                self.define_label(source, &statement)?;
+                let mut arguments = macros::split_arguments(&statement.operand_text);
+                if arguments.len() < 2 {
+                    return Err(ErrorKind::OperandCount {
+                        mnemonic: operation,
+                        expected: 2,
+                        found: arguments.len(),
+                    }
+                    .into());
+                }
+                let parameter = arguments.remove(0).to_ascii_uppercase();
+                let values = if operation == "IRP" {
+                    //`IRP R,<B,C,D>` gives its values as one bracketed list
+                    match arguments.as_slice() {
+                        [list] => macros::split_arguments(list),
+                        _ => arguments,
+                    }
+                } else {
+                    macros::irpc_characters(&arguments.join(","))
+                };
+                if let Some(recording) = &mut self.recording {
+                    recording.block = Block::Irp(parameter, values);
+                }
+            }
+            "EXITM" if source.expansions.is_empty() => {
+                return Err(ErrorKind::UnexpectedDirective(operation).into())
+            }
+            "EXITM" => {
+                let frame = self.frames.len();
+                self.conditions.retain(|condition| condition.frame < frame);
+                self.frames.pop();
+            }
+            "INCLUDE" => {
+                let name = source::file_operand(&statement.operand_text)?;
+                let path = source::resolve(&source.file, &name);
+                let display = path.display().to_string();
+                let canonical = fs::canonicalize(&path)
+                    .map_err(|error| ErrorKind::FileRead(display.clone(), error.to_string()))?;
+                if self
+                    .frames
+                    .iter()
+                    .any(|frame| frame.path.as_ref() == Some(&canonical))
+                {
+                    return Err(ErrorKind::IncludeCycle(display).into());
+                }
+                let text = String::from_utf8_lossy(&source::read(&path)?).into_owned();
+                self.frames
+                    .push(Frame::file(&display, &text, Some(canonical)));
+            }
+            "ENDM" | "LOCAL" => return Err(ErrorKind::UnexpectedDirective(operation).into()),
+            name if self.macros.contains_key(name) => {
+                self.define_label(source, &statement)?;
+                let definition = self.macros[name].clone();
+                let arguments = macros::split_arguments(&statement.operand_text);
+                if arguments.len() > definition.parameters.len() {
+                    return Err(ErrorKind::MacroArguments {
+                        name: name.to_string(),
+                        expected: definition.parameters.len(),
+                        found: arguments.len(),
+                    }
+                    .into());
+                }
+                let bindings = definition
+                    .parameters
+                    .iter()
+                    .cloned()
+                    .zip(
+                        arguments
+                            .into_iter()
+                            .chain(std::iter::repeat(String::new())),
+                    )
+                    .collect();
+                self.push_expansion(name, source, &definition.body, vec![bindings])?;
+            }
+            _ => {
+                let end = operation == "END";
+                self.place(source, statement)?;
+                return Ok(end);
+            }
+        }
+        Ok(false)
+    }
+
+    fn start_recording(&mut self, block: Block, source: &SourceLine) {
+        self.recording = Some(Recording {
+            block,
+            start: source.clone(),
+            depth: 0,
+            body: Vec::new(),
+        });
+    }
+
+    //Collect lines of a block being defined, returns false when nothing is recorded
+    fn record(&mut self, source: &SourceLine) -> Result<bool, ErrorKind> {
+        let recording = match &mut self.recording {
+            Some(recording) => recording,
+            None => return Ok(false),
+        };
+        match macros::block_directive(&source.text) {
+            Some(directive) if macros::opens_block(directive) => recording.depth += 1,
+            Some("ENDM") if recording.depth > 0 => recording.depth -= 1,
+            Some("ENDM") => {
+                let recording = self.recording.take().expect("recording in progress");
+                self.finish_recording(recording)?;
+                return Ok(true);
+            }
+            _ => (),
+        }
+        recording.body.push(source.clone());
+        Ok(true)
+    }
+
+    fn finish_recording(&mut self, recording: Recording) -> Result<(), ErrorKind> {
+        let source = &recording.start;
+        match recording.block {
+            Block::Macro(name, _) if name.is_empty() => Ok(()),
+            Block::Macro(name, parameters) => {
+                let definition = Macro {
+                    parameters,
+                    body: recording.body,
+                };
+                self.macros.insert(name, definition);
+                Ok(())
+            }
+            Block::Rept(count) => {
+                let iterations = (0..count.max(0)).map(|_| Vec::new()).collect();
+                self.push_expansion("REPT", source, &recording.body, iterations)
+            }
+            Block::Irp(parameter, values) => {
+                let iterations = values
+                    .into_iter()
+                    .map(|value| vec![(parameter.clone(), value)])
+                    .collect();
+                self.push_expansion("IRP", source, &recording.body, iterations)
+            }
+        }
+    }
+
+    //Queue the body once for every set of parameter bindings
+    fn push_expansion(
+        &mut self,
+        name: &str,
+        call: &SourceLine,
+        body: &[SourceLine],
+        iterations: Vec<Vec<(String, String)>>,
+    ) -> Result<(), ErrorKind> {
+        if call.expansions.len() >= macros::MAX_DEPTH {
+            return Err(ErrorKind::ExpansionTooDeep(name.to_string()));
+        }
+        let mut expansions = vec![Expansion {
+            name: name.to_string(),
+            file: call.file.to_string(),
+            line: call.line,
+        }];
+        expansions.extend(call.expansions.iter().cloned());
+        let expansions = Rc::new(expansions);
+        let mut lines = Vec::new();
+        for bindings in iterations {
+            lines.extend(macros::expand(
+                body,
+                bindings,
+                &mut self.locals,
+                expansions.clone(),
+            )?);
+        }
+        self.frames.push(Frame::new(lines));
+        Ok(())
+    }
+
+    //Global labels open a new scope, except the generated names of macro LOCALs
+    fn define_label(
+        &mut self,
+        source: &SourceLine,
+        statement: &Statement,
+    ) -> Result<(), ErrorKind> {
+        if statement.anonymous_label {
+            self.anonymous.push(self.location as u16);
+        }
+        match &statement.label {
+            Some(label) => {
+                if !is_local(label) && !label.starts_with("??") {
+                    self.scope = Rc::from(label.as_str());
+                }
+                let name = qualify(label, &self.scope);
+                if statement.public_label {
+                    if !self.dialect.public_labels() {
+                        let message = "`::` labels need the M80 dialect".to_string();
+                        return Err(ErrorKind::Syntax(message));
+                    }
+                    let listed = self.listing.len() - 1;
+                    self.publics.push((name.clone(), source.clone(), listed));
+                }
+                let base = self.section.base();
+                self.define(&name, self.location as i64, SymbolKind::Label, base)
+            }
+            None => Ok(()),
+        }
+    }
+
+    //Define the statement label and advance the location counter
+    fn place(&mut self, source: &SourceLine, statement: Statement) -> Result<(), Located> {
+        let operation = statement.operation.as_deref().unwrap_or("");
+        if operation != "EQU" && operation != "SET" {
+            self.define_label(source, &statement)?;
+        }
+        let position = self.position();
+
+        let size = match operation {
+            "" | "END" => 0,
+            "ASEG" | "CSEG" | "DSEG" => {
+                expect_operands(&statement, 0)?;
+                self.switch(match operation {
+                    "CSEG" => Section::Code,
+                    "DSEG" => Section::Data,
+                    _ => Section::Absolute,
+                });
+                0
+            }
+            "PUBLIC" | "EXTERN" => {
+                for operand in &statement.operands {
+                    let name = operand.text.to_ascii_uppercase();
+                    if !name.chars().all(expr::is_identifier_char) || is_local(&name) {
+                        let message = format!("invalid symbol name `{}`", operand.text);
+                        return Err(ErrorKind::Syntax(message).at(operand.span()));
+                    }
+                    if operation == "PUBLIC" {
+                        self.publics
+                            .push((name, source.clone(), self.listing.len() - 1));
+                        continue;
+                    }
+                    let base = Some(Base::External(name.clone()));
+                    self.define(&name, 0, SymbolKind::Extern, base)
+                        .map_err(|kind| kind.at(operand.span()))?;
+                    self.externals.push(name);
+                }
+                0
+            }
+            "EQU" | "SET" => {
+                let name = statement
+                    .label
+                    .as_deref()
+                    .map(|label| qualify(label, &self.scope))
+                    .ok_or_else(|| ErrorKind::MissingLabel(operation.to_string()))?;
+                if let Err(error) = self.assign(&name, source, &statement, &position) {
+                    if !self.symbols.contains_key(&name) {
+                        self.failed.insert(name);
+                    }
+                    return Err(error);
+                }
+                0
+            }
+            "ORG" => {
+                expect_operands(&statement, 1)?;
+                let operand = &statement.operands[0];
+                let origin =
+                    to_word(self.eval_now(operand)?).map_err(|kind| kind.at(operand.span()))?;
+                self.location = origin as u32;
+                0
+            }
+            "DS" => {
+                expect_operands(&statement, 1)?;
+                let operand = &statement.operands[0];
+                let size = self.eval_now(operand)?;
+                if size < 0 {
+                    return Err(ErrorKind::NegativeSize(size).at(operand.span()));
+                }
+                to_word(size).map_err(|kind| kind.at(operand.span()))?
+            }
+            "DB" => statement.operands.iter().map(db_size).sum(),
+            "INCBIN" => {
+                let length = self.binary(source, &statement)?.len();
+                u16::try_from(length).map_err(|_| ErrorKind::AddressOverflow)?
+            }
+            "DW" => statement.operands.len() as u16 * 2,
+            _ => {
+                let (_, form) = instruction::lookup(operation)
+                    .ok_or_else(|| ErrorKind::UnknownMnemonic(operation.to_string()))?;
+                form.size()
+            }
+        };
+        if (operation == "DB" || operation == "DW") && statement.operands.is_empty() {
+            return Err(ErrorKind::OperandCount {
+                mnemonic: operation.to_string(),
+                expected: 1,
+                found: 0,
+            }
+            .into());
+        }
+        self.location += size as u32;
+        if self.location > 0x10000 {
+            return Err(ErrorKind::AddressOverflow.into());
+        }
+        self.entries.push(Entry {
+            source: source.clone(),
+            position,
+            statement,
+            listed: self.listing.len() - 1,
+        });
+        Ok(())
+    }
+
+    //Value of EQU and SET, EQUs with forward references wait for the end of the first pass
+    fn assign(
+        &mut self,
+        name: &str,
+        source: &SourceLine,
+        statement: &Statement,
+        position: &Position,
+    ) -> Result<(), Located> {
+        expect_operands(statement, 1)?;
+        let operand = &statement.operands[0];
+        let expr = Expr::parse(&operand.text).map_err(|kind| operand_error(kind, operand))?;
+        let kind = if statement.operation.as_deref() == Some("EQU") {
+            SymbolKind::Equ
+        } else {
+            SymbolKind::Set
+        };
+        match expr.eval(&self.context(position)) {
+            Ok(value) => {
+                let base = expr
+                    .base(&self.context(position))
+                    .map_err(|kind| operand_error(kind, operand))?;
+                self.define(name, value, kind, base)?
+            }
+            Err(ErrorKind::UndefinedSymbol(_)) if kind == SymbolKind::Equ => {
+                self.deferred.push(Deferred {
+                    name: name.to_string(),
+                    expr,
+                    source: source.clone(),
+                    operand: operand.clone(),
+                    position: position.clone(),
+                    listed: self.listing.len() - 1,
+                })
+            }
+            Err(ErrorKind::UndefinedSymbol(name)) => {
+                return Err(operand_error(ErrorKind::ForwardReference(name), operand))
+            }
+            Err(error) => return Err(operand_error(error, operand)),
+        }
+        Ok(())
+    }
+
+    //EQUs may refer to each other in any order, repeat until nothing changes
+    fn resolve_deferred(&mut self) {
+        while !self.deferred.is_empty() {
+            let pending = std::mem::take(&mut self.deferred);
+            let count = pending.len();
+            for deferred in pending {
+                self.current = SourceRef {
+                    file: deferred.source.file.clone(),
+                    line: deferred.source.line,
+                };
+                let context = self.context(&deferred.position);
+                match deferred.expr.eval(&context) {
+                    Ok(value) => {
+                        let defined = deferred.expr.base(&context).and_then(|base| {
+                            self.define(&deferred.name, value, SymbolKind::Equ, base)
+                        });
+                        if let Err(kind) = defined {
+                            self.report(deferred.listed, error(&deferred.source, kind));
+                        }
+                    }
+                    Err(ErrorKind::UndefinedSymbol(_)) => self.deferred.push(deferred),
+                    Err(kind) => self.unresolved(&deferred, kind),
+                }
+            }
+            //Undefined names, or EQUs that refer to each other in a cycle
+            if self.deferred.len() == count {
+                for deferred in std::mem::take(&mut self.deferred) {
+                    let kind = deferred
+                        .expr
+                        .eval(&self.context(&deferred.position))
+                        .expect_err("deferred EQU stays unresolved");
+                    self.unresolved(&deferred, kind);
+                }
+            }
+        }
+    }
+
+    fn unresolved(&mut self, deferred: &Deferred, kind: ErrorKind) {
+        let located = operand_error(kind, &deferred.operand);
+        self.report(deferred.listed, error(&deferred.source, located));
+        self.failed.insert(deferred.name.clone());
+    }
+
+    //Contents of an INCBIN file, read once for both passes
+    fn binary(
+        &mut self,
+        source: &SourceLine,
+        statement: &Statement,
+    ) -> Result<Rc<Vec<u8>>, ErrorKind> {
+        let name = source::file_operand(&statement.operand_text)?;
+        let path = source::resolve(&source.file, &name);
+        if let Some(data) = self.binaries.get(&path) {
+            return Ok(data.clone());
+        }
+        let data = Rc::new(source::read(&path)?);
+        self.binaries.insert(path, data.clone());
+        Ok(data)
+    }
+
+    fn emit(&mut self, section: Section, address: u16, bytes: &[u8]) {
+        match self.segments.last_mut() {
+            Some(segment)
+                if segment.section == section
+                    && segment.address as usize + segment.data.len() == address as usize =>
+            {
+                segment.data.extend_from_slice(bytes)
+            }
+            _ => self.segments.push(Segment {
+                section,
+                address,
+                data: bytes.to_vec(),
+            }),
+        }
+    }
+
+    fn second_pass(&mut self) {
+        let entries = std::mem::take(&mut self.entries);
+        //JMP, RET or PCHL that ended the flow, until a label or data makes the code reachable
+        let mut ended: Option<&str> = None;
+        for entry in &entries {
+            self.current = self.listing[entry.listed].source.clone();
+            let statement = &entry.statement;
+            let operation = statement.operation.as_deref().unwrap_or("");
+            let is_instruction = instruction::lookup(operation).is_some();
+            if statement.label.is_some() || statement.anonymous_label || !is_instruction {
+                ended = ended.filter(|_| operation.is_empty() && statement.label.is_none());
+            } else if let Some(after) = ended.take() {
+                let kind = ErrorKind::Unreachable(after.to_string());
+                self.report(entry.listed, error(&entry.source, kind).warning());
+            }
+            if matches!(operation, "JMP" | "RET" | "PCHL") {
+                ended = Some(operation);
+            }
+
+            let mut warnings = Vec::new();
+            let bytes = match self.generate(entry, &mut warnings) {
+                Ok(bytes) => bytes,
+                Err(located) => {
+                    self.report(entry.listed, error(&entry.source, located));
+                    Vec::new()
+                }
+            };
+            for warning in warnings {
+                self.report(entry.listed, error(&entry.source, warning).warning());
+            }
+            if !bytes.is_empty() {
+                self.emit(entry.position.section, entry.position.address, &bytes);
+            }
+            self.list(entry, bytes);
+        }
+        self.entries = entries;
+    }
+
+    //Fill in the listing line of an entry and note the symbols it refers to
+    fn list(&mut self, entry: &Entry, bytes: Vec<u8>) {
+        let statement = &entry.statement;
+        let operation = statement.operation.as_deref().unwrap_or("");
+        let scope = &entry.position.scope;
+        let value = match operation {
+            "EQU" | "SET" => statement
+                .label
+                .as_deref()
+                .and_then(|label| self.symbols.get(&qualify(label, scope)))
+                .map(|symbol| symbol.value as u16),
+            "ORG" => self
+                .eval(&statement.operands[0], &entry.position)
+                .ok()
+                .map(|origin| origin as u16),
+            _ => None,
+        };
+        let cycles = match (instruction::lookup(operation), bytes.first()) {
+            (Some(_), Some(opcode)) => Some(OPCODES[*opcode as usize].cycles),
+            _ => None,
+        };
+        if operation != "INCBIN" {
+            for operand in &statement.operands {
+                if operation == "DB" && operand.string().is_some() {
+                    continue;
+                }
+                let expr = match Expr::parse(&operand.text) {
+                    Ok(expr) => expr,
+                    Err(_) => continue,
+                };
+                let mut names = Vec::new();
+                expr.symbols(&mut names);
+                for name in names {
+                    if let Some(symbol) = self.symbols.get_mut(&qualify(name, scope)) {
+                        if symbol.references.last() != Some(&self.current) {
+                            symbol.references.push(self.current.clone());
+                        }
+                    }
+                }
+            }
+        }
+        let line = &mut self.listing[entry.listed];
+        if !operation.is_empty() || statement.label.is_some() || statement.anonymous_label {
+            line.section = entry.position.section;
+            line.address = Some(entry.position.address);
+        }
+        line.value = value;
+        line.bytes = bytes;
+        line.cycles = cycles;
+    }
+
+    fn generate(&mut self, entry: &Entry, warnings: &mut Vec<Located>) -> Result<Vec<u8>, Located> {
+        let statement = &entry.statement;
+        let position = &entry.position;
+        let operation = statement.operation.as_deref().unwrap_or("");
+        let mut bytes = Vec::new();
+        match operation {
+            "" | "ORG" | "EQU" | "DS" | "ASEG" | "CSEG" | "DSEG" | "PUBLIC" | "EXTERN" => (),
+            //SET symbols take the value of the latest definition in source order
+            "SET" => {
+                let operand = &statement.operands[0];
+                let value = self.eval(operand, position)?;
+                let base = self.base(operand, position)?;
+                let label = statement.label.as_deref().unwrap_or_default();
+                let name = qualify(label, &position.scope);
+                self.define(&name, value, SymbolKind::Set, base)?;
+            }
+            "END" => {
+                if let Some(operand) = statement.operands.first() {
+                    let entry = to_word(self.eval(operand, position)?)
+                        .map_err(|kind| kind.at(operand.span()))?;
+                    let section = match self.base(operand, position)? {
+                        None => Section::Absolute,
+                        Some(Base::Code) => Section::Code,
+                        Some(Base::Data) => Section::Data,
+                        Some(Base::External(_)) => {
+                            return Err(ErrorKind::Relocation.at(operand.span()))
+                        }
+                    };
+                    self.entry = Some((section, entry));
+                }
+            }
+            "DB" => {
+                for operand in &statement.operands {
+                    match operand.string() {
+                        Some(string) if string.len() != 1 => bytes.extend(string),
+                        _ => {
+                            let value = self.eval(operand, position)?;
+                            self.relocate(operand, position, bytes.len(), false)?;
+                            bytes.push(instruction::byte(value, operand, warnings)?);
+                        }
+                    }
+                }
+            }
+            "INCBIN" => bytes = self.binary(&entry.source, statement)?.to_vec(),
+            "DW" => {
+                for operand in &statement.operands {
+                    let value = to_word(self.eval(operand, position)?)
+                        .map_err(|kind| kind.at(operand.span()))?;
+                    self.relocate(operand, position, bytes.len(), true)?;
+                    bytes.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            _ => {
+                let (opcode, form) = instruction::lookup(operation)
+                    .ok_or_else(|| ErrorKind::UnknownMnemonic(operation.to_string()))?;
+                let eval = |operand: &Operand| self.eval(operand, position);
+                bytes = instruction::encode(
+                    operation,
+                    opcode,
+                    form,
+                    &statement.operands,
+                    &eval,
+                    warnings,
+                )?;
+                if let Some(operand) = form.data_operand().map(|i| &statement.operands[i]) {
+                    self.relocate(operand, position, 1, form.size() == 3)?;
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn base(&self, operand: &Operand, position: &Position) -> Result<Option<Base>, Located> {
+        Expr::parse(&operand.text)
+            .and_then(|expr| expr.base(&self.context(position)))
+            .map_err(|kind| operand_error(kind, operand))
+    }
+
+    //Record a relocatable word at `offset` in the statement, bytes cannot be relocated
+    fn relocate(
+        &mut self,
+        operand: &Operand,
+        position: &Position,
+        offset: usize,
+        word: bool,
+    ) -> Result<(), Located> {
+        match self.base(operand, position)? {
+            None => Ok(()),
+            Some(_) if !word => Err(ErrorKind::RelocatableByte.at(operand.span())),
+            Some(base) => {
+                self.relocations.push(Relocation {
+                    section: position.section,
+                    offset: position.address.wrapping_add(offset as u16),
+                    base,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    //Keep the location counter of the current section and continue in another one
+    fn switch(&mut self, section: Section) {
+        self.counters.insert(self.section, self.location);
+        self.location = self.counters.get(&section).copied().unwrap_or(0);
+        self.section = section;
+    }
+
+    //PUBLIC names have to be defined in the module, and cannot be external
+    fn check_publics(&mut self) {
+        let publics = std::mem::take(&mut self.publics);
+        for (name, source, listed) in &publics {
+            match self.symbols.get(name) {
+                Some(symbol) if symbol.kind != SymbolKind::Extern => (),
+                _ => {
+                    let kind = ErrorKind::UndefinedPublic(name.clone());
+                    self.report(*listed, error(source, kind));
+                }
+            }
+        }
+        self.publics = publics;
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt::{self, Write};
+
+use super::{expr::Base, Segment};
+
+/* Relocatable object module, a line oriented text format:
+ * I8080OBJ 1
+ * MODULE name
+ * SIZE code data
+ * EXTERN name
+ * PUBLIC name section value
+ * ENTRY section value
+ * BYTES section offset hex...
+ * RELOC section offset CODE|DATA|EXTERN name
+ * Numbers are hexadecimal, offsets are relative to the start of the section.
+ */
+const MAGIC: &str = "I8080OBJ";
+const VERSION: u32 = 1;
+const BYTES_PER_LINE: usize = 32;
+
+//Where a piece of a module goes: ASEG, CSEG or DSEG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Section {
+    Absolute,
+    Code,
+    Data,
+}
+
+impl Section {
+    pub fn name(self) -> &'static str {
+        match self {
+            Section::Absolute => "ABS",
+            Section::Code => "CODE",
+            Section::Data => "DATA",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "ABS" => Some(Section::Absolute),
+            "CODE" => Some(Section::Code),
+            "DATA" => Some(Section::Data),
+            _ => None,
+        }
+    }
+
+    //Base of the addresses in the section, None for absolute ones
+    pub fn base(self) -> Option<Base> {
+        match self {
+            Section::Absolute => None,
+            Section::Code => Some(Base::Code),
+            Section::Data => Some(Base::Data),
+        }
+    }
+}
+
+//Word at `offset` in `section` that gets the address of `base` added when linked
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub section: Section,
+    pub offset: u16,
+    pub base: Base,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Public {
+    pub name: String,
+    pub section: Section,
+    pub value: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub name: String,
+    pub code_size: u16,
+    pub data_size: u16,
+    pub segments: Vec<Segment>,
+    pub relocations: Vec<Relocation>,
+    pub publics: Vec<Public>,
+    pub externals: Vec<String>,
+    pub entry: Option<(Section, u16)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn base_name(base: &Base) -> String {
+    match base {
+        Base::Code => "CODE".to_string(),
+        Base::Data => "DATA".to_string(),
+        Base::External(name) => format!("EXTERN {}", name),
+    }
+}
+
+impl Object {
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{} {}", MAGIC, VERSION).unwrap();
+        writeln!(out, "MODULE {}", self.name).unwrap();
+        writeln!(out, "SIZE {:04X} {:04X}", self.code_size, self.data_size).unwrap();
+        for name in &self.externals {
+            writeln!(out, "EXTERN {}", name).unwrap();
+        }
+        for public in &self.publics {
+            let section = public.section.name();
+            writeln!(
+                out,
+                "PUBLIC {} {} {:04X}",
+                public.name, section, public.value
+            )
+            .unwrap();
+        }
+        if let Some((section, offset)) = self.entry {
+            writeln!(out, "ENTRY {} {:04X}", section.name(), offset).unwrap();
+        }
+        for segment in &self.segments {
+            for (i, chunk) in segment.data.chunks(BYTES_PER_LINE).enumerate() {
+                let offset = segment.address as usize + i * BYTES_PER_LINE;
+                let hex: String = chunk.iter().map(|b| format!("{:02X}", b)).collect();
+                writeln!(
+                    out,
+                    "BYTES {} {:04X} {}",
+                    segment.section.name(),
+                    offset,
+                    hex
+                )
+                .unwrap();
+            }
+        }
+        for relocation in &self.relocations {
+            writeln!(
+                out,
+                "RELOC {} {:04X} {}",
+                relocation.section.name(),
+                relocation.offset,
+                base_name(&relocation.base)
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    pub fn parse(text: &str) -> Result<Object, ObjectError> {
+        let mut object = Object {
+            name: String::new(),
+            code_size: 0,
+            data_size: 0,
+            segments: Vec::new(),
+            relocations: Vec::new(),
+            publics: Vec::new(),
+            externals: Vec::new(),
+            entry: None,
+        };
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, header)) if header.trim() == format!("{} {}", MAGIC, VERSION) => (),
+            Some((_, header)) if header.starts_with(MAGIC) => {
+                return Err(ObjectError {
+                    line: 1,
+                    message: format!("unsupported object version `{}`", header.trim()),
+                })
+            }
+            _ => {
+                return Err(ObjectError {
+                    line: 1,
+                    message: "not an object module".to_string(),
+                })
+            }
+        }
+        for (index, line) in lines {
+            let error = |message: &str| ObjectError {
+                line: index + 1,
+                message: message.to_string(),
+            };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let section = |i: usize| {
+                fields
+                    .get(i)
+                    .and_then(|name| Section::parse(name))
+                    .ok_or_else(|| error("invalid section"))
+            };
+            let number = |i: usize| {
+                fields
+                    .get(i)
+                    .and_then(|text| u16::from_str_radix(text, 16).ok())
+                    .ok_or_else(|| error("invalid number"))
+            };
+            let name = |i: usize| {
+                fields
+                    .get(i)
+                    .map(|name| name.to_string())
+                    .ok_or_else(|| error("missing name"))
+            };
+            match fields.first().copied() {
+                None => (),
+                Some("MODULE") => object.name = name(1)?,
+                Some("SIZE") => {
+                    object.code_size = number(1)?;
+                    object.data_size = number(2)?;
+                }
+                Some("EXTERN") => object.externals.push(name(1)?),
+                Some("PUBLIC") => object.publics.push(Public {
+                    name: name(1)?,
+                    section: section(2)?,
+                    value: number(3)?,
+                }),
+                Some("ENTRY") => object.entry = Some((section(1)?, number(2)?)),
+                Some("BYTES") => {
+                    let hex = fields.get(3).copied().unwrap_or("");
+                    if hex.len() % 2 != 0 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                        return Err(error("invalid bytes"));
+                    }
+                    let data = (0..hex.len())
+                        .step_by(2)
+                        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(0))
+                        .collect();
+                    object.segments.push(Segment {
+                        section: section(1)?,
+                        address: number(2)?,
+                        data,
+                    });
+                }
+                Some("RELOC") => {
+                    let base = match fields.get(3).copied() {
+                        Some("CODE") => Base::Code,
+                        Some("DATA") => Base::Data,
+                        Some("EXTERN") => Base::External(name(4)?),
+                        _ => return Err(error("invalid relocation base")),
+                    };
+                    object.relocations.push(Relocation {
+                        section: section(1)?,
+                        offset: number(2)?,
+                        base,
+                    });
+                }
+                Some(record) => return Err(error(&format!("unknown record `{}`", record))),
+            }
+        }
+        Ok(object)
+    }
+}
@@ -0,0 +1,227 @@
+use super::{
+    error::{ErrorKind, Located, Span},
+    expr::{is_identifier_char, is_identifier_start},
+};
+
+//One source line split into its fields: [label[:]] [operation [operand, ...]] [; comment]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub label: Option<String>,
+    //A lone `:` in the label field, referenced as `:+` and `:-`
+    pub anonymous_label: bool,
+    //`LABEL::` in M80 sources, the label is also PUBLIC
+    pub public_label: bool,
+    pub operation: Option<String>,
+    pub operands: Vec<Operand>,
+    //Operand field as written, for directives that split it on their own
+    pub operand_text: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Operand {
+    pub text: String,
+    pub column: usize,
+}
+
+impl Operand {
+    pub fn span(&self) -> Span {
+        Span {
+            column: self.column,
+            length: self.text.chars().count(),
+        }
+    }
+
+    //A bare quoted string, as accepted by DB
+    pub fn string(&self) -> Option<Vec<u8>> {
+        let chars: Vec<char> = self.text.chars().collect();
+        match chars.first() {
+            Some('\'') | Some('"') => match super::expr::read_string(&chars) {
+                Some((bytes, length)) if length == chars.len() => Some(bytes),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+//Directives that take the statement label as the name they define
+const NAMING_DIRECTIVES: [&str; 3] = ["EQU", "SET", "MACRO"];
+
+fn read_identifier(chars: &[char], start: usize) -> usize {
+    let mut end = start;
+    while end < chars.len() && is_identifier_char(chars[end]) {
+        end += 1;
+    }
+    end
+}
+
+//One character at index `i` of the line
+fn unexpected(chars: &[char], i: usize) -> Located {
+    let span = Span {
+        column: i + 1,
+        length: 1,
+    };
+    ErrorKind::Syntax(format!("unexpected `{}`", chars[i])).at(span)
+}
+
+fn skip_whitespace(chars: &[char], mut i: usize) -> usize {
+    while i < chars.len() && chars[i].is_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+//Cut the line at the first `;` outside of quotes
+fn strip_comment(chars: &[char]) -> &[char] {
+    let mut quote = None;
+    for (i, c) in chars.iter().enumerate() {
+        match (quote, *c) {
+            (None, ';') => return &chars[..i],
+            (None, '\'') | (None, '"') => quote = Some(*c),
+            (Some(q), c) if q == c => quote = None,
+            _ => (),
+        }
+    }
+    chars
+}
+
+fn field(chars: &[char], start: usize, end: usize) -> Result<Operand, Located> {
+    let field: String = chars[start..end].iter().collect();
+    let trimmed = field.trim_start();
+    let column = start + (field.len() - trimmed.len()) + 1;
+    let text = trimmed.trim_end().to_string();
+    if text.is_empty() {
+        let span = Span {
+            column: start + 1,
+            length: end - start,
+        };
+        return Err(ErrorKind::Syntax("empty operand".to_string()).at(span));
+    }
+    Ok(Operand { text, column })
+}
+
+//Split operands on commas outside of quotes and parentheses
+fn split_operands(chars: &[char], start: usize) -> Result<Vec<Operand>, Located> {
+    let mut operands = Vec::new();
+    let mut quote = None;
+    let mut quote_start = 0;
+    let mut depth = 0;
+    let mut field_start = start;
+    for (i, c) in chars.iter().enumerate().skip(start) {
+        match (quote, *c) {
+            (Some(q), c) if q == c => quote = None,
+            (Some(_), _) => (),
+            (None, '\'') | (None, '"') => {
+                quote = Some(*c);
+                quote_start = i;
+            }
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, ',') if depth <= 0 => {
+                operands.push(field(chars, field_start, i)?);
+                field_start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if quote.is_some() {
+        let span = Span {
+            column: quote_start + 1,
+            length: chars.len() - quote_start,
+        };
+        return Err(ErrorKind::Syntax("unterminated string".to_string()).at(span));
+    }
+    operands.push(field(chars, field_start, chars.len())?);
+    Ok(operands)
+}
+
+pub fn parse_line(line: &str, is_operation: impl Fn(&str) -> bool) -> Result<Statement, Located> {
+    let chars: Vec<char> = line.chars().collect();
+    let chars = strip_comment(&chars);
+    let mut statement = Statement {
+        label: None,
+        anonymous_label: false,
+        public_label: false,
+        operation: None,
+        operands: Vec::new(),
+        operand_text: String::new(),
+    };
+
+    let mut i = skip_whitespace(chars, 0);
+    if chars.get(i) == Some(&':') {
+        statement.anonymous_label = true;
+        i = skip_whitespace(chars, i + 1);
+        if i == chars.len() {
+            return Ok(statement);
+        }
+        return parse_operation(chars, i, statement);
+    }
+    if i == chars.len() {
+        return Ok(statement);
+    }
+    if !is_identifier_start(chars[i]) {
+        return Err(unexpected(chars, i));
+    }
+    let first_column = i;
+    let end = read_identifier(chars, i);
+    let first: String = chars[i..end].iter().collect();
+    i = end;
+
+    let next = skip_whitespace(chars, i);
+    let second_end = read_identifier(chars, next);
+    let second = chars[next..second_end]
+        .iter()
+        .collect::<String>()
+        .to_ascii_uppercase();
+    let is_label = chars.get(i) == Some(&':')
+        || NAMING_DIRECTIVES.contains(&second.as_str())
+        || (first_column == 0 && !is_operation(&first.to_ascii_uppercase()));
+    if is_label {
+        statement.label = Some(first.to_ascii_uppercase());
+        if chars.get(i) == Some(&':') {
+            i += 1;
+            if chars.get(i) == Some(&':') {
+                statement.public_label = true;
+                i += 1;
+            }
+        }
+        i = skip_whitespace(chars, i);
+        if i == chars.len() {
+            return Ok(statement);
+        }
+        return parse_operation(chars, i, statement);
+    }
+    statement.operation = Some(first.to_ascii_uppercase());
+    parse_operands(chars, i, statement)
+}
+
+//Operation word at `i` and the operands after it
+fn parse_operation(
+    chars: &[char],
+    i: usize,
+    mut statement: Statement,
+) -> Result<Statement, Located> {
+    if !is_identifier_start(chars[i]) {
+        return Err(unexpected(chars, i));
+    }
+    let end = read_identifier(chars, i);
+    let operation: String = chars[i..end].iter().collect();
+    statement.operation = Some(operation.to_ascii_uppercase());
+    parse_operands(chars, end, statement)
+}
+
+fn parse_operands(
+    chars: &[char],
+    mut i: usize,
+    mut statement: Statement,
+) -> Result<Statement, Located> {
+    if i < chars.len() && !chars[i].is_whitespace() {
+        return Err(unexpected(chars, i));
+    }
+    i = skip_whitespace(chars, i);
+    if i < chars.len() {
+        statement.operand_text = chars[i..].iter().collect::<String>().trim_end().to_string();
+        statement.operands = split_operands(chars, i)?;
+    }
+    Ok(statement)
+}
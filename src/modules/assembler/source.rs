@@ -0,0 +1,117 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use super::{error::ErrorKind, error::Expansion, expr::read_string};
+
+//Source text with the place it was written at and the macro calls it came from
+#[derive(Debug, Clone)]
+pub struct SourceLine {
+    pub file: Rc<str>,
+    pub line: usize,
+    pub text: String,
+    pub expansions: Rc<Vec<Expansion>>,
+}
+
+//Lines are read from the top frame, macro calls and INCLUDE push a new one
+#[derive(Debug)]
+pub struct Frame {
+    pub lines: Vec<SourceLine>,
+    pub next: usize,
+    //Canonical path of an included file, used to detect include cycles
+    pub path: Option<PathBuf>,
+}
+
+impl Frame {
+    pub fn new(lines: Vec<SourceLine>) -> Self {
+        Self {
+            lines,
+            next: 0,
+            path: None,
+        }
+    }
+
+    pub fn file(name: &str, text: &str, path: Option<PathBuf>) -> Self {
+        let file: Rc<str> = Rc::from(name);
+        let expansions = Rc::new(Vec::new());
+        let lines = text
+            .lines()
+            .enumerate()
+            .map(|(number, text)| SourceLine {
+                file: file.clone(),
+                line: number + 1,
+                text: text.to_string(),
+                expansions: expansions.clone(),
+            })
+            .collect();
+        Self {
+            lines,
+            next: 0,
+            path,
+        }
+    }
+}
+
+//One IF level, `frame` is the depth of the frame stack where it was opened
+#[derive(Debug)]
+pub struct Condition {
+    pub active: bool,
+    //The enclosing level is active, ELSE can only switch on a level in an active region
+    pub reachable: bool,
+    pub has_else: bool,
+    pub frame: usize,
+    pub start: SourceLine,
+}
+
+pub const CONDITIONAL_DIRECTIVES: [&str; 5] = ["IF", "IFDEF", "IFNDEF", "ELSE", "ENDIF"];
+
+/* Directive on a raw line, found without parsing it: lines that are skipped or still
+ * hold macro parameters are not valid statements.
+ * It is the first word, or the second one after a label or macro name.
+ */
+pub fn find_directive(text: &str, directives: &[&'static str]) -> Option<&'static str> {
+    let code = text.split(';').next().unwrap_or("");
+    let mut words = code
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .filter(|word| !word.is_empty());
+    let find = |word: Option<&str>| {
+        let word = word?.to_ascii_uppercase();
+        directives.iter().find(|d| **d == word).copied()
+    };
+    let first = words.next();
+    find(first).or_else(|| find(words.next()))
+}
+
+//File name operand, quotes are optional
+pub fn file_operand(text: &str) -> Result<String, ErrorKind> {
+    let text = text.trim();
+    let chars: Vec<char> = text.chars().collect();
+    let name = match chars.first() {
+        Some('\'') | Some('"') => match read_string(&chars) {
+            Some((bytes, length)) if length == chars.len() => {
+                bytes.into_iter().map(|b| b as char).collect()
+            }
+            _ => return Err(ErrorKind::Syntax("invalid file name".to_string())),
+        },
+        _ => text.to_string(),
+    };
+    if name.is_empty() {
+        return Err(ErrorKind::Syntax("file name expected".to_string()));
+    }
+    Ok(name)
+}
+
+//Included files are looked up next to the file that includes them
+pub fn resolve(including: &str, name: &str) -> PathBuf {
+    match Path::new(including).parent() {
+        Some(directory) => directory.join(name),
+        None => PathBuf::from(name),
+    }
+}
+
+pub fn read(path: &Path) -> Result<Vec<u8>, ErrorKind> {
+    fs::read(path)
+        .map_err(|error| ErrorKind::FileRead(path.display().to_string(), error.to_string()))
+}
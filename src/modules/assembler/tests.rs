@@ -0,0 +1,872 @@
+use std::{env, fs, process};
+
+use crate::modules::{
+    linker::{self, LinkError, LinkOptions},
+    opcodes::{self, Category, OPCODES},
+};
+
+use super::{
+    analysis::{self, Access, Reference},
+    assemble_file, assemble_frame,
+    dialect::Dialect,
+    disassembler::{self, Syntax},
+    error::{Error, ErrorKind, Severity},
+    expr::Base,
+    instruction, listing,
+    object::{Public, Relocation, Section},
+    source::Frame,
+    Assembly, Options,
+};
+
+fn assemble(text: &str, dialect: Dialect) -> Result<Assembly, Vec<Error>> {
+    let options = Options {
+        dialect,
+        ..Options::default()
+    };
+    assemble_frame(Frame::file("test.asm", text, None), &options)
+}
+
+fn errors(text: &str, dialect: Dialect) -> Vec<ErrorKind> {
+    match assemble(text, dialect) {
+        Ok(_) => Vec::new(),
+        Err(errors) => errors.into_iter().map(|error| error.kind).collect(),
+    }
+}
+
+const DIGITAL_RESEARCH: &str = "\
+\tTITLE\t'CP/M hello, don't panic'
+\tPAGE
+* print a message through the BDOS
+BDOS\tEQU\t0005H
+PRINT$STRING EQU 9
+\tORG\t100H
+START:\tMVI\tC,PRINT$STRING
+\tLXI\tD,MESSAGE
+\tCALL\tBDOS
+\tLXI\tH,0FFFF$H
+\tRET
+MESSAGE: DB\t'Hello, world$'
+\tEND\tSTART
+";
+
+#[test]
+fn digital_research_source() {
+    let assembly = assemble(DIGITAL_RESEARCH, Dialect::DigitalResearch).unwrap();
+    let mut expected = vec![
+        0x0E, 0x09, 0x11, 0x0C, 0x01, 0xCD, 0x05, 0x00, 0x21, 0xFF, 0xFF, 0xC9,
+    ];
+    expected.extend_from_slice(b"Hello, world$");
+    assert_eq!(assembly.to_binary(), expected);
+    assert_eq!(assembly.symbols["PRINTSTRING"], 9);
+    assert_eq!(assembly.entry, Some((Section::Absolute, 0x100)));
+}
+
+const M80: &str = "\
+\tTITLE\tHello module
+\tSUBTTL\tIt's relocatable
+\t.8080
+\tPAGE\t60
+\tEXTRN\tPRINT
+\tPUBLIC\tBUFFER
+\tCSEG
+START::\tLXI\tH,MESSAGE
+\tJMP\tPRINT
+MESSAGE: DEFM\t'Hi'
+\tDEFB\t0
+\tDEFW\tSTART
+\tCALL\tEXIT##
+\tDSEG
+BUFFER:\tDEFS\t16
+\tASEG
+\tORG\t0
+\tJMP\tSTART
+\tEND\tSTART
+";
+
+#[test]
+fn m80_source() {
+    let object = assemble(M80, Dialect::M80).unwrap().to_object("hello");
+    assert_eq!(object.code_size, 0x0E);
+    assert_eq!(object.data_size, 0x10);
+    assert_eq!(object.segments[0].section, Section::Code);
+    assert_eq!(
+        object.segments[0].data,
+        vec![0x21, 0x06, 0x00, 0xC3, 0x00, 0x00, 0x48, 0x69, 0x00, 0x00, 0x00, 0xCD, 0x00, 0x00]
+    );
+    assert_eq!(object.segments[1].section, Section::Absolute);
+    assert_eq!(object.segments[1].data, vec![0xC3, 0x00, 0x00]);
+
+    let relocation = |section, offset, base| Relocation {
+        section,
+        offset,
+        base,
+    };
+    assert_eq!(
+        object.relocations,
+        vec![
+            relocation(Section::Code, 0x01, Base::Code),
+            relocation(Section::Code, 0x04, Base::External("PRINT".to_string())),
+            relocation(Section::Code, 0x09, Base::Code),
+            relocation(Section::Code, 0x0C, Base::External("EXIT".to_string())),
+            relocation(Section::Absolute, 0x01, Base::Code),
+        ]
+    );
+    assert_eq!(object.externals, vec!["PRINT", "EXIT"]);
+    let public = |name: &str, section| Public {
+        name: name.to_string(),
+        section,
+        value: 0,
+    };
+    assert_eq!(
+        object.publics,
+        vec![
+            public("BUFFER", Section::Data),
+            public("START", Section::Code)
+        ]
+    );
+    assert_eq!(object.entry, Some((Section::Code, 0)));
+}
+
+#[test]
+fn dialect_directives_need_their_mode() {
+    assert_eq!(
+        errors("\tDEFB\t1\n", Dialect::Intel),
+        vec![ErrorKind::UnknownMnemonic("DEFB".to_string())]
+    );
+    assert!(matches!(
+        errors("START::\tNOP\n", Dialect::Intel).as_slice(),
+        [ErrorKind::Syntax(_)]
+    ));
+    //`$` is the location counter unless it separates a name or number
+    let assembly = assemble("\tORG\t10H\n\tDW\t$+2\n", Dialect::DigitalResearch).unwrap();
+    assert_eq!(assembly.to_binary(), vec![0x12, 0x00]);
+}
+
+const ZILOG: &str = "\
+\tORG\t100H
+START:\tLD\tSP,STACK
+\tLD\tHL,MESSAGE
+LOOP:\tLD\tA,(HL)
+\tOR\tA
+\tJP\tZ,DONE
+\tOUT\t(1),A
+\tINC\tHL
+\tJR\tLOOP
+DONE:\tRST\t38H
+MESSAGE: DB\t'OK',0
+STACK\tEQU\t0FF00H
+";
+
+#[test]
+fn zilog_source() {
+    let text = ZILOG.replace("\tJR\tLOOP", "\tJP\tLOOP");
+    let assembly = assemble(&text, Dialect::Zilog).unwrap();
+    assert_eq!(
+        assembly.to_binary(),
+        vec![
+            0x31, 0x00, 0xFF, 0x21, 0x12, 0x01, 0x7E, 0xB7, 0xCA, 0x11, 0x01, 0xD3, 0x01, 0x23,
+            0xC3, 0x06, 0x01, 0xFF, b'O', b'K', 0x00
+        ]
+    );
+    assert_eq!(
+        errors(ZILOG, Dialect::Zilog),
+        vec![ErrorKind::NotIntel8080("JR LOOP".to_string())]
+    );
+    let errors = errors(
+        "\tLD\tBC,(1234H)\n\tLD\tA,(IX+2)\n\tMOV\tA,B\n\tRST\t9\n\tSET\t3,A\n\tLD\tHL,SP\n",
+        Dialect::Zilog,
+    );
+    assert_eq!(
+        errors,
+        vec![
+            ErrorKind::NotIntel8080("LD BC,(1234H)".to_string()),
+            ErrorKind::NotIntel8080("LD A,(IX+2)".to_string()),
+            ErrorKind::UnknownMnemonic("MOV".to_string()),
+            ErrorKind::RstAddress(9),
+            ErrorKind::NotIntel8080("SET 3,A".to_string()),
+            ErrorKind::NotIntel8080("LD HL,SP".to_string()),
+        ]
+    );
+}
+
+//Every documented opcode disassembles to text that assembles back to it, in both syntaxes
+#[test]
+fn disassembly_reassembles() {
+    for opcode in 0..=0xFF {
+        let bytes = [opcode, 0x34, 0x12];
+        for (syntax, dialect) in [
+            (Syntax::Intel, Dialect::Intel),
+            (Syntax::Zilog, Dialect::Zilog),
+        ] {
+            let (text, length) = match disassembler::instruction(&bytes, syntax) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            let assembly = assemble(&format!("\t{}\n", text), dialect)
+                .unwrap_or_else(|errors| panic!("`{}`: {}", text, errors[0]));
+            assert_eq!(assembly.to_binary(), &bytes[..length], "`{}`", text);
+        }
+    }
+    assert_eq!(
+        disassembler::instruction(&[0xC2, 0x34, 0x12], Syntax::Zilog),
+        Some(("JP NZ,1234H".to_string(), 3))
+    );
+    assert_eq!(
+        disassembler::instruction(&[0x0A], Syntax::Zilog),
+        Some(("LD A,(BC)".to_string(), 1))
+    );
+    assert_eq!(disassembler::instruction(&[0xCB], Syntax::Intel), None);
+    assert_eq!(
+        disassembler::instruction(&[0x3A, 0x00], Syntax::Intel),
+        None
+    );
+}
+
+#[test]
+fn disassemble_range() {
+    let bytes = [0x31, 0x00, 0xFF, 0xC5, 0xC1, 0x41, 0x08, 0x3A, 0x00];
+    let lines: Vec<(u16, Vec<u8>, String)> =
+        disassembler::disassemble(&bytes, 0xFFFC, Syntax::Intel)
+            .map(|line| (line.address, line.bytes, line.text))
+            .collect();
+    let line = |address, bytes: &[u8], text: &str| (address, bytes.to_vec(), text.to_string());
+    assert_eq!(
+        lines,
+        vec![
+            line(0xFFFC, &[0x31, 0x00, 0xFF], "LXI SP,0FF00H"),
+            line(0xFFFF, &[0xC5], "PUSH B"),
+            line(0x0000, &[0xC1], "POP B"),
+            line(0x0001, &[0x41], "MOV B,C"),
+            line(0x0002, &[0x08], "DB 08H"),
+            line(0x0003, &[0x3A], "DB 3AH"),
+            line(0x0004, &[0x00], "NOP"),
+        ]
+    );
+}
+
+const ROM: &str = "\
+\tORG\t0
+\tJMP\tSTART
+\tDB\t0FFH,0FFH,0FFH,0FFH,0FFH
+\tRET
+START:\tLXI\tH,MESSAGE
+LOOP:\tMOV\tA,M
+\tORA\tA
+\tRZ
+\tOUT\t1
+\tINX\tH
+\tJMP\tLOOP
+\tLHLD\tTABLE
+\tPCHL
+MESSAGE: DB\t'Hello',0
+TABLE:\tDW\tSTART
+";
+
+#[test]
+fn recursive_disassembly() {
+    let binary = assemble(ROM, Dialect::Intel).unwrap().to_binary();
+    let analysis = analysis::analyze(&binary, 0, &[], Syntax::Intel);
+    let lines: Vec<(u16, &str)> = analysis
+        .lines
+        .iter()
+        .map(|line| (line.address, line.text.as_str()))
+        .collect();
+    assert_eq!(
+        lines,
+        vec![
+            (0x00, "JMP L0009"),
+            (0x03, "DB 0FFH,0FFH,0FFH,0FFH,0FFH,0C9H"),
+            (0x09, "LXI H,0019H"),
+            (0x0C, "MOV A,M"),
+            (0x0D, "ORA A"),
+            (0x0E, "RZ"),
+            (0x0F, "OUT 01H"),
+            (0x11, "INX H"),
+            (0x12, "JMP L000C"),
+            (0x15, "DB 2AH,1FH,00H,0E9H"),
+            (0x19, "DB 'Hello'"),
+            (0x1E, "DB 00H"),
+            (0x1F, "DW L0009"),
+        ]
+    );
+    let labels: Vec<&str> = analysis.labels.values().map(String::as_str).collect();
+    assert_eq!(labels, vec!["L0000", "L0009", "L000C"]);
+    assert!(analysis.unresolved.is_empty());
+
+    let analysis = analysis::analyze(&binary, 0, &[0x15], Syntax::Zilog);
+    assert_eq!(analysis.unresolved, vec![0x18]);
+    assert_eq!(analysis.lines[9].text, "LD HL,(001FH)");
+    assert_eq!(analysis.labels[&0x15], "L0015");
+}
+
+//Numbers of a linear congruential generator, bytes no assembler produced
+fn noise(seed: u32, length: usize) -> Vec<u8> {
+    let mut state = seed;
+    (0..length)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+//Disassembled source of every binary assembles back to the same bytes, in both syntaxes
+#[test]
+fn disassembly_source_round_trip() {
+    let mut corpus = vec![
+        (0, assemble(ROM, Dialect::Intel).unwrap().to_binary()),
+        (
+            0x100,
+            assemble(DIGITAL_RESEARCH, Dialect::DigitalResearch)
+                .unwrap()
+                .to_binary(),
+        ),
+        (
+            0x100,
+            assemble(&ZILOG.replace("JR", "JP"), Dialect::Zilog)
+                .unwrap()
+                .to_binary(),
+        ),
+        (
+            0x100,
+            (0..=0xFF)
+                .flat_map(|opcode| vec![opcode, 0x34, 0x01])
+                .collect(),
+        ),
+    ];
+    for seed in 1..=8 {
+        corpus.push((0, noise(seed, 1024)));
+        corpus.push((0x100, noise(seed * 7919, 512)));
+    }
+    for (origin, binary) in &corpus {
+        for (syntax, dialect) in [
+            (Syntax::Intel, Dialect::Intel),
+            (Syntax::Zilog, Dialect::Zilog),
+        ] {
+            let source = analysis::analyze(binary, *origin, &analysis::VECTORS, syntax).source();
+            let assembly = assemble(&source, dialect)
+                .unwrap_or_else(|errors| panic!("{}\n{}", source, errors[0]));
+            assert_eq!(&assembly.to_binary(), binary, "{}", source);
+        }
+    }
+    let source = analysis::analyze(&[0xC3, 0x03, 0x00, 0xC9], 0, &[], Syntax::Intel).source();
+    assert_eq!(
+        source,
+        "\tORG\t0000H\nL0000:\tJMP\tL0003\nL0003:\tRET\n\tEND\n"
+    );
+}
+
+const ROUTINES: &str = "\
+\tORG\t0
+START:\tLXI\tSP,STACK
+\tCALL\tINIT
+\tCALL\tPRINT
+\tHLT
+INIT:\tLDA\tFLAG
+\tSTA\tFLAG
+\tJMP\tPRINT
+PRINT:\tIN\t2
+\tOUT\t3
+\tRET
+FLAG:\tDB\t0
+STACK\tEQU\t100H
+";
+
+#[test]
+fn cross_reference() {
+    let binary = assemble(ROUTINES, Dialect::Intel).unwrap().to_binary();
+    let analysis = analysis::analyze(&binary, 0, &[], Syntax::Intel);
+    let reference = |from, to, access| Reference { from, to, access };
+    assert_eq!(
+        analysis.references,
+        vec![
+            reference(0x00, 0x100, Access::Address),
+            reference(0x03, 0x0A, Access::Call),
+            reference(0x06, 0x13, Access::Call),
+            reference(0x0A, 0x18, Access::Read),
+            reference(0x0D, 0x18, Access::Write),
+            reference(0x10, 0x13, Access::Jump),
+            reference(0x13, 0x02, Access::Input),
+            reference(0x15, 0x03, Access::Output),
+        ]
+    );
+    assert_eq!(
+        analysis.cross_reference(),
+        "\
+000AH  L000A  call     0003H
+0013H  L0013  call     0006H
+0013H  L0013  jump     0010H
+0018H         read     000AH
+0018H         write    000DH
+0100H         address  0000H
+  02H  port   input    0013H
+  03H  port   output   0015H
+"
+    );
+    assert_eq!(
+        analysis.call_graph(),
+        "\
+digraph calls {
+    node [shape=box];
+    L0000;
+    L000A;
+    L0013;
+    L0000 -> L000A;
+    L0000 -> L0013;
+    L000A -> L0013 [style=dashed];
+}
+"
+    );
+}
+
+#[test]
+fn opcode_table() {
+    let undocumented: Vec<usize> = (0..256)
+        .filter(|opcode| !OPCODES[*opcode].documented)
+        .collect();
+    assert_eq!(
+        undocumented,
+        vec![0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38, 0xCB, 0xD9, 0xDD, 0xED, 0xFD]
+    );
+    assert_eq!(OPCODES[0xCB].mnemonic, "JMP");
+    assert_eq!(OPCODES[0xFD].mnemonic, "CALL");
+    assert_eq!(OPCODES[0x34].cycles, (10, 10));
+    assert_eq!(OPCODES[0xC4].cycles, (17, 11));
+    assert_eq!(opcodes::flag_names(OPCODES[0x3C].flags), "SZAP-");
+    assert_eq!(opcodes::flag_names(OPCODES[0xF1].flags), "SZAPC");
+    assert_eq!(opcodes::flag_names(OPCODES[0x09].flags), "----C");
+    assert_eq!(OPCODES[0xDB].category, Category::InputOutput);
+    //Forms the assembler derives from the table have its lengths
+    for opcode in 0..=0xFF {
+        if let Some(decoded) = instruction::decode(&[opcode, 0, 0]) {
+            let (_, form) = instruction::lookup(decoded.mnemonic).unwrap();
+            assert_eq!(form.size(), OPCODES[opcode as usize].length as u16);
+        }
+    }
+}
+
+//Words of the assembled image, little endian
+fn words(text: &str) -> Vec<u16> {
+    let binary = assemble(text, Dialect::Intel).unwrap().to_binary();
+    binary
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect()
+}
+
+#[test]
+fn expressions() {
+    let precedence = "\
+\tORG\t100H
+\tDW\t2+3*4, (2+3)*4, 10-2-3, 17 MOD 5 + 100/7
+\tDW\t1 SHL 4 OR 1, 1 OR 2 AND 0, NOT 1 EQ 1, 5 GT 3, 3 <> 3
+\tDW\t-1, ~0FFH, HIGH 1234H + 1, LOW (1234H + 1), 8000H SHR 15
+HERE:\tDW\t$, $-HERE+5
+";
+    assert_eq!(
+        words(precedence),
+        vec![14, 20, 5, 16, 0x11, 1, 0, 0xFFFF, 0, 0xFFFF, 0xFF00, 0x13, 0x35, 1, 0x11C, 5]
+    );
+    //Relations compare unsigned 16-bit values
+    let relations = "\tDW\t-1 GE 0FFFFH, -1 EQ 0FFFFH, -1 LT 0, 0 LT -1, -2 LE -1\n";
+    assert_eq!(words(relations), vec![0xFFFF, 0xFFFF, 0, 0xFFFF, 0xFFFF]);
+    let literals = "\tDW\t'A', 'AB', '''', 0FFH, 101B, 17O, 17Q, 99D, 99, 0x1F\n";
+    assert_eq!(
+        words(literals),
+        vec![0x41, 0x4142, 0x27, 0xFF, 5, 15, 15, 99, 99, 0x1F]
+    );
+    assert_eq!(
+        errors(
+            "\tDW\t1/0\n\tDW\t1 +\n\tDW\t'ABC'\n\tDW\t12H3\n\tDB\t1 SHL -1\n",
+            Dialect::Intel
+        ),
+        vec![
+            ErrorKind::DivisionByZero,
+            ErrorKind::Syntax("expression expected".to_string()),
+            ErrorKind::InvalidString,
+            ErrorKind::InvalidNumber("12H3".to_string()),
+            ErrorKind::NegativeShift(-1),
+        ]
+    );
+}
+
+const MACROS: &str = "\
+STORE\tMACRO\tREG,ADDR
+\tMOV\tA,REG
+\tSTA\tADDR
+\tENDM
+WAIT\tMACRO\tN
+\tLOCAL\tLOOP
+\tMVI\tB,N
+LOOP:\tDCR\tB
+\tJNZ\tLOOP
+\tENDM
+FIRST\tMACRO\tX
+\tDB\tX
+\tIF\tX EQ 0
+\tEXITM
+\tENDIF
+\tDB\tX+1
+\tENDM
+\tSTORE\tB,1234H
+\tWAIT\t3
+\tWAIT\t2
+\tFIRST\t0
+\tFIRST\t5
+\tREPT\t3
+\tNOP
+\tENDM
+\tIRP\tR,<B,C,D>
+\tINR\tR
+\tENDM
+\tIRP\tR,E,H
+\tDCR\tR
+\tENDM
+\tIRPC\tX,AB
+\tDB\t'&X'
+\tENDM
+";
+
+#[test]
+fn macros() {
+    let assembly = assemble(MACROS, Dialect::Intel).unwrap();
+    assert_eq!(
+        assembly.to_binary(),
+        vec![
+            0x78, 0x32, 0x34, 0x12, 0x06, 0x03, 0x05, 0xC2, 0x06, 0x00, 0x06, 0x02, 0x05, 0xC2,
+            0x0C, 0x00, 0x00, 0x05, 0x06, 0x00, 0x00, 0x00, 0x04, 0x0C, 0x14, 0x1D, 0x25, b'A',
+            b'B'
+        ]
+    );
+    //Every expansion gets its own LOOP
+    assert_eq!(assembly.symbols["??0001"], 0x06);
+    assert_eq!(assembly.symbols["??0002"], 0x0C);
+
+    assert_eq!(
+        errors(
+            "SELF\tMACRO\n\tSELF\n\tENDM\n\tSELF\n\tEXITM\n\tREPT\t2\n",
+            Dialect::Intel
+        ),
+        vec![
+            ErrorKind::ExpansionTooDeep("SELF".to_string()),
+            ErrorKind::UnexpectedDirective("EXITM".to_string()),
+            ErrorKind::UnterminatedBlock("REPT".to_string(), "ENDM"),
+        ]
+    );
+    let text = MACROS.replace("\tSTORE\tB,1234H", "\tSTORE\tB,1234H,5");
+    assert_eq!(
+        errors(&text, Dialect::Intel),
+        vec![ErrorKind::MacroArguments {
+            name: "STORE".to_string(),
+            expected: 2,
+            found: 3
+        }]
+    );
+}
+
+const CONDITIONAL: &str = "\
+\tIF\tDEBUG
+\tDB\t1
+\tELSE
+\tDB\t2
+\tENDIF
+\tIFDEF\tDEBUG
+\tIF\tLEVEL GT 1
+\tDB\t3
+\tENDIF
+\tENDIF
+\tIFNDEF\tMISSING
+\tINCLUDE\t'part.asm'
+\tENDIF
+\tINCBIN\tdata.bin
+";
+
+#[test]
+fn conditional_assembly_and_files() {
+    let directory = env::temp_dir().join(format!("i8080-riir-include-{}", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let file = |name: &str, data: &[u8]| {
+        let path = directory.join(name);
+        fs::write(&path, data).unwrap();
+        path
+    };
+    let main = file("main.asm", CONDITIONAL.as_bytes());
+    file("part.asm", b"\tDB\tLEVEL\n");
+    file("data.bin", &[0xAA, 0xBB]);
+    let run = |defines: &[(&str, i64)]| {
+        let options = Options {
+            defines: defines
+                .iter()
+                .map(|(name, value)| (name.to_string(), *value))
+                .collect(),
+            ..Options::default()
+        };
+        assemble_file(&main, &options).map(|assembly| assembly.to_binary())
+    };
+    assert_eq!(
+        run(&[("debug", -1), ("LEVEL", 2)]),
+        Ok(vec![0x01, 0x03, 0x02, 0xAA, 0xBB])
+    );
+    assert_eq!(
+        run(&[("DEBUG", 0), ("LEVEL", 1)]),
+        Ok(vec![0x02, 0x01, 0xAA, 0xBB])
+    );
+    let kinds = run(&[]).unwrap_err().into_iter().map(|error| error.kind);
+    assert_eq!(
+        kinds.collect::<Vec<_>>(),
+        vec![
+            ErrorKind::ForwardReference("DEBUG".to_string()),
+            ErrorKind::UndefinedSymbol("LEVEL".to_string()),
+        ]
+    );
+
+    file("first.asm", b"\tNOP\n\tINCLUDE\tsecond.asm\n");
+    file("second.asm", b"\tINCLUDE\tfirst.asm\n");
+    let cycle = assemble_file(&directory.join("first.asm"), &Options::default()).unwrap_err();
+    assert!(matches!(
+        &cycle[0].kind,
+        ErrorKind::IncludeCycle(path) if path.ends_with("first.asm")
+    ));
+    assert_eq!(cycle.len(), 1);
+    fs::remove_dir_all(&directory).unwrap();
+
+    assert_eq!(
+        errors("\tELSE\n\tIF\t1\n", Dialect::Intel),
+        vec![
+            ErrorKind::UnexpectedDirective("ELSE".to_string()),
+            ErrorKind::UnterminatedBlock("IF".to_string(), "ENDIF"),
+        ]
+    );
+}
+
+const LABELS: &str = "\
+FIRST:\tMVI\tB,2
+.LOOP:\tDCR\tB
+\tJNZ\t.LOOP
+SECOND:\tMVI\tB,3
+@LOOP:\tDCR\tB
+\tJNZ\t@LOOP
+:\tDCR\tC
+\tJNZ\t:-
+\tJMP\t:+
+\tNOP
+:\tRET
+\tJMP\t:--
+\tJMP\tFIRST.LOOP
+";
+
+#[test]
+fn local_and_anonymous_labels() {
+    let assembly = assemble(LABELS, Dialect::Intel).unwrap();
+    assert_eq!(
+        assembly.to_binary(),
+        vec![
+            0x06, 0x02, 0x05, 0xC2, 0x02, 0x00, 0x06, 0x03, 0x05, 0xC2, 0x08, 0x00, 0x0D, 0xC2,
+            0x0C, 0x00, 0xC3, 0x14, 0x00, 0x00, 0xC9, 0xC3, 0x0C, 0x00, 0xC3, 0x02, 0x00
+        ]
+    );
+    assert_eq!(assembly.symbols["FIRST.LOOP"], 0x02);
+    assert_eq!(assembly.symbols["SECOND@LOOP"], 0x08);
+
+    let text = "\tCALL\t.START\nA1:\tNOP\n.IN:\tNOP\nB1:\tCALL\t.IN\n\tCALL\t:+\n\tCALL\t:-\n";
+    assert_eq!(
+        errors(text, Dialect::Intel),
+        vec![
+            ErrorKind::UndefinedLocal(".START".to_string(), String::new()),
+            ErrorKind::UndefinedLocal(".IN".to_string(), "B1".to_string()),
+            ErrorKind::UndefinedSymbol(":+".to_string()),
+            ErrorKind::UndefinedSymbol(":-".to_string()),
+        ]
+    );
+    assert_eq!(
+        errors("A1:\tNOP\n.X:\tNOP\n.X:\tNOP\n", Dialect::Intel),
+        vec![ErrorKind::DuplicateSymbol("A1.X".to_string())]
+    );
+}
+
+const LISTED: &str = "\
+PORT\tEQU\t10H
+TWICE\tMACRO
+\tINR\tA
+\tENDM
+\tORG\t100H
+START:\tIN\tPORT
+\tTWICE
+\tIF\t0
+\tNOP
+\tENDIF
+\tCZ\tSTART
+\tDB\t'Hello',0
+";
+
+#[test]
+fn listing_columns() {
+    let assembly = assemble(LISTED, Dialect::Intel).unwrap();
+    //Location, bytes, cycles and line number are columns of fixed width
+    assert_eq!(
+        listing::render(&assembly),
+        "\
+LOC   OBJECT       CYCLES  LINE  SOURCE
+=0010                         1  PORT\tEQU\t10H
+                              2  TWICE\tMACRO
+                              3  \tINR\tA
+                              4  \tENDM
+=0100                         5  \tORG\t100H
+0100  DB 10            10     6  START:\tIN\tPORT
+                              7  \tTWICE
+0102  3C                5     3+ \tINR\tA
+                              8  \tIF\t0
+                              9- \tNOP
+                             10  \tENDIF
+0103  CC 00 01      17/11    11  \tCZ\tSTART
+0106  48 65 6C 6C            12  \tDB\t'Hello',0
+010A  6F 00
+
+SYMBOLS
+PORT                     0010
+START                    0100
+
+CROSS REFERENCE
+PORT                     0010  1# 6
+START                    0100  6# 11
+"
+    );
+}
+
+const DIAGNOSTICS: &str = "\
+\tMVI\tA,300
+\tMVI\tQ,1
+\tJMP\tNOWHERE
+\tRET
+\tLXI\tH,10000H
+DONE:\tPCHL
+\tNOP
+\tRET
+\tNOP
+";
+
+#[test]
+fn diagnostics() {
+    //Every line is checked, errors and warnings come out in source order
+    let diagnostics = assemble(DIAGNOSTICS, Dialect::Intel).unwrap_err();
+    let found: Vec<(usize, ErrorKind, Severity, usize, usize)> = diagnostics
+        .iter()
+        .map(|error| {
+            let span = error.span.unwrap();
+            let kind = error.kind.clone();
+            (error.line, kind, error.severity, span.column, span.length)
+        })
+        .collect();
+    let after = |name: &str| ErrorKind::Unreachable(name.to_string());
+    assert_eq!(
+        found,
+        vec![
+            (1, ErrorKind::Truncated(300), Severity::Warning, 8, 3),
+            (
+                2,
+                ErrorKind::InvalidRegister("Q".to_string()),
+                Severity::Error,
+                6,
+                1
+            ),
+            (
+                3,
+                ErrorKind::UndefinedSymbol("NOWHERE".to_string()),
+                Severity::Error,
+                6,
+                7
+            ),
+            (4, after("JMP"), Severity::Warning, 2, 3),
+            (5, after("RET"), Severity::Warning, 2, 12),
+            (5, ErrorKind::WordRange(0x10000), Severity::Error, 8, 6),
+            (7, after("PCHL"), Severity::Warning, 2, 3),
+            (9, after("RET"), Severity::Warning, 2, 3),
+        ]
+    );
+    assert_eq!(
+        diagnostics[2].to_string(),
+        "test.asm:3:6: error: undefined symbol `NOWHERE`\n    3 | \tJMP\tNOWHERE\n      | \t   \t^^^^^^^"
+    );
+    //Warnings alone do not fail the run
+    let assembly = assemble("\tRET\n\tNOP\nDONE:\tNOP\n", Dialect::Intel).unwrap();
+    let warnings: Vec<String> = assembly.warnings.iter().map(Error::to_string).collect();
+    assert_eq!(
+        warnings,
+        vec!["test.asm:2:2: warning: unreachable code after `RET`\n    2 | \tNOP\n      | \t^^^"]
+    );
+}
+
+const MAIN_MODULE: &str = "\
+\tPUBLIC\tSTART
+\tEXTERN\tPRINT
+\tCSEG
+START:\tLXI\tH,BUFFER
+\tCALL\tPRINT
+\tHLT
+\tDSEG
+BUFFER:\tDS\t4
+\tEND\tSTART
+";
+
+#[test]
+fn linking() {
+    let module = |name: &str, text: &str| assemble(text, Dialect::Intel).unwrap().to_object(name);
+    let main = module("main", MAIN_MODULE);
+    let print = module("print", "\tPUBLIC\tPRINT\n\tCSEG\nPRINT:\tRET\n");
+    let image = linker::link(&[main.clone(), print.clone()], &LinkOptions::default()).unwrap();
+    assert_eq!(
+        image.to_binary(),
+        vec![0x21, 0x08, 0x01, 0xCD, 0x07, 0x01, 0x76, 0xC9]
+    );
+    assert_eq!(
+        image.map(),
+        "\
+MODULE           CODE       DATA
+main             0100-0106  0108-010B
+print            0107-0107  -
+
+SYMBOL           ADDRESS MODULE
+PRINT            0107    print
+START            0100    main
+
+ENTRY 0100
+"
+    );
+    let options = LinkOptions {
+        code: 0,
+        data: Some(0x8000),
+    };
+    let image = linker::link(&[main.clone(), print.clone()], &options).unwrap();
+    assert_eq!(
+        &image.to_binary()[..6],
+        &[0x21, 0x00, 0x80, 0xCD, 0x07, 0x00]
+    );
+
+    let again = module("again", "\tPUBLIC\tPRINT\n\tCSEG\nPRINT:\tNOP\n");
+    let patch = module("patch", "\tORG\t102H\n\tDB\t0\n");
+    let options = LinkOptions::default();
+    assert_eq!(
+        linker::link(std::slice::from_ref(&main), &options),
+        Err(vec![LinkError::UndefinedSymbol {
+            name: "PRINT".to_string(),
+            module: "main".to_string()
+        }])
+    );
+    let errors = linker::link(&[main, print, again, patch], &options).unwrap_err();
+    assert_eq!(
+        errors,
+        vec![
+            LinkError::DuplicateSymbol {
+                name: "PRINT".to_string(),
+                first: "print".to_string(),
+                second: "again".to_string()
+            },
+            LinkError::Overlap {
+                address: 0x102,
+                first: "main".to_string(),
+                second: "patch".to_string()
+            },
+        ]
+    );
+    assert_eq!(errors[1].to_string(), "`main` and `patch` overlap at 0102H");
+}
@@ -0,0 +1,399 @@
+use super::{
+    error::{ErrorKind, Located},
+    expr::parse_number,
+    instruction::{hex, Decoded},
+    parser::{Operand, Statement},
+};
+
+/* Zilog mnemonics of the 8080 subset of the Z80, as
+ * (Zilog mnemonic, Zilog operands, Intel mnemonic, Intel operands).
+ * Source in Zilog syntax is translated to Intel syntax through this table and disassembled
+ * instructions are translated back with it. An operand pattern is either a fixed name or:
+ * r  register, (HL) for M
+ * p  register pair BC, DE, HL or SP
+ * s  register pair BC, DE, HL or AF for PSW
+ * n  byte, (n) port
+ * w  word, (w) word in memory
+ * c  condition, part of the Intel mnemonic
+ * v  restart address, the vector number in Intel syntax
+ * The first rule of an Intel instruction is the one used to write it.
+ */
+const RULES: [(&str, &str, &str, &str); 80] = [
+    ("NOP", "", "NOP", ""),
+    ("HALT", "", "HLT", ""),
+    ("DI", "", "DI", ""),
+    ("EI", "", "EI", ""),
+    ("RLCA", "", "RLC", ""),
+    ("RRCA", "", "RRC", ""),
+    ("RLA", "", "RAL", ""),
+    ("RRA", "", "RAR", ""),
+    ("DAA", "", "DAA", ""),
+    ("CPL", "", "CMA", ""),
+    ("SCF", "", "STC", ""),
+    ("CCF", "", "CMC", ""),
+    ("LD", "r,r", "MOV", "r,r"),
+    ("LD", "r,n", "MVI", "r,n"),
+    ("LD", "p,w", "LXI", "p,w"),
+    ("LD", "A,(BC)", "LDAX", "B"),
+    ("LD", "A,(DE)", "LDAX", "D"),
+    ("LD", "(BC),A", "STAX", "B"),
+    ("LD", "(DE),A", "STAX", "D"),
+    ("LD", "A,(w)", "LDA", "w"),
+    ("LD", "(w),A", "STA", "w"),
+    ("LD", "HL,(w)", "LHLD", "w"),
+    ("LD", "(w),HL", "SHLD", "w"),
+    ("LD", "SP,HL", "SPHL", ""),
+    ("EX", "DE,HL", "XCHG", ""),
+    ("EX", "(SP),HL", "XTHL", ""),
+    ("PUSH", "s", "PUSH", "s"),
+    ("POP", "s", "POP", "s"),
+    ("INC", "r", "INR", "r"),
+    ("INC", "p", "INX", "p"),
+    ("DEC", "r", "DCR", "r"),
+    ("DEC", "p", "DCX", "p"),
+    ("ADD", "HL,p", "DAD", "p"),
+    ("ADD", "A,r", "ADD", "r"),
+    ("ADD", "A,n", "ADI", "n"),
+    ("ADC", "A,r", "ADC", "r"),
+    ("ADC", "A,n", "ACI", "n"),
+    ("SUB", "r", "SUB", "r"),
+    ("SUB", "n", "SUI", "n"),
+    ("SBC", "A,r", "SBB", "r"),
+    ("SBC", "A,n", "SBI", "n"),
+    ("AND", "r", "ANA", "r"),
+    ("AND", "n", "ANI", "n"),
+    ("XOR", "r", "XRA", "r"),
+    ("XOR", "n", "XRI", "n"),
+    ("OR", "r", "ORA", "r"),
+    ("OR", "n", "ORI", "n"),
+    ("CP", "r", "CMP", "r"),
+    ("CP", "n", "CPI", "n"),
+    //The accumulator operand written the other way around
+    ("ADD", "r", "ADD", "r"),
+    ("ADD", "n", "ADI", "n"),
+    ("ADC", "r", "ADC", "r"),
+    ("ADC", "n", "ACI", "n"),
+    ("SUB", "A,r", "SUB", "r"),
+    ("SUB", "A,n", "SUI", "n"),
+    ("SBC", "r", "SBB", "r"),
+    ("SBC", "n", "SBI", "n"),
+    ("AND", "A,r", "ANA", "r"),
+    ("AND", "A,n", "ANI", "n"),
+    ("XOR", "A,r", "XRA", "r"),
+    ("XOR", "A,n", "XRI", "n"),
+    ("OR", "A,r", "ORA", "r"),
+    ("OR", "A,n", "ORI", "n"),
+    ("CP", "A,r", "CMP", "r"),
+    ("CP", "A,n", "CPI", "n"),
+    ("JP", "(HL)", "PCHL", ""),
+    ("JP", "w", "JMP", "w"),
+    ("JP", "c,w", "J", "w"),
+    ("CALL", "w", "CALL", "w"),
+    ("CALL", "c,w", "C", "w"),
+    ("RET", "", "RET", ""),
+    ("RET", "c", "R", ""),
+    ("RST", "v", "RST", "v"),
+    ("IN", "A,(n)", "IN", "n"),
+    ("OUT", "(n),A", "OUT", "n"),
+    //Spellings without the parentheses some assemblers accept
+    ("JP", "HL", "PCHL", ""),
+    ("IN", "A,n", "IN", "n"),
+    ("OUT", "n,A", "OUT", "n"),
+    ("EX", "HL,DE", "XCHG", ""),
+    ("EX", "HL,(SP)", "XTHL", ""),
+];
+
+//Z80 instructions without an 8080 counterpart, named in errors instead of unknown mnemonics
+const Z80_ONLY: [&str; 36] = [
+    "EXX", "DJNZ", "JR", "LDI", "LDIR", "LDD", "LDDR", "CPI", "CPIR", "CPD", "CPDR", "INI", "INIR",
+    "IND", "INDR", "OUTI", "OTIR", "OUTD", "OTDR", "NEG", "IM", "RETI", "RETN", "RLD", "RRD",
+    "BIT", "SET", "RES", "RL", "RR", "RLC", "RRC", "SLA", "SRA", "SRL", "SLL",
+];
+
+//Z80 register names, never taken for symbols in an expression operand
+const Z80_REGISTERS: [&str; 21] = [
+    "A", "B", "C", "D", "E", "H", "L", "I", "R", "AF", "AF'", "BC", "DE", "HL", "SP", "IX", "IY",
+    "IXH", "IXL", "IYH", "IYL",
+];
+
+const CONDITIONS: [&str; 8] = ["NZ", "Z", "NC", "C", "PO", "PE", "P", "M"];
+
+//Operand names of one syntax
+struct Names {
+    registers: [&'static str; 8],
+    pairs: [&'static str; 4],
+    stack_pairs: [&'static str; 4],
+    //What the operand of RST is multiplied by to get the address
+    vector_scale: i64,
+}
+
+const ZILOG: Names = Names {
+    registers: ["B", "C", "D", "E", "H", "L", "(HL)", "A"],
+    pairs: ["BC", "DE", "HL", "SP"],
+    stack_pairs: ["BC", "DE", "HL", "AF"],
+    vector_scale: 8,
+};
+
+const INTEL: Names = Names {
+    registers: ["B", "C", "D", "E", "H", "L", "M", "A"],
+    pairs: ["B", "D", "H", "SP"],
+    stack_pairs: ["B", "D", "H", "PSW"],
+    vector_scale: 1,
+};
+
+//What an operand pattern matched, the same in both syntaxes
+#[derive(Debug, Clone, PartialEq)]
+enum Binding {
+    Register(usize),
+    Pair(usize),
+    StackPair(usize),
+    Value(Operand),
+    Vector(i64),
+}
+
+pub fn is_mnemonic(name: &str) -> bool {
+    RULES.iter().any(|(zilog, _, _, _)| *zilog == name) || Z80_ONLY.contains(&name)
+}
+
+fn pattern(operands: &str) -> Vec<&str> {
+    match operands {
+        "" => Vec::new(),
+        _ => operands.split(',').collect(),
+    }
+}
+
+fn is_placeholder(piece: &str) -> bool {
+    matches!(
+        piece,
+        "r" | "p" | "s" | "n" | "w" | "(n)" | "(w)" | "c" | "v"
+    )
+}
+
+fn position(names: &[&str], text: &str) -> Option<usize> {
+    names.iter().position(|name| *name == text)
+}
+
+//Text between the outer parentheses of `(expr)` as an operand of its own
+fn indirect(operand: &Operand) -> Option<Operand> {
+    let inner = operand.text.strip_prefix('(')?.strip_suffix(')')?;
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return None,
+            ')' => depth -= 1,
+            _ => (),
+        }
+    }
+    let trimmed = inner.trim_start();
+    Some(Operand {
+        text: trimmed.trim_end().to_string(),
+        column: operand.column + 1 + inner.len() - trimmed.len(),
+    })
+}
+
+fn is_register(text: &str) -> bool {
+    let text = text.to_ascii_uppercase();
+    let indexed = ["IX+", "IX-", "IY+", "IY-"]
+        .iter()
+        .any(|prefix| text.starts_with(prefix));
+    Z80_REGISTERS.contains(&text.as_str()) || indexed
+}
+
+/* Match one operand against one piece of a pattern: Some(None) for a fixed name,
+ * Some(binding) for a placeholder and None when it does not fit.
+ */
+fn bind(
+    piece: &str,
+    operand: &Operand,
+    names: &Names,
+    eval: &dyn Fn(&Operand) -> Result<i64, Located>,
+) -> Result<Option<Option<Binding>>, Located> {
+    let text = operand
+        .text
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_uppercase();
+    let binding = match piece {
+        "r" => position(&names.registers, &text).map(Binding::Register),
+        "p" => position(&names.pairs, &text).map(Binding::Pair),
+        "s" => position(&names.stack_pairs, &text).map(Binding::StackPair),
+        "n" | "w" if indirect(operand).is_none() && !is_register(&text) => {
+            Some(Binding::Value(operand.clone()))
+        }
+        "(n)" | "(w)" => indirect(operand)
+            .filter(|inner| !is_register(&inner.text))
+            .map(Binding::Value),
+        "v" => {
+            let address = eval(operand)?;
+            let scale = names.vector_scale;
+            if address % scale != 0 || !(0..8).contains(&(address / scale)) {
+                let kind = match scale {
+                    1 => ErrorKind::RstRange(address),
+                    _ => ErrorKind::RstAddress(address),
+                };
+                return Err(kind.at(operand.span()));
+            }
+            Some(Binding::Vector(address / scale))
+        }
+        name if name == text => return Ok(Some(None)),
+        _ => None,
+    };
+    Ok(binding.map(Some))
+}
+
+//Bindings of the placeholders with the columns of their operands, None when the operands do not fit
+fn bind_all(
+    pieces: &[&str],
+    operands: &[Operand],
+    names: &Names,
+    eval: &dyn Fn(&Operand) -> Result<i64, Located>,
+) -> Result<Option<Vec<(Binding, usize)>>, Located> {
+    if pieces.len() != operands.len() {
+        return Ok(None);
+    }
+    let mut bindings = Vec::new();
+    for (piece, operand) in pieces.iter().zip(operands) {
+        match bind(piece, operand, names, eval)? {
+            Some(Some(binding)) => bindings.push((binding, operand.column)),
+            Some(None) => (),
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(bindings))
+}
+
+//Operands of a pattern filled in from the bindings, fixed names get the column `column`
+fn fill(
+    pieces: &[&str],
+    bindings: &[(Binding, usize)],
+    names: &Names,
+    column: usize,
+) -> Vec<Operand> {
+    let mut bindings = bindings.iter();
+    pieces
+        .iter()
+        .map(|piece| {
+            if !is_placeholder(piece) {
+                return Operand {
+                    text: piece.to_string(),
+                    column,
+                };
+            }
+            let (binding, column) = bindings.next().expect("binding for every placeholder");
+            let text = match binding {
+                Binding::Register(index) => names.registers[*index].to_string(),
+                Binding::Pair(index) => names.pairs[*index].to_string(),
+                Binding::StackPair(index) => names.stack_pairs[*index].to_string(),
+                Binding::Vector(vector) if names.vector_scale == 1 => vector.to_string(),
+                Binding::Vector(vector) => hex((vector * names.vector_scale) as u16, 2),
+                Binding::Value(operand) if piece.starts_with('(') => format!("({})", operand.text),
+                Binding::Value(operand) => return operand.clone(),
+            };
+            Operand {
+                text,
+                column: *column,
+            }
+        })
+        .collect()
+}
+
+/* Rewrite a statement in Zilog syntax into the Intel instruction it stands for.
+ * `eval` gives the value of the address operand of RST.
+ */
+pub fn translate(
+    statement: &mut Statement,
+    eval: &dyn Fn(&Operand) -> Result<i64, Located>,
+) -> Result<(), Located> {
+    let mnemonic = match &statement.operation {
+        Some(mnemonic) => mnemonic.clone(),
+        None => return Ok(()),
+    };
+    let column = statement
+        .operands
+        .first()
+        .map_or(0, |operand| operand.column);
+    for (zilog, zilog_operands, intel, intel_operands) in RULES.iter() {
+        if *zilog != mnemonic {
+            continue;
+        }
+        //The condition is the first operand in Zilog syntax and part of the mnemonic in Intel's
+        let mut pieces = pattern(zilog_operands);
+        let mut operands = &statement.operands[..];
+        let mut condition = "";
+        if pieces.first() == Some(&"c") {
+            let text = operands
+                .first()
+                .map(|operand| operand.text.to_ascii_uppercase());
+            match text.and_then(|text| position(&CONDITIONS, &text)) {
+                Some(index) => condition = CONDITIONS[index],
+                None => continue,
+            }
+            pieces.remove(0);
+            operands = &operands[1..];
+        }
+        let bindings = match bind_all(&pieces, operands, &ZILOG, eval)? {
+            Some(bindings) => bindings,
+            None => continue,
+        };
+        let operands = fill(&pattern(intel_operands), &bindings, &INTEL, column);
+        statement.operation = Some(format!("{}{}", intel, condition));
+        statement.operand_text = operands
+            .iter()
+            .map(|operand| operand.text.as_str())
+            .collect::<Vec<&str>>()
+            .join(",");
+        statement.operands = operands;
+        return Ok(());
+    }
+    if !is_mnemonic(&mnemonic) {
+        return Err(ErrorKind::UnknownMnemonic(mnemonic).into());
+    }
+    let text = format!("{} {}", mnemonic, statement.operand_text);
+    Err(ErrorKind::NotIntel8080(text.trim_end().to_string()).into())
+}
+
+//Decoded instruction in Zilog syntax
+pub fn render(decoded: &Decoded) -> Option<String> {
+    let operands: Vec<Operand> = decoded
+        .operands
+        .iter()
+        .map(|text| Operand {
+            text: text.clone(),
+            column: 0,
+        })
+        .collect();
+    let eval = |operand: &Operand| parse_number(&operand.text).map_err(Located::from);
+    for (zilog, zilog_operands, intel, intel_operands) in RULES.iter() {
+        let mut pieces = pattern(zilog_operands);
+        let condition = match decoded.mnemonic.strip_prefix(intel) {
+            Some("") if pieces.first() != Some(&"c") => None,
+            Some(suffix) if pieces.first() == Some(&"c") => match position(&CONDITIONS, suffix) {
+                Some(index) => Some(CONDITIONS[index]),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let bindings = match bind_all(&pattern(intel_operands), &operands, &INTEL, &eval) {
+            Ok(Some(bindings)) => bindings,
+            _ => continue,
+        };
+        let mut text = Vec::new();
+        if let Some(condition) = condition {
+            pieces.remove(0);
+            text.push(condition.to_string());
+        }
+        text.extend(
+            fill(&pieces, &bindings, &ZILOG, 0)
+                .into_iter()
+                .map(|operand| operand.text),
+        );
+        return Some(
+            format!("{} {}", zilog, text.join(","))
+                .trim_end()
+                .to_string(),
+        );
+    }
+    None
+}
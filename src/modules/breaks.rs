@@ -0,0 +1,140 @@
+use std::fmt;
+
+use super::opcodes::{self, OPCODES};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl Direction {
+    pub fn name(self) -> &'static str {
+        match self {
+            Direction::In => "IN",
+            Direction::Out => "OUT",
+        }
+    }
+}
+
+//IN or OUT on the ports from `start` to `end`, either direction without one, any value without one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PortBreak {
+    pub start: u8,
+    pub end: u8,
+    pub direction: Option<Direction>,
+    pub value: Option<u8>,
+}
+
+impl PortBreak {
+    pub fn triggers(&self, direction: Direction, port: u8, value: u8) -> bool {
+        (self.start..=self.end).contains(&port)
+            && self.direction.map_or(true, |wanted| wanted == direction)
+            && self.value.map_or(true, |wanted| wanted == value)
+    }
+}
+
+impl fmt::Display for PortBreak {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}", self.direction.map_or("IN/OUT", Direction::name))?;
+        write!(fmt, " {:02X}H", self.start)?;
+        if self.end != self.start {
+            write!(fmt, "-{:02X}H", self.end)?;
+        }
+        if let Some(value) = self.value {
+            write!(fmt, " value {:02X}H", value)?;
+        }
+        Ok(())
+    }
+}
+
+//Instructions that stop wherever they are
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Class {
+    Rst,
+    Hlt,
+    //EI and DI
+    Interrupts,
+    Pchl,
+    Undocumented,
+}
+
+impl Class {
+    pub fn parse(name: &str) -> Option<Class> {
+        match name.to_ascii_lowercase().as_str() {
+            "rst" => Some(Class::Rst),
+            "hlt" => Some(Class::Hlt),
+            "ei" | "di" | "ei/di" => Some(Class::Interrupts),
+            "pchl" => Some(Class::Pchl),
+            "undocumented" => Some(Class::Undocumented),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Class::Rst => "RST",
+            Class::Hlt => "HLT",
+            Class::Interrupts => "EI/DI",
+            Class::Pchl => "PCHL",
+            Class::Undocumented => "undocumented",
+        }
+    }
+
+    //By the fetched opcode, an undocumented one is also in the class of its twin
+    pub fn contains(self, opcode: u8) -> bool {
+        match self {
+            Class::Undocumented => !OPCODES[opcode as usize].documented,
+            _ => match opcodes::twin(opcode) {
+                0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => self == Class::Rst,
+                0x76 => self == Class::Hlt,
+                0xF3 | 0xFB => self == Class::Interrupts,
+                0xE9 => self == Class::Pchl,
+                _ => false,
+            },
+        }
+    }
+}
+
+//Port access or instruction that triggered a break, `pc` is the address of the instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Port {
+        pc: u16,
+        direction: Direction,
+        port: u8,
+        value: u8,
+    },
+    Instruction {
+        pc: u16,
+        class: Class,
+        opcode: u8,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Port {
+                pc,
+                direction,
+                port,
+                value,
+            } => write!(
+                fmt,
+                "{} {:02X}H on port {:02X}H by the instruction at {:04X}H",
+                direction.name(),
+                value,
+                port,
+                pc
+            ),
+            Event::Instruction { pc, class, opcode } => write!(
+                fmt,
+                "{} opcode {:02X}H at {:04X}H",
+                class.name(),
+                opcode,
+                pc
+            ),
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use std::collections::BTreeMap;
+
+use super::{
+    assembler::expr::parse_number,
+    memory::Memory,
+    registers::{Flag, Registers},
+};
+
+/* Breakpoint conditions, C-like from the tightest binding:
+ * ( ) [address] w[address], unary ! ~ -
+ * + -
+ * & | ^
+ * == != < <= > >=
+ * &&
+ * ||
+ * Values are registers, register pairs, the flags S Z AC P CY, CYCLES, HITS, numbers and
+ * symbols; `[HL]` is the byte at HL and `w[SP]` the word at SP. Comparisons and the logical
+ * operators give 1 for true and 0 for false, a condition holds when it is not 0.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Number(i64),
+    Name(&'static str),
+    Byte(Box<Condition>),
+    Word(Box<Condition>),
+    Unary(&'static str, Box<Condition>),
+    Binary(&'static str, Box<Condition>, Box<Condition>),
+}
+
+//Everything a condition can look at
+pub struct Machine<'a> {
+    pub registers: &'a Registers,
+    pub memory: &'a Memory,
+    pub cycles: u64,
+    //Times the breakpoint was reached, this time included
+    pub hits: u64,
+}
+
+const NAMES: [&str; 21] = [
+    "A", "B", "C", "D", "E", "H", "L", "F", "M", "BC", "DE", "HL", "SP", "PC", "S", "Z", "AC", "P",
+    "CY", "CYCLES", "HITS",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+const OPERATORS: [&str; 20] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "~", "+", "-", "&", "|", "^", "(", ")", "[",
+    "]", "=",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let first = rest.chars().next().unwrap_or(' ');
+        let length = if first.is_ascii_alphanumeric() || first == '_' {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            let word = &rest[..length];
+            tokens.push(if first.is_ascii_digit() {
+                Token::Number(parse_number(word).map_err(|_| format!("invalid number `{}`", word))?)
+            } else {
+                Token::Ident(word.to_ascii_uppercase())
+            });
+            length
+        } else {
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("unexpected character `{}`", first))?;
+            //A single = compares like ==
+            tokens.push(Token::Op(if *op == "=" { "==" } else { op }));
+            op.len()
+        };
+        rest = rest[length..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: &'a BTreeMap<String, u16>,
+}
+
+impl Parser<'_> {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        match self.peek_op() {
+            Some(found) if found == op => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(format!("missing `{}`", op)),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[&'static str],
+        next: fn(&mut Self) -> Result<Condition, String>,
+    ) -> Result<Condition, String> {
+        let mut left = next(self)?;
+        while let Some(op) = self.peek_op().filter(|op| ops.contains(op)) {
+            self.position += 1;
+            let right = next(self)?;
+            left = Condition::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Condition, String> {
+        self.binary(&["||"], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Condition, String> {
+        self.binary(&["&&"], Self::relation)
+    }
+
+    fn relation(&mut self) -> Result<Condition, String> {
+        self.binary(&["==", "!=", "<", "<=", ">", ">="], Self::bits)
+    }
+
+    fn bits(&mut self) -> Result<Condition, String> {
+        self.binary(&["&", "|", "^"], Self::additive)
+    }
+
+    fn additive(&mut self) -> Result<Condition, String> {
+        self.binary(&["+", "-"], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Condition, String> {
+        match self.peek_op() {
+            Some(op @ ("!" | "~" | "-")) => {
+                self.position += 1;
+                Ok(Condition::Unary(op, Box::new(self.unary()?)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<Condition, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| "value expected".to_string())?;
+        self.position += 1;
+        match token {
+            Token::Number(value) => Ok(Condition::Number(value)),
+            Token::Ident(name) if name == "W" && self.peek_op() == Some("[") => {
+                self.position += 1;
+                let address = self.or()?;
+                self.expect("]")?;
+                Ok(Condition::Word(Box::new(address)))
+            }
+            Token::Ident(name) => match NAMES.iter().find(|known| **known == name) {
+                Some(known) => Ok(Condition::Name(known)),
+                None => match self.symbols.get(&name) {
+                    Some(value) => Ok(Condition::Number(*value as i64)),
+                    None => Err(format!("unknown register, flag or symbol `{}`", name)),
+                },
+            },
+            Token::Op("(") => {
+                let condition = self.or()?;
+                self.expect(")")?;
+                Ok(condition)
+            }
+            Token::Op("[") => {
+                let address = self.or()?;
+                self.expect("]")?;
+                Ok(Condition::Byte(Box::new(address)))
+            }
+            Token::Op(op) => Err(format!("operator `{}` where a value was expected", op)),
+        }
+    }
+}
+
+impl Condition {
+    //Symbols are looked up in upper case, like the assembler writes them
+    pub fn parse(text: &str, symbols: &BTreeMap<String, u16>) -> Result<Condition, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+            symbols,
+        };
+        let condition = parser.or()?;
+        match parser.tokens.get(parser.position) {
+            None => Ok(condition),
+            Some(_) => Err("unexpected text after the condition".to_string()),
+        }
+    }
+
+    pub fn holds(&self, machine: &Machine) -> bool {
+        self.eval(machine) != 0
+    }
+
+    fn eval(&self, machine: &Machine) -> i64 {
+        let registers = machine.registers;
+        let pair = |high: u8, low: u8| u16::from_le_bytes([low, high]) as i64;
+        let flag = |flag| registers.get_flag(flag) as i64;
+        match self {
+            Condition::Number(value) => *value,
+            Condition::Name(name) => match *name {
+                "A" => registers.a as i64,
+                "B" => registers.b as i64,
+                "C" => registers.c as i64,
+                "D" => registers.d as i64,
+                "E" => registers.e as i64,
+                "H" => registers.h as i64,
+                "L" => registers.l as i64,
+                "F" => registers.f as i64,
+                "M" => machine.memory.program[pair(registers.h, registers.l) as u16] as i64,
+                "BC" => pair(registers.b, registers.c),
+                "DE" => pair(registers.d, registers.e),
+                "HL" => pair(registers.h, registers.l),
+                "SP" => registers.sp as i64,
+                "PC" => registers.pc as i64,
+                "S" => flag(Flag::Sign),
+                "Z" => flag(Flag::Zero),
+                "AC" => flag(Flag::ACarry),
+                "P" => flag(Flag::Parity),
+                "CY" => flag(Flag::Carry),
+                "CYCLES" => machine.cycles as i64,
+                _ => machine.hits as i64,
+            },
+            Condition::Byte(address) => machine.memory.program[address.eval(machine) as u16] as i64,
+            Condition::Word(address) => {
+                let address = address.eval(machine) as u16;
+                let low = machine.memory.program[address];
+                let high = machine.memory.program[address.wrapping_add(1)];
+                pair(high, low)
+            }
+            Condition::Unary(op, value) => {
+                let value = value.eval(machine);
+                match *op {
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value.wrapping_neg(),
+                }
+            }
+            Condition::Binary(op, left, right) => {
+                let left = left.eval(machine);
+                //&& and || do not look at the right side when the left decides
+                match *op {
+                    "&&" => return (left != 0 && right.eval(machine) != 0) as i64,
+                    "||" => return (left != 0 || right.eval(machine) != 0) as i64,
+                    _ => (),
+                }
+                let right = right.eval(machine);
+                match *op {
+                    "==" => (left == right) as i64,
+                    "!=" => (left != right) as i64,
+                    "<" => (left < right) as i64,
+                    "<=" => (left <= right) as i64,
+                    ">" => (left > right) as i64,
+                    ">=" => (left >= right) as i64,
+                    "&" => left & right,
+                    "|" => left | right,
+                    "^" => left ^ right,
+                    "+" => left.wrapping_add(right),
+                    _ => left.wrapping_sub(right),
+                }
+            }
+        }
+    }
+}
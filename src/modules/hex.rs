@@ -0,0 +1,131 @@
+use std::{convert::TryFrom, fmt::Write};
+
+use super::image::{Image, ParseError};
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT: u8 = 0x02;
+const START_SEGMENT: u8 = 0x03;
+const EXTENDED_LINEAR: u8 = 0x04;
+const START_LINEAR: u8 = 0x05;
+const BYTES_PER_RECORD: usize = 16;
+
+//Bytes of a record without the colon, the checksum is checked
+fn record_bytes(line: &str) -> Result<Vec<u8>, String> {
+    let digits = line
+        .strip_prefix(':')
+        .ok_or_else(|| "a record starts with `:`".to_string())?;
+    let bytes = hex_bytes(digits)?;
+    if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+        return Err("record length does not match its byte count".to_string());
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 1);
+    let expected = checksum_of(body);
+    if checksum[0] != expected {
+        return Err(format!(
+            "checksum is {:02X}H, expected {:02X}H",
+            checksum[0], expected
+        ));
+    }
+    Ok(body.to_vec())
+}
+
+//Pairs of hexadecimal digits, shared with S-records
+pub(super) fn hex_bytes(digits: &str) -> Result<Vec<u8>, String> {
+    if digits.len() % 2 != 0 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err("a record is an even number of hexadecimal digits".to_string());
+    }
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap_or(0))
+        .collect())
+}
+
+fn checksum_of(bytes: &[u8]) -> u8 {
+    bytes
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+        .wrapping_neg()
+}
+
+pub fn parse(text: &str) -> Result<Image, ParseError> {
+    let mut image = Image {
+        segments: Vec::new(),
+        start: None,
+    };
+    //Added to the address of data records by extended address records
+    let mut base = 0u32;
+    let mut lines = 0;
+    for (index, line) in text.lines().enumerate() {
+        lines = index + 1;
+        let error = |message: String| ParseError {
+            line: index + 1,
+            message,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let bytes = record_bytes(line).map_err(error)?;
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let data = &bytes[4..];
+        let value = |length: usize| match data.len() {
+            n if n == length => Ok(data.iter().fold(0u32, |v, b| v << 8 | *b as u32)),
+            _ => Err(error(format!(
+                "record type {:02X} needs {} bytes",
+                bytes[3], length
+            ))),
+        };
+        let address = |address: u32| {
+            u16::try_from(address)
+                .map_err(|_| error(format!("address {:X}H is beyond 64K", address)))
+        };
+        match bytes[3] {
+            DATA => {
+                let address = base + offset as u32;
+                if address + data.len() as u32 > 0x10000 {
+                    return Err(error(format!("data at {:X}H runs beyond 64K", address)));
+                }
+                image.add(address as u16, data);
+            }
+            END_OF_FILE => return Ok(image),
+            EXTENDED_SEGMENT => base = value(2)? << 4,
+            EXTENDED_LINEAR => base = value(2)? << 16,
+            START_SEGMENT => {
+                let start = value(4)?;
+                image.start = Some(address((start >> 16 << 4) + (start & 0xFFFF))?);
+            }
+            START_LINEAR => image.start = Some(address(value(4)?)?),
+            kind => return Err(error(format!("unknown record type {:02X}", kind))),
+        }
+    }
+    Err(ParseError {
+        line: lines + 1,
+        message: "missing end of file record".to_string(),
+    })
+}
+
+//Data records of 16 bytes, then the start address as a segment record and the end of file
+pub fn to_text(image: &Image) -> String {
+    let mut out = String::new();
+    let mut record = |kind: u8, address: u16, data: &[u8]| {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend_from_slice(&address.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(data);
+        bytes.push(checksum_of(&bytes));
+        let digits: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(out, ":{}", digits).unwrap();
+    };
+    for segment in &image.segments {
+        for (i, chunk) in segment.data.chunks(BYTES_PER_RECORD).enumerate() {
+            let address = segment.address.wrapping_add((i * BYTES_PER_RECORD) as u16);
+            record(DATA, address, chunk);
+        }
+    }
+    if let Some(start) = image.start {
+        record(START_SEGMENT, 0, &[0, 0, (start >> 8) as u8, start as u8]);
+    }
+    record(END_OF_FILE, 0, &[]);
+    out
+}
@@ -0,0 +1,185 @@
+use std::{convert::TryFrom, fmt, fs, path::Path};
+
+use super::{
+    assembler::{expr::parse_number, object::Section, Segment},
+    hex,
+    memory::Memory,
+    srec,
+};
+
+//Bytes at known addresses, read from a file or to be written to one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    //From a start address record, where execution begins
+    pub start: Option<u16>,
+}
+
+//Error in a line of a text image format
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "line {}: {}", self.line, self.message)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Binary,
+    IntelHex,
+    SRecord,
+}
+
+impl Format {
+    //By the extension of the file name, anything unknown is a raw binary
+    pub fn of(path: &str) -> Format {
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("hex" | "ihx" | "ihex") => Format::IntelHex,
+            Some("s19" | "srec" | "s1" | "mot") => Format::SRecord,
+            _ => Format::Binary,
+        }
+    }
+}
+
+impl Image {
+    pub fn new(address: u16, data: Vec<u8>) -> Image {
+        Image {
+            segments: vec![Segment {
+                section: Section::Absolute,
+                address,
+                data,
+            }],
+            start: None,
+        }
+    }
+
+    //Data right after the last segment is appended to it
+    pub(super) fn add(&mut self, address: u16, data: &[u8]) {
+        match self.segments.last_mut() {
+            Some(last) if last.address as usize + last.data.len() == address as usize => {
+                last.data.extend_from_slice(data)
+            }
+            _ => self.segments.push(Segment {
+                section: Section::Absolute,
+                address,
+                data: data.to_vec(),
+            }),
+        }
+    }
+
+    pub fn load(&self, memory: &mut Memory) {
+        for segment in &self.segments {
+            memory.load(segment.address, &segment.data);
+        }
+    }
+
+    //Lowest address and the end of the highest segment
+    pub fn bounds(&self) -> Option<(u16, usize)> {
+        let first = self.segments.iter().map(|s| s.address).min()?;
+        let end = self
+            .segments
+            .iter()
+            .map(|s| s.address as usize + s.data.len());
+        Some((first, end.max()?))
+    }
+
+    pub fn to_bytes(&self, format: Format) -> Vec<u8> {
+        match format {
+            Format::Binary => self.segments.iter().flat_map(|s| s.data.clone()).collect(),
+            Format::IntelHex => hex::to_text(self).into_bytes(),
+            Format::SRecord => srec::to_text(self).into_bytes(),
+        }
+    }
+}
+
+/* Read `file` in the format of its extension, or `file@address` for a raw binary that
+ * belongs at an address. Raw binaries without one are loaded at 0.
+ */
+pub fn read(spec: &str) -> Result<Image, String> {
+    let (path, address) = match spec.rfind('@') {
+        Some(at) => {
+            let address = parse_number(&spec[at + 1..])
+                .ok()
+                .and_then(|address| u16::try_from(address).ok())
+                .ok_or_else(|| format!("{}: invalid address `{}`", spec, &spec[at + 1..]))?;
+            (&spec[..at], Some(address))
+        }
+        None => (spec, None),
+    };
+    let format = Format::of(path);
+    let error = |error: &dyn fmt::Display| format!("{}: {}", path, error);
+    if format != Format::Binary {
+        if address.is_some() {
+            return Err(format!("{}: only raw binaries take an @address", spec));
+        }
+        let text = fs::read_to_string(path).map_err(|e| error(&e))?;
+        let image = match format {
+            Format::IntelHex => hex::parse(&text),
+            _ => srec::parse(&text),
+        };
+        return image.map_err(|e| error(&e));
+    }
+    let data = fs::read(path).map_err(|e| error(&e))?;
+    if address.unwrap_or(0) as usize + data.len() > 0x10000 {
+        return Err(format!("{} runs beyond 64K", spec));
+    }
+    Ok(Image::new(address.unwrap_or(0), data))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Overlap {
+    pub first: String,
+    pub second: String,
+    pub start: u16,
+    pub end: u16,
+}
+
+impl fmt::Display for Overlap {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            fmt,
+            "{} overlaps {} at {:04X}H-{:04X}H",
+            self.second, self.first, self.start, self.end
+        )
+    }
+}
+
+/* Every pair of named images, or segments of one image, that share addresses.
+ * The first of the pair is the one loaded first.
+ */
+pub fn overlaps(images: &[(String, Image)]) -> Vec<Overlap> {
+    let segments: Vec<(&String, usize, usize)> = images
+        .iter()
+        .flat_map(|(name, image)| {
+            image.segments.iter().map(move |segment| {
+                let start = segment.address as usize;
+                (name, start, start + segment.data.len())
+            })
+        })
+        .filter(|(_, start, end)| start < end)
+        .collect();
+    let mut overlaps = Vec::new();
+    for (i, (first, first_start, first_end)) in segments.iter().enumerate() {
+        for (second, second_start, second_end) in &segments[i + 1..] {
+            let start = *first_start.max(second_start);
+            let end = *first_end.min(second_end);
+            if start < end {
+                overlaps.push(Overlap {
+                    first: first.to_string(),
+                    second: second.to_string(),
+                    start: start as u16,
+                    end: (end - 1) as u16,
+                });
+            }
+        }
+    }
+    overlaps
+}
@@ -0,0 +1,306 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::{self, Write},
+};
+
+use super::assembler::{
+    expr::Base,
+    flatten,
+    object::{Object, Section},
+    Segment,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkOptions {
+    //Address of the code of the first module, the others follow it
+    pub code: u16,
+    //Address of the data of the first module, right after all code when None
+    pub data: Option<u16>,
+}
+
+impl Default for LinkOptions {
+    fn default() -> Self {
+        Self {
+            code: 0x100,
+            data: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    DuplicateSymbol {
+        name: String,
+        first: String,
+        second: String,
+    },
+    UndefinedSymbol {
+        name: String,
+        module: String,
+    },
+    Overlap {
+        address: u16,
+        first: String,
+        second: String,
+    },
+    AddressOverflow(String),
+    MultipleEntries(String, String),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol {
+                name,
+                first,
+                second,
+            } => write!(
+                fmt,
+                "symbol `{}` is public in both `{}` and `{}`",
+                name, first, second
+            ),
+            LinkError::UndefinedSymbol { name, module } => {
+                write!(
+                    fmt,
+                    "undefined symbol `{}` referenced in `{}`",
+                    name, module
+                )
+            }
+            LinkError::Overlap { address, first, .. } if self.is_internal() => write!(
+                fmt,
+                "code and data of `{}` overlap at {:04X}H",
+                first, address
+            ),
+            LinkError::Overlap {
+                address,
+                first,
+                second,
+            } => write!(
+                fmt,
+                "`{}` and `{}` overlap at {:04X}H",
+                first, second, address
+            ),
+            LinkError::AddressOverflow(module) => {
+                write!(fmt, "`{}` does not fit below 10000H", module)
+            }
+            LinkError::MultipleEntries(first, second) => {
+                write!(fmt, "both `{}` and `{}` give an entry point", first, second)
+            }
+        }
+    }
+}
+
+impl LinkError {
+    fn is_internal(&self) -> bool {
+        matches!(self, LinkError::Overlap { first, second, .. } if first == second)
+    }
+}
+
+//Where the sections of one module were placed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub module: String,
+    pub code: u16,
+    pub code_size: u16,
+    pub data: u16,
+    pub data_size: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    //Address and module of every public symbol
+    pub symbols: BTreeMap<String, (u16, String)>,
+    pub modules: Vec<Placement>,
+    pub entry: Option<u16>,
+}
+
+impl Image {
+    pub fn to_binary(&self) -> Vec<u8> {
+        flatten(&self.segments)
+    }
+
+    pub fn map(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{:<16} {:<10} DATA", "MODULE", "CODE").unwrap();
+        let range = |start: u16, size: u16| match size {
+            0 => "-".to_string(),
+            _ => format!("{:04X}-{:04X}", start, start as u32 + size as u32 - 1),
+        };
+        for placement in &self.modules {
+            writeln!(
+                out,
+                "{:<16} {:<10} {}",
+                placement.module,
+                range(placement.code, placement.code_size),
+                range(placement.data, placement.data_size)
+            )
+            .unwrap();
+        }
+        writeln!(out, "\n{:<16} {:<7} MODULE", "SYMBOL", "ADDRESS").unwrap();
+        for (name, (address, module)) in &self.symbols {
+            writeln!(out, "{:<16} {:04X}    {}", name, address, module).unwrap();
+        }
+        if let Some(entry) = self.entry {
+            writeln!(out, "\nENTRY {:04X}", entry).unwrap();
+        }
+        out
+    }
+}
+
+impl Placement {
+    fn address(&self, section: Section, offset: u16) -> u16 {
+        match section {
+            Section::Absolute => offset,
+            Section::Code => self.code.wrapping_add(offset),
+            Section::Data => self.data.wrapping_add(offset),
+        }
+    }
+}
+
+/* Place the code sections one after the other, then the data sections, resolve the
+ * public symbols and patch every relocated word. All errors are reported together.
+ */
+pub fn link(objects: &[Object], options: &LinkOptions) -> Result<Image, Vec<LinkError>> {
+    let mut errors = Vec::new();
+
+    let mut modules = Vec::new();
+    let mut code = options.code as u32;
+    for object in objects {
+        modules.push(Placement {
+            module: object.name.clone(),
+            code: code as u16,
+            code_size: object.code_size,
+            data: 0,
+            data_size: object.data_size,
+        });
+        code += object.code_size as u32;
+        if code > 0x10000 {
+            errors.push(LinkError::AddressOverflow(object.name.clone()));
+        }
+    }
+    let mut data = options.data.map_or(code, u32::from);
+    for placement in &mut modules {
+        placement.data = data as u16;
+        data += placement.data_size as u32;
+        if data > 0x10000 {
+            errors.push(LinkError::AddressOverflow(placement.module.clone()));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut symbols = BTreeMap::new();
+    for (object, placement) in objects.iter().zip(&modules) {
+        for public in &object.publics {
+            let address = placement.address(public.section, public.value);
+            match symbols.get(&public.name) {
+                Some((_, first)) => errors.push(LinkError::DuplicateSymbol {
+                    name: public.name.clone(),
+                    first: String::clone(first),
+                    second: object.name.clone(),
+                }),
+                None => {
+                    symbols.insert(public.name.clone(), (address, object.name.clone()));
+                }
+            }
+        }
+    }
+
+    //Which module wrote each byte, overlaps are reported once per pair of modules
+    let mut memory: Vec<Option<(u8, usize)>> = vec![None; 0x10000];
+    let mut overlaps = HashSet::new();
+    for (index, (object, placement)) in objects.iter().zip(&modules).enumerate() {
+        for segment in &object.segments {
+            let start = placement.address(segment.section, segment.address) as usize;
+            for (i, byte) in segment.data.iter().enumerate() {
+                let address = (start + i) & 0xFFFF;
+                if let Some((_, other)) = memory[address] {
+                    if overlaps.insert((other, index)) {
+                        errors.push(LinkError::Overlap {
+                            address: address as u16,
+                            first: modules[other].module.clone(),
+                            second: object.name.clone(),
+                        });
+                    }
+                }
+                memory[address] = Some((*byte, index));
+            }
+        }
+    }
+
+    for (object, placement) in objects.iter().zip(&modules) {
+        for name in &object.externals {
+            if !symbols.contains_key(name) {
+                errors.push(LinkError::UndefinedSymbol {
+                    name: name.clone(),
+                    module: object.name.clone(),
+                });
+            }
+        }
+        for relocation in &object.relocations {
+            let value = match &relocation.base {
+                Base::Code => placement.code,
+                Base::Data => placement.data,
+                Base::External(name) => match symbols.get(name) {
+                    Some((address, _)) => *address,
+                    None => continue,
+                },
+            };
+            let low = placement.address(relocation.section, relocation.offset) as usize;
+            let high = (low + 1) & 0xFFFF;
+            let byte = |address: usize| memory[address].map_or(0, |(byte, _)| byte);
+            let word = u16::from_le_bytes([byte(low), byte(high)]).wrapping_add(value);
+            for (address, byte) in [(low, word as u8), (high, (word >> 8) as u8)] {
+                if let Some((old, _)) = &mut memory[address] {
+                    *old = byte;
+                }
+            }
+        }
+    }
+
+    let mut entry = None;
+    let mut entry_module: Option<&str> = None;
+    for (object, placement) in objects.iter().zip(&modules) {
+        if let Some((section, offset)) = object.entry {
+            match entry_module {
+                Some(first) => errors.push(LinkError::MultipleEntries(
+                    first.to_string(),
+                    object.name.clone(),
+                )),
+                None => {
+                    entry = Some(placement.address(section, offset));
+                    entry_module = Some(&object.name);
+                }
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut segments: Vec<Segment> = Vec::new();
+    for (address, cell) in memory.iter().enumerate() {
+        let byte = match cell {
+            Some((byte, _)) => *byte,
+            None => continue,
+        };
+        match segments.last_mut() {
+            Some(segment) if segment.address as usize + segment.data.len() == address => {
+                segment.data.push(byte)
+            }
+            _ => segments.push(Segment {
+                section: Section::Absolute,
+                address: address as u16,
+                data: vec![byte],
+            }),
+        }
+    }
+    Ok(Image {
+        segments,
+        symbols,
+        modules,
+        entry,
+    })
+}
@@ -3,29 +3,33 @@ use std::{fmt, ops};
 pub struct Ram<T>(Vec<T>);
 
 pub struct Memory {
-    pub(crate) stack: Ram<u16>,
+    //The whole 64K address space, the stack included
     pub(crate) program: Ram<u8>,
-    pub(crate) heap: Ram<u8>,
 }
 
 impl Memory {
     pub fn new() -> Self {
         Self {
-            stack: Ram(vec![0u16; 0x80]),
-            program: Ram(vec![0u8; 0x400]),
-            heap: Ram(vec![0u8; 0xFB00]),
+            program: Ram(vec![0u8; 0x10000]),
         }
     }
+
+    //Copy `data` from `address` on, wrapping around at the end of the address space
+    pub fn load(&mut self, address: u16, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            self.program[address.wrapping_add(i as u16)] = *byte;
+        }
+    }
+
+    //Bytes from `start` to `end` inclusive
+    pub fn read(&self, start: u16, end: u16) -> Vec<u8> {
+        self.program.0[start as usize..=end as usize].to_vec()
+    }
 }
 
 impl fmt::Debug for Memory {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            fmt,
-            "Stack: {:x?}\nHeap: {:x?}",
-            &self.stack[0x0..0x1F],
-            &self.heap[0x0..0x1F]
-        )
+        write!(fmt, "Program: {:x?}", &self.program[0x0..0x1F])
     }
 }
 
@@ -1,3 +1,14 @@
 pub mod assembler;
+pub mod breaks;
+pub mod condition;
+pub mod hex;
+pub mod image;
+pub mod linker;
 pub mod memory;
+pub mod observer;
+pub mod opcodes;
 pub mod registers;
+pub mod snapshot;
+pub mod srec;
+pub mod trace;
+pub mod watch;
@@ -0,0 +1,33 @@
+use std::{fmt, io};
+
+use crate::cpu::Cpu;
+
+/* Hooks host code registers with Cpu::observe, all of them do nothing unless implemented.
+ * `pc` is the address of the running instruction. Nothing is called without observers, the
+ * CPU prints nothing on its own.
+ */
+pub trait Observer: fmt::Debug {
+    //Before the instruction at PC runs
+    fn before(&mut self, _cpu: &Cpu) {}
+
+    fn after(&mut self, _cpu: &Cpu) {}
+
+    //Memory the instruction reads or writes, fetching the instruction itself is neither
+    fn read(&mut self, _pc: u16, _address: u16, _value: u8) {}
+
+    fn write(&mut self, _pc: u16, _address: u16, _old: u8, _new: u8) {}
+
+    fn input(&mut self, _pc: u16, _port: u8, _value: u8) {}
+
+    fn output(&mut self, _pc: u16, _port: u8, _value: u8) {}
+
+    //Accepted interrupt, `pc` is where the program goes on after the RST
+    fn interrupt(&mut self, _pc: u16, _vector: u8) {}
+
+    fn halt(&mut self, _pc: u16) {}
+
+    //End of a run, output is flushed here and its errors end the run
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,388 @@
+use self::{Category::*, Operand::*};
+use super::registers::Flag;
+
+//What an instruction does, as the 8080 manuals group them
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Transfer,
+    Arithmetic,
+    Branch,
+    InputOutput,
+    Control,
+}
+
+impl Category {
+    pub fn name(self) -> &'static str {
+        match self {
+            Transfer => "transfer",
+            Arithmetic => "arithmetic",
+            Branch => "branch",
+            InputOutput => "I/O",
+            Control => "control",
+        }
+    }
+}
+
+//Operands in Intel order, registers and pairs are encoded in the opcode itself
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Register(&'static str), //B, C, D, E, H, L, M or A
+    Pair(&'static str),     //B, D, H, SP or PSW
+    Data8,
+    Data16,
+    Address, //Word after the opcode that is a memory address or a branch target
+    Port,
+    Vector(u8), //Number of the restart, the target is 8 times it
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub operands: &'static [Operand],
+    //Bytes, with the opcode
+    pub length: u8,
+    //Clock cycles as (taken, not taken), the two differ only for conditional calls and returns
+    pub cycles: (u8, u8),
+    //Mask of the flags in F the instruction changes
+    pub flags: u8,
+    pub category: Category,
+    //Undocumented opcodes run as the documented instruction they alias
+    pub documented: bool,
+}
+
+pub const SIGN: u8 = 1 << Flag::Sign as u8;
+pub const ZERO: u8 = 1 << Flag::Zero as u8;
+pub const AUX_CARRY: u8 = 1 << Flag::ACarry as u8;
+pub const PARITY: u8 = 1 << Flag::Parity as u8;
+pub const CARRY: u8 = 1 << Flag::Carry as u8;
+const NONE: u8 = 0;
+const ALL: u8 = SIGN | ZERO | AUX_CARRY | PARITY | CARRY;
+const NOT_CARRY: u8 = ALL & !CARRY;
+
+const fn op(
+    mnemonic: &'static str,
+    operands: &'static [Operand],
+    length: u8,
+    cycles: (u8, u8),
+    flags: u8,
+    category: Category,
+) -> Opcode {
+    Opcode {
+        mnemonic,
+        operands,
+        length,
+        cycles,
+        flags,
+        category,
+        documented: true,
+    }
+}
+
+const fn undocumented(opcode: Opcode) -> Opcode {
+    Opcode {
+        documented: false,
+        ..opcode
+    }
+}
+
+//Every opcode, indexed by its value
+#[rustfmt::skip]
+pub static OPCODES: [Opcode; 256] = [
+    //00..0F
+    op("NOP", &[], 1, (4, 4), NONE, Control),
+    op("LXI", &[Pair("B"), Data16], 3, (10, 10), NONE, Transfer),
+    op("STAX", &[Pair("B")], 1, (7, 7), NONE, Transfer),
+    op("INX", &[Pair("B")], 1, (5, 5), NONE, Arithmetic),
+    op("INR", &[Register("B")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("DCR", &[Register("B")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("MVI", &[Register("B"), Data8], 2, (7, 7), NONE, Transfer),
+    op("RLC", &[], 1, (4, 4), CARRY, Arithmetic),
+    undocumented(op("NOP", &[], 1, (4, 4), NONE, Control)),
+    op("DAD", &[Pair("B")], 1, (10, 10), CARRY, Arithmetic),
+    op("LDAX", &[Pair("B")], 1, (7, 7), NONE, Transfer),
+    op("DCX", &[Pair("B")], 1, (5, 5), NONE, Arithmetic),
+    op("INR", &[Register("C")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("DCR", &[Register("C")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("MVI", &[Register("C"), Data8], 2, (7, 7), NONE, Transfer),
+    op("RRC", &[], 1, (4, 4), CARRY, Arithmetic),
+    //10..1F
+    undocumented(op("NOP", &[], 1, (4, 4), NONE, Control)),
+    op("LXI", &[Pair("D"), Data16], 3, (10, 10), NONE, Transfer),
+    op("STAX", &[Pair("D")], 1, (7, 7), NONE, Transfer),
+    op("INX", &[Pair("D")], 1, (5, 5), NONE, Arithmetic),
+    op("INR", &[Register("D")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("DCR", &[Register("D")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("MVI", &[Register("D"), Data8], 2, (7, 7), NONE, Transfer),
+    op("RAL", &[], 1, (4, 4), CARRY, Arithmetic),
+    undocumented(op("NOP", &[], 1, (4, 4), NONE, Control)),
+    op("DAD", &[Pair("D")], 1, (10, 10), CARRY, Arithmetic),
+    op("LDAX", &[Pair("D")], 1, (7, 7), NONE, Transfer),
+    op("DCX", &[Pair("D")], 1, (5, 5), NONE, Arithmetic),
+    op("INR", &[Register("E")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("DCR", &[Register("E")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("MVI", &[Register("E"), Data8], 2, (7, 7), NONE, Transfer),
+    op("RAR", &[], 1, (4, 4), CARRY, Arithmetic),
+    //20..2F
+    undocumented(op("NOP", &[], 1, (4, 4), NONE, Control)),
+    op("LXI", &[Pair("H"), Data16], 3, (10, 10), NONE, Transfer),
+    op("SHLD", &[Address], 3, (16, 16), NONE, Transfer),
+    op("INX", &[Pair("H")], 1, (5, 5), NONE, Arithmetic),
+    op("INR", &[Register("H")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("DCR", &[Register("H")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("MVI", &[Register("H"), Data8], 2, (7, 7), NONE, Transfer),
+    op("DAA", &[], 1, (4, 4), ALL, Arithmetic),
+    undocumented(op("NOP", &[], 1, (4, 4), NONE, Control)),
+    op("DAD", &[Pair("H")], 1, (10, 10), CARRY, Arithmetic),
+    op("LHLD", &[Address], 3, (16, 16), NONE, Transfer),
+    op("DCX", &[Pair("H")], 1, (5, 5), NONE, Arithmetic),
+    op("INR", &[Register("L")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("DCR", &[Register("L")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("MVI", &[Register("L"), Data8], 2, (7, 7), NONE, Transfer),
+    op("CMA", &[], 1, (4, 4), NONE, Arithmetic),
+    //30..3F
+    undocumented(op("NOP", &[], 1, (4, 4), NONE, Control)),
+    op("LXI", &[Pair("SP"), Data16], 3, (10, 10), NONE, Transfer),
+    op("STA", &[Address], 3, (13, 13), NONE, Transfer),
+    op("INX", &[Pair("SP")], 1, (5, 5), NONE, Arithmetic),
+    op("INR", &[Register("M")], 1, (10, 10), NOT_CARRY, Arithmetic),
+    op("DCR", &[Register("M")], 1, (10, 10), NOT_CARRY, Arithmetic),
+    op("MVI", &[Register("M"), Data8], 2, (10, 10), NONE, Transfer),
+    op("STC", &[], 1, (4, 4), CARRY, Arithmetic),
+    undocumented(op("NOP", &[], 1, (4, 4), NONE, Control)),
+    op("DAD", &[Pair("SP")], 1, (10, 10), CARRY, Arithmetic),
+    op("LDA", &[Address], 3, (13, 13), NONE, Transfer),
+    op("DCX", &[Pair("SP")], 1, (5, 5), NONE, Arithmetic),
+    op("INR", &[Register("A")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("DCR", &[Register("A")], 1, (5, 5), NOT_CARRY, Arithmetic),
+    op("MVI", &[Register("A"), Data8], 2, (7, 7), NONE, Transfer),
+    op("CMC", &[], 1, (4, 4), CARRY, Arithmetic),
+    //40..4F
+    op("MOV", &[Register("B"), Register("B")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("B"), Register("C")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("B"), Register("D")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("B"), Register("E")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("B"), Register("H")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("B"), Register("L")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("B"), Register("M")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("B"), Register("A")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("C"), Register("B")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("C"), Register("C")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("C"), Register("D")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("C"), Register("E")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("C"), Register("H")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("C"), Register("L")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("C"), Register("M")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("C"), Register("A")], 1, (5, 5), NONE, Transfer),
+    //50..5F
+    op("MOV", &[Register("D"), Register("B")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("D"), Register("C")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("D"), Register("D")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("D"), Register("E")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("D"), Register("H")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("D"), Register("L")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("D"), Register("M")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("D"), Register("A")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("E"), Register("B")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("E"), Register("C")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("E"), Register("D")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("E"), Register("E")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("E"), Register("H")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("E"), Register("L")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("E"), Register("M")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("E"), Register("A")], 1, (5, 5), NONE, Transfer),
+    //60..6F
+    op("MOV", &[Register("H"), Register("B")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("H"), Register("C")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("H"), Register("D")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("H"), Register("E")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("H"), Register("H")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("H"), Register("L")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("H"), Register("M")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("H"), Register("A")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("L"), Register("B")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("L"), Register("C")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("L"), Register("D")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("L"), Register("E")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("L"), Register("H")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("L"), Register("L")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("L"), Register("M")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("L"), Register("A")], 1, (5, 5), NONE, Transfer),
+    //70..7F
+    op("MOV", &[Register("M"), Register("B")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("M"), Register("C")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("M"), Register("D")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("M"), Register("E")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("M"), Register("H")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("M"), Register("L")], 1, (7, 7), NONE, Transfer),
+    op("HLT", &[], 1, (7, 7), NONE, Control),
+    op("MOV", &[Register("M"), Register("A")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("A"), Register("B")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("A"), Register("C")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("A"), Register("D")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("A"), Register("E")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("A"), Register("H")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("A"), Register("L")], 1, (5, 5), NONE, Transfer),
+    op("MOV", &[Register("A"), Register("M")], 1, (7, 7), NONE, Transfer),
+    op("MOV", &[Register("A"), Register("A")], 1, (5, 5), NONE, Transfer),
+    //80..8F
+    op("ADD", &[Register("B")], 1, (4, 4), ALL, Arithmetic),
+    op("ADD", &[Register("C")], 1, (4, 4), ALL, Arithmetic),
+    op("ADD", &[Register("D")], 1, (4, 4), ALL, Arithmetic),
+    op("ADD", &[Register("E")], 1, (4, 4), ALL, Arithmetic),
+    op("ADD", &[Register("H")], 1, (4, 4), ALL, Arithmetic),
+    op("ADD", &[Register("L")], 1, (4, 4), ALL, Arithmetic),
+    op("ADD", &[Register("M")], 1, (7, 7), ALL, Arithmetic),
+    op("ADD", &[Register("A")], 1, (4, 4), ALL, Arithmetic),
+    op("ADC", &[Register("B")], 1, (4, 4), ALL, Arithmetic),
+    op("ADC", &[Register("C")], 1, (4, 4), ALL, Arithmetic),
+    op("ADC", &[Register("D")], 1, (4, 4), ALL, Arithmetic),
+    op("ADC", &[Register("E")], 1, (4, 4), ALL, Arithmetic),
+    op("ADC", &[Register("H")], 1, (4, 4), ALL, Arithmetic),
+    op("ADC", &[Register("L")], 1, (4, 4), ALL, Arithmetic),
+    op("ADC", &[Register("M")], 1, (7, 7), ALL, Arithmetic),
+    op("ADC", &[Register("A")], 1, (4, 4), ALL, Arithmetic),
+    //90..9F
+    op("SUB", &[Register("B")], 1, (4, 4), ALL, Arithmetic),
+    op("SUB", &[Register("C")], 1, (4, 4), ALL, Arithmetic),
+    op("SUB", &[Register("D")], 1, (4, 4), ALL, Arithmetic),
+    op("SUB", &[Register("E")], 1, (4, 4), ALL, Arithmetic),
+    op("SUB", &[Register("H")], 1, (4, 4), ALL, Arithmetic),
+    op("SUB", &[Register("L")], 1, (4, 4), ALL, Arithmetic),
+    op("SUB", &[Register("M")], 1, (7, 7), ALL, Arithmetic),
+    op("SUB", &[Register("A")], 1, (4, 4), ALL, Arithmetic),
+    op("SBB", &[Register("B")], 1, (4, 4), ALL, Arithmetic),
+    op("SBB", &[Register("C")], 1, (4, 4), ALL, Arithmetic),
+    op("SBB", &[Register("D")], 1, (4, 4), ALL, Arithmetic),
+    op("SBB", &[Register("E")], 1, (4, 4), ALL, Arithmetic),
+    op("SBB", &[Register("H")], 1, (4, 4), ALL, Arithmetic),
+    op("SBB", &[Register("L")], 1, (4, 4), ALL, Arithmetic),
+    op("SBB", &[Register("M")], 1, (7, 7), ALL, Arithmetic),
+    op("SBB", &[Register("A")], 1, (4, 4), ALL, Arithmetic),
+    //A0..AF
+    op("ANA", &[Register("B")], 1, (4, 4), ALL, Arithmetic),
+    op("ANA", &[Register("C")], 1, (4, 4), ALL, Arithmetic),
+    op("ANA", &[Register("D")], 1, (4, 4), ALL, Arithmetic),
+    op("ANA", &[Register("E")], 1, (4, 4), ALL, Arithmetic),
+    op("ANA", &[Register("H")], 1, (4, 4), ALL, Arithmetic),
+    op("ANA", &[Register("L")], 1, (4, 4), ALL, Arithmetic),
+    op("ANA", &[Register("M")], 1, (7, 7), ALL, Arithmetic),
+    op("ANA", &[Register("A")], 1, (4, 4), ALL, Arithmetic),
+    op("XRA", &[Register("B")], 1, (4, 4), ALL, Arithmetic),
+    op("XRA", &[Register("C")], 1, (4, 4), ALL, Arithmetic),
+    op("XRA", &[Register("D")], 1, (4, 4), ALL, Arithmetic),
+    op("XRA", &[Register("E")], 1, (4, 4), ALL, Arithmetic),
+    op("XRA", &[Register("H")], 1, (4, 4), ALL, Arithmetic),
+    op("XRA", &[Register("L")], 1, (4, 4), ALL, Arithmetic),
+    op("XRA", &[Register("M")], 1, (7, 7), ALL, Arithmetic),
+    op("XRA", &[Register("A")], 1, (4, 4), ALL, Arithmetic),
+    //B0..BF
+    op("ORA", &[Register("B")], 1, (4, 4), ALL, Arithmetic),
+    op("ORA", &[Register("C")], 1, (4, 4), ALL, Arithmetic),
+    op("ORA", &[Register("D")], 1, (4, 4), ALL, Arithmetic),
+    op("ORA", &[Register("E")], 1, (4, 4), ALL, Arithmetic),
+    op("ORA", &[Register("H")], 1, (4, 4), ALL, Arithmetic),
+    op("ORA", &[Register("L")], 1, (4, 4), ALL, Arithmetic),
+    op("ORA", &[Register("M")], 1, (7, 7), ALL, Arithmetic),
+    op("ORA", &[Register("A")], 1, (4, 4), ALL, Arithmetic),
+    op("CMP", &[Register("B")], 1, (4, 4), ALL, Arithmetic),
+    op("CMP", &[Register("C")], 1, (4, 4), ALL, Arithmetic),
+    op("CMP", &[Register("D")], 1, (4, 4), ALL, Arithmetic),
+    op("CMP", &[Register("E")], 1, (4, 4), ALL, Arithmetic),
+    op("CMP", &[Register("H")], 1, (4, 4), ALL, Arithmetic),
+    op("CMP", &[Register("L")], 1, (4, 4), ALL, Arithmetic),
+    op("CMP", &[Register("M")], 1, (7, 7), ALL, Arithmetic),
+    op("CMP", &[Register("A")], 1, (4, 4), ALL, Arithmetic),
+    //C0..CF
+    op("RNZ", &[], 1, (11, 5), NONE, Branch),
+    op("POP", &[Pair("B")], 1, (10, 10), NONE, Transfer),
+    op("JNZ", &[Address], 3, (10, 10), NONE, Branch),
+    op("JMP", &[Address], 3, (10, 10), NONE, Branch),
+    op("CNZ", &[Address], 3, (17, 11), NONE, Branch),
+    op("PUSH", &[Pair("B")], 1, (11, 11), NONE, Transfer),
+    op("ADI", &[Data8], 2, (7, 7), ALL, Arithmetic),
+    op("RST", &[Vector(0)], 1, (11, 11), NONE, Branch),
+    op("RZ", &[], 1, (11, 5), NONE, Branch),
+    op("RET", &[], 1, (10, 10), NONE, Branch),
+    op("JZ", &[Address], 3, (10, 10), NONE, Branch),
+    undocumented(op("JMP", &[Address], 3, (10, 10), NONE, Branch)),
+    op("CZ", &[Address], 3, (17, 11), NONE, Branch),
+    op("CALL", &[Address], 3, (17, 17), NONE, Branch),
+    op("ACI", &[Data8], 2, (7, 7), ALL, Arithmetic),
+    op("RST", &[Vector(1)], 1, (11, 11), NONE, Branch),
+    //D0..DF
+    op("RNC", &[], 1, (11, 5), NONE, Branch),
+    op("POP", &[Pair("D")], 1, (10, 10), NONE, Transfer),
+    op("JNC", &[Address], 3, (10, 10), NONE, Branch),
+    op("OUT", &[Port], 2, (10, 10), NONE, InputOutput),
+    op("CNC", &[Address], 3, (17, 11), NONE, Branch),
+    op("PUSH", &[Pair("D")], 1, (11, 11), NONE, Transfer),
+    op("SUI", &[Data8], 2, (7, 7), ALL, Arithmetic),
+    op("RST", &[Vector(2)], 1, (11, 11), NONE, Branch),
+    op("RC", &[], 1, (11, 5), NONE, Branch),
+    undocumented(op("RET", &[], 1, (10, 10), NONE, Branch)),
+    op("JC", &[Address], 3, (10, 10), NONE, Branch),
+    op("IN", &[Port], 2, (10, 10), NONE, InputOutput),
+    op("CC", &[Address], 3, (17, 11), NONE, Branch),
+    undocumented(op("CALL", &[Address], 3, (17, 17), NONE, Branch)),
+    op("SBI", &[Data8], 2, (7, 7), ALL, Arithmetic),
+    op("RST", &[Vector(3)], 1, (11, 11), NONE, Branch),
+    //E0..EF
+    op("RPO", &[], 1, (11, 5), NONE, Branch),
+    op("POP", &[Pair("H")], 1, (10, 10), NONE, Transfer),
+    op("JPO", &[Address], 3, (10, 10), NONE, Branch),
+    op("XTHL", &[], 1, (18, 18), NONE, Transfer),
+    op("CPO", &[Address], 3, (17, 11), NONE, Branch),
+    op("PUSH", &[Pair("H")], 1, (11, 11), NONE, Transfer),
+    op("ANI", &[Data8], 2, (7, 7), ALL, Arithmetic),
+    op("RST", &[Vector(4)], 1, (11, 11), NONE, Branch),
+    op("RPE", &[], 1, (11, 5), NONE, Branch),
+    op("PCHL", &[], 1, (5, 5), NONE, Branch),
+    op("JPE", &[Address], 3, (10, 10), NONE, Branch),
+    op("XCHG", &[], 1, (4, 4), NONE, Transfer),
+    op("CPE", &[Address], 3, (17, 11), NONE, Branch),
+    undocumented(op("CALL", &[Address], 3, (17, 17), NONE, Branch)),
+    op("XRI", &[Data8], 2, (7, 7), ALL, Arithmetic),
+    op("RST", &[Vector(5)], 1, (11, 11), NONE, Branch),
+    //F0..FF
+    op("RP", &[], 1, (11, 5), NONE, Branch),
+    op("POP", &[Pair("PSW")], 1, (10, 10), ALL, Transfer),
+    op("JP", &[Address], 3, (10, 10), NONE, Branch),
+    op("DI", &[], 1, (4, 4), NONE, Control),
+    op("CP", &[Address], 3, (17, 11), NONE, Branch),
+    op("PUSH", &[Pair("PSW")], 1, (11, 11), NONE, Transfer),
+    op("ORI", &[Data8], 2, (7, 7), ALL, Arithmetic),
+    op("RST", &[Vector(6)], 1, (11, 11), NONE, Branch),
+    op("RM", &[], 1, (11, 5), NONE, Branch),
+    op("SPHL", &[], 1, (5, 5), NONE, Transfer),
+    op("JM", &[Address], 3, (10, 10), NONE, Branch),
+    op("EI", &[], 1, (4, 4), NONE, Control),
+    op("CM", &[Address], 3, (17, 11), NONE, Branch),
+    undocumented(op("CALL", &[Address], 3, (17, 17), NONE, Branch)),
+    op("CPI", &[Data8], 2, (7, 7), ALL, Arithmetic),
+    op("RST", &[Vector(7)], 1, (11, 11), NONE, Branch),
+];
+
+//Names of the flags in the mask, in the order of the bits in F
+pub fn flag_names(flags: u8) -> String {
+    [
+        (SIGN, 'S'),
+        (ZERO, 'Z'),
+        (AUX_CARRY, 'A'),
+        (PARITY, 'P'),
+        (CARRY, 'C'),
+    ]
+    .iter()
+    .map(|(flag, name)| if flags & flag != 0 { *name } else { '-' })
+    .collect()
+}
+
+//The documented opcode an undocumented one runs as, documented opcodes are their own twin
+pub fn twin(opcode: u8) -> u8 {
+    let alias = &OPCODES[opcode as usize];
+    (0..=0xFF)
+        .find(|twin| {
+            let twin = &OPCODES[*twin as usize];
+            twin.documented && twin.mnemonic == alias.mnemonic && twin.operands == alias.operands
+        })
+        .unwrap_or(opcode)
+}
@@ -60,7 +60,7 @@ impl Registers {
         ((*dw.0 as u16) << 8) | *dw.1 as u16
     }
 
-    pub fn set_dw_reg(&mut self, b: u8, value: u16) -> () {
+    pub fn set_dw_reg(&mut self, b: u8, value: u16) {
         let dw = self.bin_as_dregister(b);
         *dw.0 = (value >> 8) as u8;
         *dw.1 = (value & 0xFF) as u8;
@@ -93,17 +93,14 @@ impl Registers {
 
 //implementation for flags(F register)
 impl Registers {
-    pub fn set_flag(&mut self, flag: Flag, value: bool) -> () {
-        self.f |= (value as u8) << flag as u8;
+    pub fn set_flag(&mut self, flag: Flag, value: bool) {
+        let bit = flag as u8;
+        self.f = self.f & !(1 << bit) | (value as u8) << bit;
     }
 
     pub fn get_flag(&self, flag: Flag) -> bool {
         (self.f >> (flag as u8) & 1) != 0
     }
-
-    pub fn clr(&mut self) {
-        self.f = 0;
-    }
 }
 
 impl fmt::Debug for Registers {
@@ -0,0 +1,156 @@
+use std::{convert::TryInto, fmt};
+
+use crate::cpu::Cpu;
+
+/* Machine state in a file, all numbers little endian:
+ * magic      I8080SNP
+ * version    u16, VERSION
+ * registers  A F B C D E H L, then SP and PC as u16
+ * state      u8, bit 0 interrupts enabled, bit 1 halted
+ * cycles     u64
+ * memory     64K
+ * devices    u16 count, then per device a u8 name length, the name, a u32 length and its data
+ * Devices are sections of their own so new ones do not change the version; `ports` is the
+ * last value written to each of the 256 ports.
+ */
+const MAGIC: &[u8; 8] = b"I8080SNP";
+pub const VERSION: u16 = 1;
+const MEMORY: usize = 0x10000;
+const INTERRUPTS: u8 = 1;
+const HALTED: u8 = 2;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    NotSnapshot,
+    Version(u16),
+    Truncated,
+    UnknownDevice(String),
+    DeviceSize { name: String, size: usize },
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::NotSnapshot => write!(fmt, "not a snapshot"),
+            SnapshotError::Version(version) => write!(
+                fmt,
+                "snapshot version {} is not supported, this build reads version {}",
+                version, VERSION
+            ),
+            SnapshotError::Truncated => write!(fmt, "the snapshot ends early"),
+            SnapshotError::UnknownDevice(name) => write!(fmt, "unknown device `{}`", name),
+            SnapshotError::DeviceSize { name, size } => {
+                write!(fmt, "device `{}` has {} bytes of state", name, size)
+            }
+            SnapshotError::TrailingBytes(count) => {
+                write!(fmt, "{} bytes after the end of the snapshot", count)
+            }
+        }
+    }
+}
+
+pub fn save(cpu: &Cpu) -> Vec<u8> {
+    let r = &cpu.registers;
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bytes.extend_from_slice(&[r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l]);
+    bytes.extend_from_slice(&r.sp.to_le_bytes());
+    bytes.extend_from_slice(&r.pc.to_le_bytes());
+    let state = if cpu.interrupts { INTERRUPTS } else { 0 } | if cpu.halted { HALTED } else { 0 };
+    bytes.push(state);
+    bytes.extend_from_slice(&cpu.cycles.to_le_bytes());
+    bytes.extend_from_slice(&cpu.memory.read(0, 0xFFFF));
+    let devices: [(&str, &[u8]); 1] = [("ports", &cpu.ports)];
+    bytes.extend_from_slice(&(devices.len() as u16).to_le_bytes());
+    for (name, state) in &devices {
+        bytes.push(name.len() as u8);
+        bytes.extend_from_slice(name.as_bytes());
+        bytes.extend_from_slice(&(state.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(state);
+    }
+    bytes
+}
+
+//Bytes of the snapshot in the order they are read
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < count {
+            return Err(SnapshotError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/* Restore the machine of a snapshot into `cpu`, which is left alone when the snapshot is
+ * broken. Settings like strict mode, breakpoints and observers stay as they are.
+ */
+pub fn load(cpu: &mut Cpu, bytes: &[u8]) -> Result<(), SnapshotError> {
+    if !bytes.starts_with(MAGIC) {
+        return Err(SnapshotError::NotSnapshot);
+    }
+    let mut reader = Reader {
+        bytes: &bytes[MAGIC.len()..],
+    };
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(SnapshotError::Version(version));
+    }
+    let registers = reader.take(8)?;
+    let (sp, pc) = (reader.u16()?, reader.u16()?);
+    let state = reader.take(1)?[0];
+    let cycles = reader.u64()?;
+    let memory = reader.take(MEMORY)?;
+    let mut ports = None;
+    for _ in 0..reader.u16()? {
+        let length = reader.take(1)?[0] as usize;
+        let name = String::from_utf8_lossy(reader.take(length)?).into_owned();
+        let length = reader.u32()? as usize;
+        let data = reader.take(length)?;
+        match name.as_str() {
+            "ports" if data.len() == cpu.ports.len() => ports = Some(data),
+            "ports" => return Err(SnapshotError::DeviceSize { name, size: length }),
+            _ => return Err(SnapshotError::UnknownDevice(name)),
+        }
+    }
+    if !reader.bytes.is_empty() {
+        return Err(SnapshotError::TrailingBytes(reader.bytes.len()));
+    }
+
+    let r = &mut cpu.registers;
+    if let [a, f, b, c, d, e, h, l] = *registers {
+        (r.a, r.f, r.b, r.c, r.d, r.e, r.h, r.l) = (a, f, b, c, d, e, h, l);
+    }
+    r.sp = sp;
+    r.pc = pc;
+    cpu.interrupts = state & INTERRUPTS != 0;
+    cpu.halted = state & HALTED != 0;
+    cpu.cycles = cycles;
+    cpu.memory.load(0, memory);
+    //A device missing from the snapshot starts as it does on power up
+    match ports {
+        Some(ports) => cpu.ports.copy_from_slice(ports),
+        None => cpu.ports.iter_mut().for_each(|port| *port = 0),
+    }
+    cpu.hits.clear();
+    cpu.events.clear();
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use std::fmt::Write;
+
+use super::{
+    hex::hex_bytes,
+    image::{Image, ParseError},
+};
+
+const BYTES_PER_RECORD: usize = 16;
+
+//Bytes of a record after its type: count, address, data; the checksum is checked
+fn record_bytes(digits: &str) -> Result<Vec<u8>, String> {
+    let bytes = hex_bytes(digits)?;
+    if bytes.len() < 4 || bytes.len() != bytes[0] as usize + 1 {
+        return Err("record length does not match its byte count".to_string());
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 1);
+    let expected = checksum_of(body);
+    if checksum[0] != expected {
+        return Err(format!(
+            "checksum is {:02X}H, expected {:02X}H",
+            checksum[0], expected
+        ));
+    }
+    Ok(body.to_vec())
+}
+
+//Ones' complement of the sum of count, address and data
+fn checksum_of(bytes: &[u8]) -> u8 {
+    !bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+//S1 data records with 16-bit addresses up to the S9 record with the start address
+pub fn parse(text: &str) -> Result<Image, ParseError> {
+    let mut image = Image {
+        segments: Vec::new(),
+        start: None,
+    };
+    let mut records = 0;
+    let mut lines = 0;
+    for (index, line) in text.lines().enumerate() {
+        lines = index + 1;
+        let error = |message: String| ParseError {
+            line: index + 1,
+            message,
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        //The type is one ASCII character, `get` gives nothing when it is missing or multibyte
+        let (kind, digits) = line
+            .strip_prefix('S')
+            .and_then(|rest| Some((rest.get(..1)?, rest.get(1..)?)))
+            .ok_or_else(|| error("a record starts with `S` and its type".to_string()))?;
+        let bytes = match kind {
+            "0" | "1" | "5" | "9" => record_bytes(digits).map_err(error)?,
+            "2" | "3" | "7" | "8" => {
+                let message = format!(
+                    "S{} records have addresses beyond 16 bits, only S1 and S9 are supported",
+                    kind
+                );
+                return Err(error(message));
+            }
+            _ => return Err(error(format!("unknown record type S{}", kind))),
+        };
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let data = &bytes[3..];
+        match kind {
+            "1" => {
+                if address as usize + data.len() > 0x10000 {
+                    return Err(error(format!("data at {:04X}H runs beyond 64K", address)));
+                }
+                image.add(address, data);
+                records += 1;
+            }
+            //Number of S1 records so far
+            "5" if address != records => {
+                return Err(error(format!(
+                    "record count is {}, there were {} data records",
+                    address, records
+                )))
+            }
+            "9" => {
+                image.start = Some(address);
+                return Ok(image);
+            }
+            _ => (),
+        }
+    }
+    Err(ParseError {
+        line: lines + 1,
+        message: "missing S9 end record".to_string(),
+    })
+}
+
+//An empty S0 header, S1 records of 16 bytes and the S9 record with the start address or 0
+pub fn to_text(image: &Image) -> String {
+    let mut out = String::new();
+    let mut record = |kind: u8, address: u16, data: &[u8]| {
+        let mut bytes = vec![data.len() as u8 + 3];
+        bytes.extend_from_slice(&address.to_be_bytes());
+        bytes.extend_from_slice(data);
+        bytes.push(checksum_of(&bytes));
+        let digits: String = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        writeln!(out, "S{}{}", kind, digits).unwrap();
+    };
+    record(0, 0, &[]);
+    for segment in &image.segments {
+        for (i, chunk) in segment.data.chunks(BYTES_PER_RECORD).enumerate() {
+            let address = segment.address.wrapping_add((i * BYTES_PER_RECORD) as u16);
+            record(1, address, chunk);
+        }
+    }
+    record(9, image.start.unwrap_or(0), &[]);
+    out
+}
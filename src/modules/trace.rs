@@ -0,0 +1,391 @@
+use crate::cpu::Cpu;
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+use super::{
+    assembler::disassembler::{self, Syntax},
+    memory::Memory,
+    observer::Observer,
+    opcodes::{self, AUX_CARRY, CARRY, OPCODES, PARITY, SIGN, ZERO},
+    registers::Registers,
+};
+
+/* Columns of a trace line, each of a fixed width so traces of different runs line up:
+ * pc      0100
+ * bytes   31 00 F0
+ * dis     LXI SP,0F000H
+ * regs    A=00 BC=0000 DE=0000 HL=0000 SP=0000
+ * flags   F=02 -----
+ * cycles  CYC=0
+ * writes  W=0FFE:03,0FFF:00, memory the instruction wrote, always last and only when there are
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Pc,
+    Bytes,
+    Disassembly,
+    Registers,
+    Flags,
+    Cycles,
+    Writes,
+}
+
+pub const DEFAULT_FIELDS: &str = "pc,bytes,dis,regs,flags,cycles,writes";
+
+impl Field {
+    pub fn parse(name: &str) -> Option<Field> {
+        match name {
+            "pc" => Some(Field::Pc),
+            "bytes" => Some(Field::Bytes),
+            "dis" => Some(Field::Disassembly),
+            "regs" => Some(Field::Registers),
+            "flags" => Some(Field::Flags),
+            "cycles" => Some(Field::Cycles),
+            "writes" => Some(Field::Writes),
+            _ => None,
+        }
+    }
+
+    //Comma separated names like DEFAULT_FIELDS
+    pub fn parse_list(names: &str) -> Result<Vec<Field>, String> {
+        let fields = names
+            .split(',')
+            .map(|name| {
+                Field::parse(name.trim()).ok_or_else(|| {
+                    format!(
+                        "unknown trace field `{}`, use {}",
+                        name,
+                        DEFAULT_FIELDS.replace(',', ", ")
+                    )
+                })
+            })
+            .collect::<Result<Vec<Field>, String>>()?;
+        match fields.iter().position(|field| *field == Field::Writes) {
+            Some(at) if at + 1 != fields.len() => {
+                Err("writes has to be the last field".to_string())
+            }
+            _ => Ok(fields),
+        }
+    }
+}
+
+//Longest disassembly, CALL NZ,0F000H and the like
+const TEXT_WIDTH: usize = 15;
+const BYTES_WIDTH: usize = 8;
+const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+pub struct Trace {
+    out: Box<dyn Write>,
+    fields: Vec<Field>,
+    //Only instructions in these ranges are traced, all of them without any
+    ranges: Vec<(u16, u16)>,
+    syntax: Syntax,
+    //Disassembly by address, kept as long as the bytes there stay the same
+    texts: HashMap<u16, ([u8; 3], String)>,
+    //The line of the running instruction waits for its writes
+    pending: bool,
+    writes: Vec<(u16, u8)>,
+    //First failed write, the trace stops there and the run reports it at the end
+    error: Option<io::Error>,
+}
+
+impl fmt::Debug for Trace {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "Trace {:?} in {:x?}", self.fields, self.ranges)
+    }
+}
+
+impl Trace {
+    pub fn new(
+        out: Box<dyn Write>,
+        fields: Vec<Field>,
+        ranges: Vec<(u16, u16)>,
+        syntax: Syntax,
+    ) -> Self {
+        Self {
+            out,
+            fields,
+            ranges,
+            syntax,
+            texts: HashMap::new(),
+            pending: false,
+            writes: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn records_writes(&self) -> bool {
+        self.fields.last() == Some(&Field::Writes)
+    }
+
+    //Start of the line for the instruction at PC, before it runs
+    fn start_line(
+        &mut self,
+        registers: &Registers,
+        memory: &Memory,
+        cycles: u64,
+    ) -> io::Result<()> {
+        let pc = registers.pc;
+        if !self.ranges.is_empty()
+            && !self
+                .ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&pc))
+        {
+            return Ok(());
+        }
+        let opcode = memory.program[pc];
+        let length = OPCODES[opcode as usize].length as usize;
+        let mut bytes = [0; 3];
+        for (i, byte) in bytes.iter_mut().enumerate().take(length) {
+            *byte = memory.program[pc.wrapping_add(i as u16)];
+        }
+        let r = registers;
+        let count = self.fields.len() - self.records_writes() as usize;
+        for (i, field) in self.fields[..count].iter().enumerate() {
+            if i > 0 {
+                self.out.write_all(b"  ")?;
+            }
+            let last = i + 1 == count;
+            match field {
+                Field::Pc => write!(self.out, "{:04X}", pc)?,
+                Field::Bytes => {
+                    let mut shown = [b' '; BYTES_WIDTH];
+                    for (i, byte) in bytes[..length].iter().enumerate() {
+                        shown[i * 3] = DIGITS[(byte >> 4) as usize];
+                        shown[i * 3 + 1] = DIGITS[(byte & 0xF) as usize];
+                    }
+                    self.out.write_all(&shown)?;
+                }
+                Field::Disassembly => {
+                    let cached = self.texts.get(&pc).filter(|(cached, _)| *cached == bytes);
+                    if cached.is_none() {
+                        let text = disassembler::disassemble(&bytes[..length], pc, self.syntax)
+                            .next()
+                            .map_or_else(String::new, |line| line.text);
+                        self.texts.insert(pc, (bytes, text));
+                    }
+                    let text = &self.texts[&pc].1;
+                    if last {
+                        self.out.write_all(text.as_bytes())?
+                    } else {
+                        write!(self.out, "{:<width$}", text, width = TEXT_WIDTH)?
+                    }
+                }
+                Field::Registers => write!(
+                    self.out,
+                    "A={:02X} BC={:02X}{:02X} DE={:02X}{:02X} HL={:02X}{:02X} SP={:04X}",
+                    r.a, r.b, r.c, r.d, r.e, r.h, r.l, r.sp
+                )?,
+                Field::Flags => write!(self.out, "F={:02X} {}", r.f, opcodes::flag_names(r.f))?,
+                Field::Cycles => {
+                    if last {
+                        write!(self.out, "CYC={}", cycles)?
+                    } else {
+                        write!(self.out, "CYC={:<10}", cycles)?
+                    }
+                }
+                Field::Writes => (),
+            }
+        }
+        self.pending = true;
+        Ok(())
+    }
+
+    //End of the line after the instruction ran, with the writes it made
+    fn end_line(&mut self) -> io::Result<()> {
+        self.pending = false;
+        if !self.writes.is_empty() {
+            self.out.write_all(b"  W=")?;
+            for (i, (address, value)) in self.writes.drain(..).enumerate() {
+                let separator = if i > 0 { "," } else { "" };
+                write!(self.out, "{}{:04X}:{:02X}", separator, address, value)?;
+            }
+        }
+        self.out.write_all(b"\n")
+    }
+}
+
+impl Observer for Trace {
+    fn before(&mut self, cpu: &Cpu) {
+        if self.error.is_none() {
+            let result = self.start_line(&cpu.registers, &cpu.memory, cpu.cycles);
+            self.error = result.err();
+        }
+    }
+
+    fn write(&mut self, _pc: u16, address: u16, _old: u8, new: u8) {
+        if self.pending && self.records_writes() {
+            self.writes.push((address, new));
+        }
+    }
+
+    fn after(&mut self, _cpu: &Cpu) {
+        if self.pending && self.error.is_none() {
+            self.error = self.end_line().err();
+        }
+    }
+
+    //A run that stops in the middle of an instruction leaves its line without writes
+    fn finish(&mut self) -> io::Result<()> {
+        if self.pending && self.error.is_none() {
+            self.error = self.end_line().err();
+        }
+        match self.error.take() {
+            Some(error) => Err(error),
+            None => self.out.flush(),
+        }
+    }
+}
+
+//Trace line taken apart to say what differs from another one
+#[derive(Debug, Default)]
+struct Record<'a> {
+    pc: Option<&'a str>,
+    //Bytes and disassembly
+    code: Vec<&'a str>,
+    //Registers, flags and cycles as NAME=value, in the order of the line
+    values: Vec<(&'a str, &'a str)>,
+    writes: Vec<(&'a str, &'a str)>,
+}
+
+fn record(line: &str) -> Record<'_> {
+    let mut record = Record::default();
+    let mut words = line.split_whitespace().enumerate();
+    while let Some((i, word)) = words.next() {
+        match word.split_once('=') {
+            Some(("W", writes)) => {
+                record.writes = writes
+                    .split(',')
+                    .filter_map(|w| w.split_once(':'))
+                    .collect()
+            }
+            //The flag letters after F say the same as its value
+            Some(value @ ("F", _)) => {
+                record.values.push(value);
+                words.next();
+            }
+            Some(value) => record.values.push(value),
+            None if i == 0 && word.len() == 4 => record.pc = Some(word),
+            None => record.code.push(word),
+        }
+    }
+    record
+}
+
+const FLAGS: [(u8, &str); 5] = [
+    (SIGN, "S"),
+    (ZERO, "Z"),
+    (AUX_CARRY, "AC"),
+    (PARITY, "P"),
+    (CARRY, "CY"),
+];
+
+//What the second line has differently, flags one by one
+fn differences(first: &Record, second: &Record) -> Vec<String> {
+    let mut out = Vec::new();
+    if first.pc != second.pc {
+        let (a, b) = (first.pc.unwrap_or("none"), second.pc.unwrap_or("none"));
+        out.push(format!("PC: {} / {}", a, b));
+    }
+    if first.code != second.code {
+        let (a, b) = (first.code.join(" "), second.code.join(" "));
+        out.push(format!("instruction: {} / {}", a, b));
+    }
+    for (name, a) in &first.values {
+        let b = second.values.iter().find(|(other, _)| other == name);
+        let b = b.map_or("none", |(_, b)| *b);
+        if *a == b {
+            continue;
+        }
+        match (*name, u8::from_str_radix(a, 16), u8::from_str_radix(b, 16)) {
+            ("F", Ok(a), Ok(b)) => {
+                for (bit, flag) in FLAGS.iter().filter(|(bit, _)| (a ^ b) & bit != 0) {
+                    let set = |f: u8| (f & bit != 0) as u8;
+                    out.push(format!("flag {}: {} / {}", flag, set(a), set(b)));
+                }
+                if (a ^ b) & !FLAGS.iter().fold(0, |all, (bit, _)| all | bit) != 0 {
+                    out.push(format!("F: {:02X} / {:02X}", a, b));
+                }
+            }
+            _ => out.push(format!("{}: {} / {}", name, a, b)),
+        }
+    }
+    let addresses: BTreeSet<&str> = first
+        .writes
+        .iter()
+        .chain(&second.writes)
+        .map(|(address, _)| *address)
+        .collect();
+    for address in addresses {
+        let value = |writes: &[(&str, &str)]| {
+            let values: Vec<&str> = writes
+                .iter()
+                .filter(|(at, _)| *at == address)
+                .map(|(_, value)| *value)
+                .collect();
+            if values.is_empty() {
+                "none".to_string()
+            } else {
+                values.join(",")
+            }
+        };
+        let (a, b) = (value(&first.writes), value(&second.writes));
+        if a != b {
+            out.push(format!("write {}: {} / {}", address, a, b));
+        }
+    }
+    out
+}
+
+/* Compare two traces instruction by instruction, None when they are the same. Otherwise the
+ * first differing lines after up to `context` lines before them, and what differs.
+ */
+pub fn diff(
+    first: impl BufRead,
+    second: impl BufRead,
+    context: usize,
+) -> io::Result<Option<String>> {
+    let (mut firsts, mut seconds) = (first.lines(), second.lines());
+    let mut before = VecDeque::with_capacity(context + 1);
+    let mut number = 0;
+    loop {
+        number += 1;
+        let (a, b) = match (firsts.next().transpose()?, seconds.next().transpose()?) {
+            (None, None) => return Ok(None),
+            (Some(a), Some(b)) if a == b => {
+                before.push_back(a);
+                if before.len() > context {
+                    before.pop_front();
+                }
+                continue;
+            }
+            (a, b) => (a, b),
+        };
+        let mut out = format!("first difference at instruction {}\n", number);
+        let start = number - before.len();
+        for (i, line) in before.iter().enumerate() {
+            out += &format!("  {:>8}  {}\n", start + i, line);
+        }
+        for (side, line) in [("<", &a), (">", &b)] {
+            let line = line.as_deref().unwrap_or("end of trace");
+            out += &format!("{} {:>8}  {}\n", side, number, line);
+        }
+        match (&a, &b) {
+            (Some(a), Some(b)) => {
+                let found = differences(&record(a), &record(b));
+                if found.is_empty() {
+                    out += "the lines differ in spacing\n";
+                } else {
+                    out += &format!("{}\n", found.join("\n"));
+                }
+            }
+            (None, _) => out += &format!("the first trace ends after {}\n", number - 1),
+            (_, None) => out += &format!("the second trace ends after {}\n", number - 1),
+        }
+        return Ok(Some(out));
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+
+//What a watchpoint stops on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trigger {
+    Read,
+    Write,
+    //A write that stores a different value
+    Change,
+}
+
+impl Trigger {
+    pub fn parse(name: &str) -> Option<Trigger> {
+        match name.to_ascii_lowercase().as_str() {
+            "r" | "read" => Some(Trigger::Read),
+            "w" | "write" => Some(Trigger::Write),
+            "c" | "change" => Some(Trigger::Change),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Trigger::Read => "read",
+            Trigger::Write => "write",
+            Trigger::Change => "change",
+        }
+    }
+}
+
+//Memory access by an instruction, fetching the instruction itself is none
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+//Addresses from `start` to `end` inclusive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+    pub trigger: Trigger,
+}
+
+impl Watchpoint {
+    pub fn triggers(&self, address: u16, access: Access, old: u8, new: u8) -> bool {
+        (self.start..=self.end).contains(&address)
+            && match (self.trigger, access) {
+                (Trigger::Read, Access::Read) | (Trigger::Write, Access::Write) => true,
+                (Trigger::Change, Access::Write) => old != new,
+                _ => false,
+            }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.start == self.end {
+            write!(fmt, "{:04X}H {}", self.start, self.trigger.name())
+        } else {
+            write!(
+                fmt,
+                "{:04X}H-{:04X}H {}",
+                self.start,
+                self.end,
+                self.trigger.name()
+            )
+        }
+    }
+}
+
+//Access that triggered a watchpoint, a read has the same old and new value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hit {
+    //Address of the instruction that accessed the memory
+    pub pc: u16,
+    pub address: u16,
+    pub access: Access,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.access {
+            Access::Read => write!(
+                fmt,
+                "read {:02X}H at {:04X}H by the instruction at {:04X}H",
+                self.new, self.address, self.pc
+            ),
+            Access::Write => write!(
+                fmt,
+                "write {:02X}H over {:02X}H at {:04X}H by the instruction at {:04X}H",
+                self.new, self.old, self.address, self.pc
+            ),
+        }
+    }
+}
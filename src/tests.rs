@@ -0,0 +1,767 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use crate::{
+    cpu::{Cpu, CpuError},
+    debugger::{self, Debugger},
+    modules::assembler::disassembler::Syntax,
+    modules::{
+        breaks::{Class, Direction, Event, PortBreak},
+        condition::{Condition, Machine},
+        hex,
+        image::{self, Image},
+        memory::Memory,
+        observer::Observer,
+        opcodes::{AUX_CARRY, CARRY, PARITY, SIGN, ZERO},
+        registers::Flag,
+        snapshot::{self, SnapshotError},
+        srec,
+        trace::{self, Field, Trace},
+        watch::{Access, Hit, Trigger, Watchpoint},
+    },
+};
+
+fn execute(cpu: &mut Cpu, program: &[u8]) -> Result<(), CpuError> {
+    cpu.memory.load(0, program);
+    cpu.run(program.len())
+}
+
+#[test]
+fn mov_and_hlt() {
+    //MVI A,5, MOV B,A and HLT, the MVI B,7 after it never runs
+    let program = [0x3E, 0x05, 0x47, 0x76, 0x06, 0x07];
+    let mut cpu = Cpu::new();
+    assert_eq!(execute(&mut cpu, &program), Ok(()));
+    assert_eq!((cpu.registers.a, cpu.registers.b), (0x05, 0x05));
+    assert_eq!(cpu.registers.pc, 4);
+}
+
+#[test]
+fn undocumented_aliases() {
+    //0x08 runs as NOP, 0xCB as JMP to the HLT
+    let program = vec![0x08, 0xCB, 0x05, 0x00, 0x3C, 0x76];
+    let mut cpu = Cpu::new();
+    assert_eq!(execute(&mut cpu, &program), Ok(()));
+    assert_eq!(cpu.registers.a, 0);
+    assert_eq!(cpu.cycles, 4 + 10 + 7);
+
+    let mut cpu = Cpu::new();
+    cpu.strict = true;
+    assert_eq!(
+        execute(&mut cpu, &program),
+        Err(CpuError::Undocumented {
+            address: 0,
+            opcode: 0x08
+        })
+    );
+}
+
+#[test]
+fn instructions() {
+    //BCD 15 + 27, PUSH PSW and POP B, then a subroutine that decrements B to zero
+    let program = vec![
+        0x31, 0x00, 0x10, 0x3E, 0x15, 0xC6, 0x27, 0x27, 0xF5, 0xC1, 0xCD, 0x0F, 0x00, 0x76, 0x00,
+        0x05, 0xC2, 0x0F, 0x00, 0xC9,
+    ];
+    let mut cpu = Cpu::new();
+    assert_eq!(execute(&mut cpu, &program), Ok(()));
+    assert!(cpu.halted);
+    assert_eq!(cpu.registers.a, 0x42);
+    assert_eq!(cpu.registers.c, 0x16);
+    assert_eq!(cpu.registers.b, 0);
+    assert_eq!(cpu.registers.f & 0x40, 0x40);
+    assert_eq!(cpu.registers.sp, 0x1000);
+}
+
+//A, F and the cycles after one instruction at 0, run with A, B and the carry set up
+fn single(program: &[u8], a: u8, b: u8, carry: bool) -> (u8, u8, u64) {
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, program);
+    cpu.registers.a = a;
+    cpu.registers.b = b;
+    cpu.registers.f = if carry { CARRY } else { 0 };
+    cpu.step().unwrap();
+    (cpu.registers.a, cpu.registers.f, cpu.cycles)
+}
+
+//Name, program, A, B and carry before, then A, F and the cycles after
+type FlagCase = (&'static str, &'static [u8], u8, u8, bool, (u8, u8, u64));
+
+#[test]
+fn instruction_flags() {
+    #[rustfmt::skip]
+    let cases: [FlagCase; 26] = [
+        ("ADD B", &[0x80], 0x3A, 0xC6, false, (0x00, ZERO | AUX_CARRY | PARITY | CARRY, 4)),
+        ("ADC B", &[0x88], 0x3D, 0x42, true, (0x80, SIGN | AUX_CARRY, 4)),
+        ("SUB B", &[0x90], 0x3E, 0x3E, false, (0x00, ZERO | AUX_CARRY | PARITY, 4)),
+        ("SBB B", &[0x98], 0x04, 0x02, true, (0x01, AUX_CARRY, 4)),
+        ("CMP B", &[0xB8], 0x02, 0x05, false, (0x02, SIGN | CARRY, 4)),
+        ("ANA B", &[0xA0], 0xFC, 0x0F, true, (0x0C, AUX_CARRY | PARITY, 4)),
+        ("XRA B", &[0xA8], 0x5C, 0x5C, true, (0x00, ZERO | PARITY, 4)),
+        ("ORA B", &[0xB0], 0x33, 0x0C, true, (0x3F, PARITY, 4)),
+        ("ADD M", &[0x86], 0x01, 0x00, false, (0x87, SIGN | PARITY, 7)),
+        ("ADI", &[0xC6, 0x01], 0x7F, 0x00, false, (0x80, SIGN | AUX_CARRY, 7)),
+        ("ACI", &[0xCE, 0x00], 0xFF, 0x00, true, (0x00, ZERO | AUX_CARRY | PARITY | CARRY, 7)),
+        ("SUI", &[0xD6, 0x01], 0x00, 0x00, false, (0xFF, SIGN | PARITY | CARRY, 7)),
+        ("SBI", &[0xDE, 0x00], 0x00, 0x00, true, (0xFF, SIGN | PARITY | CARRY, 7)),
+        ("CPI", &[0xFE, 0x40], 0x4A, 0x00, false, (0x4A, AUX_CARRY | PARITY, 7)),
+        ("INR A", &[0x3C], 0xFF, 0x00, true, (0x00, ZERO | AUX_CARRY | PARITY | CARRY, 5)),
+        ("DCR A", &[0x3D], 0x00, 0x00, false, (0xFF, SIGN | PARITY, 5)),
+        ("DAA", &[0x27], 0x9B, 0x00, false, (0x01, AUX_CARRY | CARRY, 4)),
+        ("RLC", &[0x07], 0xF2, 0x00, false, (0xE5, CARRY, 4)),
+        ("RRC", &[0x0F], 0xF2, 0x00, true, (0x79, 0, 4)),
+        ("RAL", &[0x17], 0xB5, 0x00, false, (0x6A, CARRY, 4)),
+        ("RAR", &[0x1F], 0x6A, 0x00, true, (0xB5, 0, 4)),
+        ("CMA", &[0x2F], 0x51, 0x00, true, (0xAE, CARRY, 4)),
+        ("STC", &[0x37], 0x00, 0x00, false, (0x00, CARRY, 4)),
+        ("CMC", &[0x3F], 0x00, 0x00, true, (0x00, 0, 4)),
+        ("MOV A,B", &[0x78], 0x00, 0x42, true, (0x42, CARRY, 5)),
+        ("MVI A", &[0x3E, 0x42], 0x00, 0x00, false, (0x42, 0, 7)),
+    ];
+    for (name, program, a, b, carry, expected) in cases.iter() {
+        assert_eq!(single(program, *a, *b, *carry), *expected, "{}", name);
+    }
+
+    //DAD only changes the carry
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, &[0x09, 0x19]);
+    cpu.registers.set_dw_reg(0, 0x339F);
+    cpu.registers.set_dw_reg(2, 0x2AE6);
+    cpu.registers.set_dw_reg(4, 0xA17B);
+    cpu.registers.f = ZERO;
+    cpu.step().unwrap();
+    assert_eq!(
+        (cpu.registers.get_dw_reg(4), cpu.registers.f),
+        (0xD51A, ZERO)
+    );
+    cpu.step().unwrap();
+    assert_eq!(
+        (cpu.registers.get_dw_reg(4), cpu.registers.f),
+        (0x0000, ZERO | CARRY)
+    );
+    assert_eq!(cpu.cycles, 20);
+}
+
+#[test]
+fn instruction_cycles() {
+    //Clock cycles of the 8080 manual, F is clear so Z and NZ give both sides of a condition
+    #[rustfmt::skip]
+    let cases: [(&str, &[u8], u64); 34] = [
+        ("NOP", &[0x00], 4), ("HLT", &[0x76], 7), ("MOV M,A", &[0x77], 7),
+        ("MOV A,M", &[0x7E], 7), ("MVI M", &[0x36, 0x00], 10), ("INR M", &[0x34], 10),
+        ("LXI B", &[0x01, 0x00, 0x00], 10), ("LDA", &[0x3A, 0x00, 0x00], 13),
+        ("STA", &[0x32, 0x00, 0x00], 13), ("LHLD", &[0x2A, 0x00, 0x00], 16),
+        ("SHLD", &[0x22, 0x00, 0x00], 16), ("LDAX B", &[0x0A], 7), ("STAX D", &[0x12], 7),
+        ("XCHG", &[0xEB], 4), ("INX B", &[0x03], 5), ("DCX SP", &[0x3B], 5),
+        ("DAD B", &[0x09], 10), ("JMP", &[0xC3, 0x00, 0x00], 10),
+        ("JZ", &[0xCA, 0x00, 0x00], 10), ("CALL", &[0xCD, 0x00, 0x00], 17),
+        ("CZ", &[0xCC, 0x00, 0x00], 11), ("CNZ", &[0xC4, 0x00, 0x00], 17), ("RET", &[0xC9], 10),
+        ("RZ", &[0xC8], 5), ("RNZ", &[0xC0], 11), ("RST 1", &[0xCF], 11), ("PCHL", &[0xE9], 5),
+        ("SPHL", &[0xF9], 5), ("PUSH B", &[0xC5], 11), ("POP PSW", &[0xF1], 10),
+        ("XTHL", &[0xE3], 18), ("IN", &[0xDB, 0x00], 10), ("OUT", &[0xD3, 0x00], 10),
+        ("EI", &[0xFB], 4),
+    ];
+    for (name, program, cycles) in cases.iter() {
+        assert_eq!(single(program, 0, 0, false).2, *cycles, "{}", name);
+    }
+}
+
+#[test]
+fn stack_instructions() {
+    //PUSH B from SP=0 goes to the top of memory, high byte first
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, &[0xC5, 0xF1, 0xE3, 0xCD, 0x34, 0x12]);
+    cpu.registers.set_dw_reg(0, 0x12FF);
+    cpu.step().unwrap();
+    assert_eq!(cpu.registers.sp, 0xFFFE);
+    assert_eq!(cpu.memory.read(0xFFFE, 0xFFFF), vec![0xFF, 0x12]);
+    //POP PSW keeps bit 1 of F set and bits 3 and 5 clear
+    cpu.step().unwrap();
+    assert_eq!((cpu.registers.a, cpu.registers.f), (0x12, 0xD7));
+    assert_eq!(cpu.registers.sp, 0x0000);
+    //XTHL swaps HL with the top of the stack
+    cpu.registers.sp = 0x10F0;
+    cpu.memory.load(0x10F0, &[0xF0, 0x0D]);
+    cpu.registers.set_dw_reg(4, 0x0B3C);
+    cpu.step().unwrap();
+    assert_eq!(cpu.registers.get_dw_reg(4), 0x0DF0);
+    assert_eq!(cpu.memory.read(0x10F0, 0x10F1), vec![0x3C, 0x0B]);
+    //CALL pushes the address after it
+    cpu.step().unwrap();
+    assert_eq!((cpu.registers.pc, cpu.registers.sp), (0x1234, 0x10EE));
+    assert_eq!(cpu.memory.read(0x10EE, 0x10EF), vec![0x06, 0x00]);
+}
+
+//Output the test can read back while the trace owns it
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn trace() {
+    //LXI SP,1000H; CALL 8; HLT; undocumented 08H, RET at 8
+    let program = vec![0x31, 0x00, 0x10, 0xCD, 0x08, 0x00, 0x76, 0x00, 0x08, 0xC9];
+    let out = Shared::default();
+    let fields = Field::parse_list(trace::DEFAULT_FIELDS).unwrap();
+    let mut cpu = Cpu::new();
+    cpu.registers.f = 0x02;
+    cpu.observe(Box::new(Trace::new(
+        Box::new(out.clone()),
+        fields,
+        Vec::new(),
+        Syntax::Intel,
+    )));
+    cpu.memory.load(0, &program);
+    cpu.run(7).unwrap();
+    assert_eq!(
+        String::from_utf8(out.0.borrow().clone()).unwrap(),
+        "\
+0000  31 00 10  LXI SP,1000H     A=00 BC=0000 DE=0000 HL=0000 SP=0000  F=02 -----  CYC=0
+0003  CD 08 00  CALL 0008H       A=00 BC=0000 DE=0000 HL=0000 SP=1000  F=02 -----  CYC=10  W=0FFE:06,0FFF:00
+0008  08        DB 08H           A=00 BC=0000 DE=0000 HL=0000 SP=0FFE  F=02 -----  CYC=27
+0009  C9        RET              A=00 BC=0000 DE=0000 HL=0000 SP=0FFE  F=02 -----  CYC=31
+0006  76        HLT              A=00 BC=0000 DE=0000 HL=0000 SP=1000  F=02 -----  CYC=41
+"
+    );
+
+    let out = Shared::default();
+    let fields = Field::parse_list("cycles, pc,dis").unwrap();
+    let mut cpu = Cpu::new();
+    cpu.observe(Box::new(Trace::new(
+        Box::new(out.clone()),
+        fields,
+        vec![(0x08, 0x0F)],
+        Syntax::Zilog,
+    )));
+    cpu.memory.load(0, &program);
+    cpu.run(7).unwrap();
+    assert_eq!(
+        String::from_utf8(out.0.borrow().clone()).unwrap(),
+        "CYC=27          0008  DB 08H\nCYC=31          0009  RET\n"
+    );
+    assert_eq!(
+        Field::parse_list("pc,registers").unwrap_err(),
+        "unknown trace field `registers`, use pc, bytes, dis, regs, flags, cycles, writes"
+    );
+    assert_eq!(
+        Field::parse_list("writes,pc").unwrap_err(),
+        "writes has to be the last field"
+    );
+}
+
+//Every hook as a line of text
+#[derive(Debug, Default)]
+struct Recorder(Rc<RefCell<Vec<String>>>);
+
+impl Observer for Recorder {
+    fn before(&mut self, cpu: &Cpu) {
+        let line = format!("before {:04X}", cpu.registers.pc);
+        self.0.borrow_mut().push(line);
+    }
+
+    fn after(&mut self, cpu: &Cpu) {
+        let line = format!("after {:04X} {}", cpu.registers.pc, cpu.cycles);
+        self.0.borrow_mut().push(line);
+    }
+
+    fn read(&mut self, pc: u16, address: u16, value: u8) {
+        let line = format!("read {:04X} {:04X} {:02X}", pc, address, value);
+        self.0.borrow_mut().push(line);
+    }
+
+    fn write(&mut self, pc: u16, address: u16, old: u8, new: u8) {
+        let line = format!("write {:04X} {:04X} {:02X} {:02X}", pc, address, old, new);
+        self.0.borrow_mut().push(line);
+    }
+
+    fn input(&mut self, pc: u16, port: u8, value: u8) {
+        let line = format!("in {:04X} {:02X} {:02X}", pc, port, value);
+        self.0.borrow_mut().push(line);
+    }
+
+    fn output(&mut self, pc: u16, port: u8, value: u8) {
+        let line = format!("out {:04X} {:02X} {:02X}", pc, port, value);
+        self.0.borrow_mut().push(line);
+    }
+
+    fn interrupt(&mut self, pc: u16, vector: u8) {
+        let line = format!("interrupt {:04X} {}", pc, vector);
+        self.0.borrow_mut().push(line);
+    }
+
+    fn halt(&mut self, pc: u16) {
+        self.0.borrow_mut().push(format!("halt {:04X}", pc));
+    }
+}
+
+#[test]
+fn observers() {
+    //LDA 10H; OUT 1; IN 1; EI; HLT, RET at 18H for RST 3
+    let program = vec![0x3A, 0x10, 0x00, 0xD3, 0x01, 0xDB, 0x01, 0xFB, 0x76];
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let mut cpu = Cpu::new();
+    cpu.registers.sp = 0x100;
+    cpu.memory.load(0x18, &[0xC9]);
+    cpu.memory.load(0x10, &[0x5A]);
+    cpu.observe(Box::new(Recorder(lines.clone())));
+    execute(&mut cpu, &program).unwrap();
+    assert!(cpu.interrupt(3));
+    //Interrupts stay disabled until the next EI
+    assert!(!cpu.interrupt(3));
+    cpu.step().unwrap();
+    assert_eq!(
+        lines.borrow().join("\n"),
+        "\
+before 0000
+read 0000 0010 5A
+after 0003 13
+before 0003
+out 0003 01 5A
+after 0005 23
+before 0005
+in 0005 01 5A
+after 0007 33
+before 0007
+after 0008 37
+before 0008
+halt 0008
+after 0009 44
+write 0009 00FE 00 09
+write 0009 00FF 00 00
+interrupt 0018 3
+before 0018
+read 0018 00FE 09
+read 0018 00FF 00
+after 0009 65"
+    );
+}
+
+#[test]
+fn trace_diff() {
+    let first = "\
+0000  31 00 10  LXI SP,1000H     A=00 BC=0000 DE=0000 HL=0000 SP=0000  F=02 -----  CYC=0
+0003  CD 08 00  CALL 0008H       A=00 BC=0000 DE=0000 HL=0000 SP=1000  F=02 -----  CYC=10  W=0FFE:06,0FFF:00
+0008  3C        INR A            A=00 BC=0000 DE=0000 HL=0000 SP=0FFE  F=02 -----  CYC=27
+0009  C9        RET              A=01 BC=0000 DE=0000 HL=0000 SP=0FFE  F=02 -----  CYC=32
+";
+    assert_eq!(
+        trace::diff(first.as_bytes(), first.as_bytes(), 5).unwrap(),
+        None
+    );
+    let second = first
+        .replace(
+            "A=01 BC=0000 DE=0000 HL=0000 SP=0FFE  F=02 -----",
+            "A=00 BC=0000 DE=0000 HL=0000 SP=0FFE  F=46 -Z-P-",
+        )
+        .replace("CYC=32", "CYC=33");
+    assert_eq!(
+        trace::diff(first.as_bytes(), second.as_bytes(), 1).unwrap().unwrap(),
+        "\
+first difference at instruction 4
+         3  0008  3C        INR A            A=00 BC=0000 DE=0000 HL=0000 SP=0FFE  F=02 -----  CYC=27
+<        4  0009  C9        RET              A=01 BC=0000 DE=0000 HL=0000 SP=0FFE  F=02 -----  CYC=32
+>        4  0009  C9        RET              A=00 BC=0000 DE=0000 HL=0000 SP=0FFE  F=46 -Z-P-  CYC=33
+A: 01 / 00
+flag Z: 0 / 1
+flag P: 0 / 1
+CYC: 32 / 33
+"
+    );
+    let second = first.replace("0FFE:06,0FFF:00", "0FFE:06");
+    let report = trace::diff(first.as_bytes(), second.as_bytes(), 0)
+        .unwrap()
+        .unwrap();
+    assert!(report.ends_with("W=0FFE:06\nwrite 0FFF: 00 / none\n"));
+    let second: String = first.split_inclusive('\n').take(2).collect();
+    let report = trace::diff(first.as_bytes(), second.as_bytes(), 0)
+        .unwrap()
+        .unwrap();
+    assert!(report.ends_with(">        3  end of trace\nthe second trace ends after 2\n"));
+}
+
+#[test]
+fn watchpoints() {
+    //LDA 20H, STA 20H, INR A, STA 21H, HLT
+    let program = vec![
+        0x3A, 0x20, 0x00, 0x32, 0x20, 0x00, 0x3C, 0x32, 0x21, 0x00, 0x76,
+    ];
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0x20, &[0x41, 0x10]);
+    cpu.watchpoints.push(Watchpoint {
+        start: 0x20,
+        end: 0x21,
+        trigger: Trigger::Change,
+    });
+    //Storing the value that is already there is no change
+    let hit = Hit {
+        pc: 0x07,
+        address: 0x21,
+        access: Access::Write,
+        old: 0x10,
+        new: 0x42,
+    };
+    assert_eq!(
+        execute(&mut cpu, &program),
+        Err(CpuError::Watchpoint(vec![hit]))
+    );
+    assert_eq!(cpu.registers.pc, 0x0A);
+
+    let mut cpu = Cpu::new();
+    cpu.watchpoints.push(Watchpoint {
+        start: 0x20,
+        end: 0x20,
+        trigger: Trigger::Read,
+    });
+    let error = execute(&mut cpu, &program).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "watchpoint, read 00H at 0020H by the instruction at 0000H"
+    );
+}
+
+#[test]
+fn port_and_class_breaks() {
+    //MVI A,41H; OUT 2; OUT 3; IN 2; EI; LXI H,0DH; PCHL; RST 0 at 0DH
+    let program = vec![
+        0x3E, 0x41, 0xD3, 0x02, 0xD3, 0x03, 0xDB, 0x02, 0xFB, 0x21, 0x0D, 0x00, 0xE9,
+    ];
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, &program);
+    cpu.memory.load(0x0D, &[0xC7]);
+    cpu.port_breaks.push(PortBreak {
+        start: 0x02,
+        end: 0x02,
+        direction: Some(Direction::In),
+        value: None,
+    });
+    cpu.port_breaks.push(PortBreak {
+        start: 0x03,
+        end: 0x04,
+        direction: None,
+        value: Some(0x41),
+    });
+    cpu.class_breaks = vec![Class::Interrupts, Class::Pchl, Class::Rst];
+    for _ in 0..8 {
+        cpu.step().unwrap();
+    }
+    assert_eq!(
+        cpu.events,
+        vec![
+            Event::Port {
+                pc: 0x04,
+                direction: Direction::Out,
+                port: 0x03,
+                value: 0x41
+            },
+            Event::Port {
+                pc: 0x06,
+                direction: Direction::In,
+                port: 0x02,
+                value: 0x41
+            },
+            Event::Instruction {
+                pc: 0x08,
+                class: Class::Interrupts,
+                opcode: 0xFB
+            },
+            Event::Instruction {
+                pc: 0x0C,
+                class: Class::Pchl,
+                opcode: 0xE9
+            },
+            Event::Instruction {
+                pc: 0x0D,
+                class: Class::Rst,
+                opcode: 0xC7
+            },
+        ]
+    );
+
+    let mut cpu = Cpu::new();
+    cpu.class_breaks.push(Class::Undocumented);
+    execute(&mut cpu, &[0x00, 0xCB, 0x04, 0x00, 0x76]).unwrap();
+    assert_eq!(
+        cpu.events.iter().map(Event::to_string).collect::<Vec<_>>(),
+        ["undocumented opcode CBH at 0001H"]
+    );
+}
+
+#[test]
+fn conditions() {
+    let mut cpu = Cpu::new();
+    cpu.registers.a = 0x1B;
+    cpu.registers.set_flag(Flag::Zero, true);
+    cpu.registers.set_dw_reg(0b100, 0x2000);
+    cpu.registers.sp = 0x3000;
+    cpu.memory.load(0x2000, &[0x7F]);
+    cpu.memory.load(0x3000, &[0x34, 0x12]);
+    cpu.cycles = 1000;
+    let machine = Machine {
+        registers: &cpu.registers,
+        memory: &cpu.memory,
+        cycles: cpu.cycles,
+        hits: 101,
+    };
+    let mut symbols = BTreeMap::new();
+    symbols.insert("BUFFER".to_string(), 0x2000);
+    let holds = |text: &str| Condition::parse(text, &symbols).unwrap().holds(&machine);
+
+    assert!(holds("A == 0x1B && Z"));
+    assert!(holds("hits > 100"));
+    assert!(holds("[HL] = 7FH && w[SP] == 1234H && M == [buffer]"));
+    assert!(holds("!(CY || S) && cycles >= 1000 && HL - 1 == 1FFFH"));
+    assert!(holds("(F & 40H) != 0 && ~A & 0FFH == 0E4H"));
+    assert!(!holds("A == 1BH && hits < 100 || BC"));
+    assert!(holds("w[SP + 1] - 12H == 0"));
+
+    let error = |text: &str| Condition::parse(text, &symbols).unwrap_err();
+    assert_eq!(error("A =="), "value expected");
+    assert_eq!(error("[HL"), "missing `]`");
+    assert_eq!(
+        error("COUNT > 3"),
+        "unknown register, flag or symbol `COUNT`"
+    );
+    assert_eq!(error("A == 1 B"), "unexpected text after the condition");
+    assert_eq!(error("A == 0x"), "invalid number `0x`");
+}
+
+#[test]
+fn debugger() {
+    let program = [
+        0x31, 0x00, 0xF0, 0x3E, 0x05, 0xCD, 0x0F, 0x00, 0xCD, 0x0F, 0x00, 0x32, 0x11, 0x00, 0x76,
+        0x87, 0xC9, 0x00,
+    ];
+    let symbols = debugger::parse_symbols("DOUBLE           EQU 0000FH\nRESULT EQU 00011H\n");
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, &program);
+    let mut debugger = Debugger::new(cpu, symbols);
+    let mut input = |line: &str| debugger.input(line).unwrap();
+
+    assert_eq!(
+        input("b double if a ==== 5"),
+        "error: operator `==` where a value was expected\n"
+    );
+    assert_eq!(input("b double if hits == 2 && A == 0AH"), "");
+    assert_eq!(
+        input("c"),
+        "breakpoint, hits == 2 && A == 0AH\nDOUBLE:\n000F  87           ADD A\n"
+    );
+    assert_eq!(
+        input("b"),
+        "000F  DOUBLE           hits 2  if hits == 2 && A == 0AH\n"
+    );
+    assert_eq!(input("set pc 0"), input("r"));
+    assert_eq!(input("d double"), "");
+    assert_eq!(input("b double"), "");
+    assert_eq!(
+        input("c"),
+        "breakpoint\nDOUBLE:\n000F  87           ADD A\n"
+    );
+    assert_eq!(input("out"), "0008  CD 0F 00     CALL 000FH\n");
+    //Enter repeats the step out, which stops at the breakpoint in the second call
+    assert!(input("").starts_with("breakpoint\n"));
+    assert_eq!(input("d all"), "");
+    assert_eq!(input("s 2"), "000B  32 11 00     STA 0011H\n");
+    assert!(input("set cy 1").starts_with("A=14 F=15 [--APC]"));
+    assert!(input("set A 100H").starts_with("error: A takes a byte"));
+    assert_eq!(input("c"), "halted\nDOUBLE:\n000F  87           ADD A\n");
+    assert_eq!(input("x RESULT 2"), format!("0011  14 00{:44}..\n", ""));
+    assert_eq!(input("e 20H 'ab' 0DH"), "");
+    assert_eq!(input("find 'b',0DH"), "0021\n");
+    assert_eq!(input("set pc 8"), input("r"));
+    assert_eq!(input("n"), "000B  32 11 00     STA 0011H\n");
+    assert!(input("l").contains("=> 000B"));
+    assert_eq!(input("!2"), "");
+    assert_eq!(input("watch RESULT-12H change"), "");
+    assert_eq!(input("watch"), "   1  0011H-0012H change\n");
+    assert_eq!(
+        input("c"),
+        "watchpoint, write 28H over 14H at 0011H by the instruction at 000BH\n\
+         000E  76           HLT\n"
+    );
+    assert_eq!(input("unwatch 1"), "");
+    assert!(input("history").ends_with("  27  history\n"));
+    assert_eq!(debugger.input("quit"), None);
+}
+
+#[test]
+fn debugger_at_the_ends_of_memory() {
+    //LXI SP,0; CALL 8; HLT; NOP; RET
+    let program = [0x31, 0x00, 0x00, 0xCD, 0x08, 0x00, 0x76, 0x00, 0xC9];
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, &program);
+    let mut debugger = Debugger::new(cpu, BTreeMap::new());
+    let mut input = |line: &str| debugger.input(line).unwrap();
+
+    assert_eq!(input("s 2"), "0008  C9           RET\n");
+    //The stack is at the top of memory, the return takes SP round to 0000H
+    assert_eq!(input("o"), "0006  76           HLT\n");
+    assert!(input("r").contains(" SP=0000 PC=0006 "));
+    let listing = input("l 0 30000");
+    assert_eq!(listing.lines().count(), 30000);
+    assert!(listing.ends_with("   7533  00           NOP\n"));
+    //Up to the return address the CALL left at FFFEH
+    let listing = input("l 0FFF0H 30000");
+    assert_eq!(listing.lines().count(), 15);
+    assert!(listing.ends_with(" FFFE  06 00        MVI B,00H\n"));
+}
+
+#[test]
+fn intel_hex() {
+    let text = "\
+:0300300002337A1E
+:10010000214601360121470136007EFE09D2190140
+
+:020000021000EC
+:0400000300001234B3
+:00000001FF
+";
+    let image = hex::parse(text).unwrap();
+    assert_eq!(image.start, Some(0x1234));
+    let segments: Vec<(u16, usize)> = image
+        .segments
+        .iter()
+        .map(|segment| (segment.address, segment.data.len()))
+        .collect();
+    assert_eq!(segments, vec![(0x0030, 3), (0x0100, 16)]);
+    assert_eq!(hex::parse(&hex::to_text(&image)), Ok(image.clone()));
+
+    let mut memory = Memory::new();
+    image.load(&mut memory);
+    assert_eq!(memory.read(0x30, 0x32), vec![0x02, 0x33, 0x7A]);
+
+    let error = |text: &str| hex::parse(text).unwrap_err().to_string();
+    assert_eq!(
+        error(":0300300002337A1F\n"),
+        "line 1: checksum is 1FH, expected 1EH"
+    );
+    assert_eq!(
+        error(":020000021000EC\n:0300300002337A1E\n"),
+        "line 2: data at 10030H runs beyond 64K"
+    );
+    assert_eq!(
+        error(":0300300002337A1E\n"),
+        "line 2: missing end of file record"
+    );
+    assert_eq!(error("0300\n"), "line 1: a record starts with `:`");
+}
+
+#[test]
+fn s_records_and_overlaps() {
+    let text = "\
+S00600004844521B
+S1130000285F245F2212226A000424290008237C2A
+S11300100002000800082629001853812341001813
+S5030002FA
+S9030000FC
+";
+    let image = srec::parse(text).unwrap();
+    assert_eq!(image.start, Some(0));
+    assert_eq!(image.segments[0].data.len(), 32);
+    assert_eq!(srec::parse(&srec::to_text(&image)), Ok(image.clone()));
+    let error = |text: &str| srec::parse(text).unwrap_err().to_string();
+    assert_eq!(
+        error("S9030000FD\n"),
+        "line 1: checksum is FDH, expected FCH"
+    );
+    assert_eq!(
+        error("S2080100000102033C\n"),
+        "line 1: S2 records have addresses beyond 16 bits, only S1 and S9 are supported"
+    );
+    assert_eq!(
+        error("S5030005F7\n"),
+        "line 1: record count is 5, there were 0 data records"
+    );
+    for text in ["S\n", "S\u{e9}12\n"].iter() {
+        assert_eq!(error(text), "line 1: a record starts with `S` and its type");
+    }
+
+    let images = vec![
+        ("rom.s19".to_string(), image),
+        ("patch.bin@1CH".to_string(), Image::new(0x1C, vec![0; 8])),
+        ("ram.bin@100H".to_string(), Image::new(0x100, vec![0; 8])),
+    ];
+    let overlaps: Vec<String> = image::overlaps(&images)
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+    assert_eq!(
+        overlaps,
+        vec!["patch.bin@1CH overlaps rom.s19 at 001CH-001FH"]
+    );
+}
+
+#[test]
+fn snapshots() {
+    //LXI SP,0F000H; MVI A,41H; OUT 2; EI; INR A; STA 8000H; JMP 8
+    let program = [
+        0x31, 0x00, 0xF0, 0x3E, 0x41, 0xD3, 0x02, 0xFB, 0x3C, 0x32, 0x00, 0x80, 0xC3, 0x08, 0x00,
+    ];
+    let mut cpu = Cpu::new();
+    cpu.memory.load(0, &program);
+    cpu.memory.load(0x38, &[0x3C, 0xC9]);
+    for _ in 0..6 {
+        cpu.step().unwrap();
+    }
+    let saved = snapshot::save(&cpu);
+    let mut restored = Cpu::new();
+    snapshot::load(&mut restored, &saved).unwrap();
+    assert_eq!(snapshot::save(&restored), saved);
+    assert_eq!(
+        format!("{:?}", restored.registers),
+        format!("{:?}", cpu.registers)
+    );
+    assert_eq!((restored.cycles, restored.interrupts), (cpu.cycles, true));
+    assert_eq!(restored.ports[2], 0x41);
+    assert_eq!(restored.memory.read(0x8000, 0x8000), vec![0x42]);
+
+    //Both go on the same way, an interrupt included
+    for machine in [&mut cpu, &mut restored].iter_mut() {
+        assert!(machine.interrupt(7));
+        for _ in 0..20 {
+            machine.step().unwrap();
+        }
+    }
+    assert_eq!(snapshot::save(&restored), snapshot::save(&cpu));
+
+    let mut other = saved.clone();
+    other[8] = 2;
+    let mut fresh = Cpu::new();
+    let errors = [
+        snapshot::load(&mut fresh, &other),
+        snapshot::load(&mut fresh, b"I8080"),
+        snapshot::load(&mut fresh, &saved[..100]),
+    ];
+    let errors: Vec<String> = errors
+        .iter()
+        .map(|result| result.clone().unwrap_err().to_string())
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "snapshot version 2 is not supported, this build reads version 1",
+            "not a snapshot",
+            "the snapshot ends early"
+        ]
+    );
+    other = saved.clone();
+    other.push(0);
+    assert_eq!(
+        snapshot::load(&mut fresh, &other),
+        Err(SnapshotError::TrailingBytes(1))
+    );
+    assert_eq!((fresh.registers.pc, fresh.cycles), (0, 0));
+}